@@ -0,0 +1,126 @@
+// parse_retry_time_from_body 吞吐基准
+//
+// 运行: cargo bench --features bench --bench rate_limit_parse
+//
+// `per_call_regex` 组复现正则改为 Lazy 静态之前的做法 (每次调用都编译正则)，作为对照基线。
+
+use antigravity_tools_lib::bench_parse_retry_time_from_body;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use regex::Regex;
+
+const BODIES: [(&str, &str); 5] = [
+    (
+        "json_quota_reset_delay",
+        r#"{"error":{"code":429,"message":"Resource has been exhausted","status":"RESOURCE_EXHAUSTED","details":[{"@type":"type.googleapis.com/google.rpc.ErrorInfo","reason":"QUOTA_EXHAUSTED","metadata":{"quotaResetDelay":"1h16m0.667s"}}]}}"#,
+    ),
+    (
+        "text_try_again_min_sec",
+        "Rate limit reached for requests. Please try again in 2m 30s.",
+    ),
+    (
+        "text_quota_reset",
+        "You have exceeded your quota. Your quota will reset in 45 seconds.",
+    ),
+    (
+        "text_retry_after",
+        "Too many requests. Retry after 42 seconds.",
+    ),
+    (
+        "text_no_match",
+        "The service is currently unavailable. Please contact support if the problem persists.",
+    ),
+];
+
+/// 基线: 与 `parse_retry_time_from_body` 相同的解析路径，但每次调用都现场编译正则
+fn parse_retry_time_per_call_regex(body: &str) -> Option<u64> {
+    let trimmed = body.trim();
+    if trimmed.starts_with('{') {
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
+            let error = json.get("error").unwrap_or(&json);
+            if let Some(delay) = error
+                .get("details")
+                .and_then(|d| d.get(0))
+                .and_then(|d| d.get("metadata"))
+                .and_then(|m| m.get("quotaResetDelay"))
+                .and_then(|v| v.as_str())
+            {
+                let re = Regex::new(
+                    r"(?:(\d+)d)?(?:(\d+)h)?(?:(\d+)m)?(?:(\d+(?:\.\d+)?)s)?(?:(\d+(?:\.\d+)?)ms)?",
+                )
+                .ok()?;
+                let caps = re.captures(delay)?;
+                let int = |i| caps.get(i).and_then(|m| m.as_str().parse::<u64>().ok());
+                let secs = caps
+                    .get(4)
+                    .and_then(|m| m.as_str().parse::<f64>().ok())
+                    .unwrap_or(0.0);
+                return Some(
+                    int(1).unwrap_or(0) * 86400
+                        + int(2).unwrap_or(0) * 3600
+                        + int(3).unwrap_or(0) * 60
+                        + secs.ceil() as u64,
+                );
+            }
+            if let Some(retry) = error.get("retry_after").and_then(|v| v.as_u64()) {
+                return Some(retry);
+            }
+        }
+    }
+
+    let re = Regex::new(r"(?i)try again in (\d+)m\s*(\d+(?:\.\d+)?)s").ok()?;
+    if let Some(caps) = re.captures(body) {
+        if let (Ok(m), Ok(s)) = (caps[1].parse::<u64>(), caps[2].parse::<f64>()) {
+            return Some(m * 60 + s.ceil() as u64);
+        }
+    }
+    for pattern in [
+        r"(?i)(?:try again in|backoff for|wait)\s*(\d+(?:\.\d+)?)s",
+        r"(?i)quota will reset in (\d+(?:\.\d+)?) second",
+        r"(?i)retry after (\d+(?:\.\d+)?) second",
+        r"\(wait (\d+(?:\.\d+)?)s\)",
+    ] {
+        let re = Regex::new(pattern).ok()?;
+        if let Some(s) = re
+            .captures(body)
+            .and_then(|caps| caps[1].parse::<f64>().ok())
+        {
+            return Some(s.ceil() as u64);
+        }
+    }
+    None
+}
+
+fn parse_retry_time_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_retry_time_from_body");
+    for (name, body) in BODIES {
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| bench_parse_retry_time_from_body(black_box(body)))
+        });
+    }
+    group.finish();
+}
+
+fn parse_retry_time_per_call_regex_baseline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_retry_time_from_body/per_call_regex");
+    for (name, body) in BODIES {
+        // 两种实现对同一输入的结果须一致，否则对比没有意义
+        assert_eq!(
+            parse_retry_time_per_call_regex(body),
+            bench_parse_retry_time_from_body(body),
+            "{name}"
+        );
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| parse_retry_time_per_call_regex(black_box(body)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    parse_retry_time_throughput,
+    parse_retry_time_per_call_regex_baseline
+);
+criterion_main!(benches);
@@ -144,6 +144,15 @@ pub async fn internal_start_proxy_service(
 
     // 同步配置到运行中的 TokenManager
     token_manager.start_auto_cleanup().await;
+    if let crate::modules::integration::SystemManager::Desktop(ref h) = integration {
+        use tauri::Emitter;
+        let app_handle = h.clone();
+        token_manager
+            .start_health_watch(move |report| {
+                let _ = app_handle.emit("proxy://rate-limit-health", &report);
+            })
+            .await;
+    }
     token_manager
         .update_sticky_config(config.scheduling.clone())
         .await;
@@ -742,6 +751,64 @@ pub async fn clear_proxy_rate_limit(
     }
 }
 
+/// 获取最近一段时间内被限流的请求比例 (默认窗口 300 秒)
+#[tauri::command]
+pub async fn get_proxy_throttle_rate(
+    state: State<'_, ProxyServiceState>,
+    window_secs: Option<u64>,
+) -> Result<f64, String> {
+    let instance_lock = state.instance.read().await;
+    if let Some(instance) = instance_lock.as_ref() {
+        let window = std::time::Duration::from_secs(window_secs.unwrap_or(300));
+        Ok(instance.token_manager.get_throttle_rate(window))
+    } else {
+        Err("服务未运行".to_string())
+    }
+}
+
+/// 获取限流健康状况 (只读)
+///
+/// 等级变化由反代启动时注册的监听在加锁/解锁时检测，并发出 `proxy://rate-limit-health` 事件。
+#[tauri::command]
+pub async fn get_proxy_rate_limit_health(
+    state: State<'_, ProxyServiceState>,
+) -> Result<crate::proxy::rate_limit::TrackerHealthReport, String> {
+    let instance_lock = state.instance.read().await;
+    if let Some(instance) = instance_lock.as_ref() {
+        Ok(instance.token_manager.rate_limit_health())
+    } else {
+        Err("服务未运行".to_string())
+    }
+}
+
+/// 重置指定账号的失败计数 (退避阶梯归零，不解除当前锁定)
+#[tauri::command]
+pub async fn reset_proxy_failure_count(
+    state: State<'_, ProxyServiceState>,
+    account_id: String,
+) -> Result<bool, String> {
+    let instance_lock = state.instance.read().await;
+    if let Some(instance) = instance_lock.as_ref() {
+        Ok(instance.token_manager.reset_failure_count(&account_id))
+    } else {
+        Err("服务未运行".to_string())
+    }
+}
+
+/// 重置所有账号的失败计数 (退避阶梯归零，不解除当前锁定)
+#[tauri::command]
+pub async fn reset_all_proxy_failure_counts(
+    state: State<'_, ProxyServiceState>,
+) -> Result<(), String> {
+    let instance_lock = state.instance.read().await;
+    if let Some(instance) = instance_lock.as_ref() {
+        instance.token_manager.reset_all_failure_counts();
+        Ok(())
+    } else {
+        Err("服务未运行".to_string())
+    }
+}
+
 /// 清除所有限流记录
 #[tauri::command]
 pub async fn clear_all_proxy_rate_limits(
@@ -6,6 +6,9 @@ mod modules;
 mod proxy; // Proxy service module
 mod utils;
 
+#[cfg(feature = "bench")]
+pub use proxy::rate_limit::bench_parse_retry_time_from_body;
+
 use modules::logger;
 use std::sync::Arc;
 use tauri::Manager;
@@ -531,7 +534,11 @@ pub fn run() {
             commands::proxy::set_preferred_account,
             commands::proxy::get_preferred_account,
             commands::proxy::clear_proxy_rate_limit,
+            commands::proxy::get_proxy_throttle_rate,
+            commands::proxy::get_proxy_rate_limit_health,
             commands::proxy::clear_all_proxy_rate_limits,
+            commands::proxy::reset_proxy_failure_count,
+            commands::proxy::reset_all_proxy_failure_counts,
             commands::proxy::check_proxy_health,
             // Proxy Pool Binding commands
             commands::proxy_pool::bind_account_proxy,
@@ -287,6 +287,11 @@ pub struct ExperimentalConfig {
     /// 上下文压缩阈值 L3 (Fork + Summary)
     #[serde(default = "default_threshold_l3")]
     pub context_compression_threshold_l3: f32,
+
+    /// 单个请求最多轮换的不同账号数 (0 表示不限制)
+    /// 同一账号上的原地重试不计入
+    #[serde(default)]
+    pub max_accounts_tried: usize,
 }
 
 impl Default for ExperimentalConfig {
@@ -299,6 +304,7 @@ impl Default for ExperimentalConfig {
             context_compression_threshold_l1: 0.4,
             context_compression_threshold_l2: 0.55,
             context_compression_threshold_l3: 0.7,
+            max_accounts_tried: 0,
         }
     }
 }
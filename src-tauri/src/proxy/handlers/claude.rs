@@ -238,7 +238,8 @@ The structure MUST be as follows:
 // ===== 统一退避策略模块 =====
 // 移除本地重复定义，使用 common 中的统一实现
 use super::common::{
-    apply_retry_strategy, determine_retry_strategy, should_rotate_account, RetryStrategy,
+    apply_retry_strategy, determine_retry_strategy, rotation_budget_exhausted,
+    should_rotate_account, AccountRotationBudget, RetryStrategy,
 };
 
 // ===== 退避策略模块结束 =====
@@ -447,6 +448,7 @@ pub async fn handle_messages(
     let threshold_l1 = experimental.context_compression_threshold_l1;
     let threshold_l2 = experimental.context_compression_threshold_l2;
     let threshold_l3 = experimental.context_compression_threshold_l3;
+    let max_accounts_tried = experimental.max_accounts_tried;
 
     // 获取最新一条“有意义”的消息内容（用于日志记录和后台任务检测）
     // 策略：反向遍历，首先筛选出所有角色为 "user" 的消息，然后从中找到第一条非 "Warmup" 且非空的文本消息
@@ -585,6 +587,7 @@ pub async fn handle_messages(
     let mut last_mapped_model: Option<String> = None;
     let mut last_status = StatusCode::SERVICE_UNAVAILABLE; // Default to 503 if no response reached
     let mut force_rotate = false;
+    let mut rotation_budget = AccountRotationBudget::new(max_accounts_tried);
 
     for attempt in 0..max_attempts {
         // 2. 模型路由解析
@@ -649,6 +652,15 @@ pub async fn handle_messages(
             }
         };
 
+        if rotation_budget_exhausted(
+            &mut rotation_budget,
+            &account_id,
+            &mut last_error,
+            &trace_id,
+        ) {
+            break;
+        }
+
         last_email = Some(email.clone());
         info!("✓ Using account: {} (type: {})", email, config.request_type);
 
@@ -6,6 +6,7 @@ use axum::{
     Json,
 };
 use serde_json::{json, Value};
+use std::collections::HashSet;
 use tokio::time::{sleep, Duration};
 use tracing::{debug, info};
 
@@ -163,6 +164,85 @@ pub async fn apply_retry_strategy(
     }
 }
 
+/// 账号轮换预算耗尽
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RotationError {
+    /// 已尝试的不同账号数达到上限
+    PoolExhausted { accounts_tried: usize },
+}
+
+impl std::fmt::Display for RotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RotationError::PoolExhausted { accounts_tried } => {
+                write!(f, "已尝试 {} 个不同账号，停止轮换", accounts_tried)
+            }
+        }
+    }
+}
+
+/// 单个请求内的账号轮换预算
+///
+/// 只统计不同的账号：同一账号上的重试 (如 Grace Retry) 不消耗预算。
+/// `max_accounts_tried` 为 0 表示不限制。
+#[derive(Debug, Clone)]
+pub struct AccountRotationBudget {
+    max_accounts_tried: usize,
+    tried: HashSet<String>,
+}
+
+impl AccountRotationBudget {
+    pub fn new(max_accounts_tried: usize) -> Self {
+        Self {
+            max_accounts_tried,
+            tried: HashSet::new(),
+        }
+    }
+
+    /// 在向 `account_id` 发起请求前调用；切换到新账号会超出上限时返回 `PoolExhausted`
+    pub fn try_account(&mut self, account_id: &str) -> Result<(), RotationError> {
+        if self.tried.contains(account_id) {
+            return Ok(());
+        }
+        if self.max_accounts_tried > 0 && self.tried.len() >= self.max_accounts_tried {
+            return Err(RotationError::PoolExhausted {
+                accounts_tried: self.tried.len(),
+            });
+        }
+        self.tried.insert(account_id.to_string());
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn accounts_tried(&self) -> usize {
+        self.tried.len()
+    }
+}
+
+/// 在重试循环中选定账号后调用
+///
+/// 超出轮换预算时把原因写入 `last_error` 并返回 `true`，调用方应 `break`
+/// 走已有的 "全部尝试失败" 响应路径。
+pub fn rotation_budget_exhausted(
+    budget: &mut AccountRotationBudget,
+    account_id: &str,
+    last_error: &mut String,
+    trace_id: &str,
+) -> bool {
+    match budget.try_account(account_id) {
+        Ok(()) => false,
+        Err(e) => {
+            tracing::warn!("[{}] {}", trace_id, e);
+            *last_error = if last_error.is_empty() {
+                e.to_string()
+            } else {
+                format!("{} ({})", last_error, e)
+            };
+            true
+        }
+    }
+}
+
 /// 判断是否应该轮换账号
 pub fn should_rotate_account(status_code: u16, strategy: Option<&RetryStrategy>) -> bool {
     // [NEW] 如果识别为 Grace Retry，则显式要求不轮换账号
@@ -227,3 +307,30 @@ pub async fn handle_detect_model(
 
     Json(response).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_budget_stops_after_distinct_account_cap() {
+        let mut budget = AccountRotationBudget::new(3);
+        let attempts = ["acc_a", "acc_a", "acc_b", "acc_b", "acc_c", "acc_a"];
+        for account in attempts {
+            assert!(budget.try_account(account).is_ok());
+        }
+        assert_eq!(budget.accounts_tried(), 3);
+
+        assert_eq!(
+            budget.try_account("acc_d"),
+            Err(RotationError::PoolExhausted { accounts_tried: 3 })
+        );
+        // 已尝试过的账号仍可原地重试
+        assert!(budget.try_account("acc_c").is_ok());
+
+        let mut unlimited = AccountRotationBudget::new(0);
+        for i in 0..10 {
+            assert!(unlimited.try_account(&format!("acc_{}", i)).is_ok());
+        }
+    }
+}
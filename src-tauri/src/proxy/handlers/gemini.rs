@@ -11,7 +11,8 @@ use tracing::{debug, error, info};
 use crate::proxy::common::client_adapter::CLIENT_ADAPTERS;
 use crate::proxy::debug_logger;
 use crate::proxy::handlers::common::{
-    apply_retry_strategy, determine_retry_strategy, should_rotate_account,
+    apply_retry_strategy, determine_retry_strategy, rotation_budget_exhausted,
+    should_rotate_account, AccountRotationBudget,
 };
 use crate::proxy::mappers::gemini::{unwrap_response, wrap_request};
 use crate::proxy::server::AppState;
@@ -94,6 +95,8 @@ pub async fn handle_generate(
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
     let mut force_rotate = false;
+    let mut rotation_budget =
+        AccountRotationBudget::new(state.experimental.read().await.max_accounts_tried);
 
     for attempt in 0..max_attempts {
         // 3. 模型路由解析
@@ -151,6 +154,15 @@ pub async fn handle_generate(
             }
         };
 
+        if rotation_budget_exhausted(
+            &mut rotation_budget,
+            &account_id,
+            &mut last_error,
+            &trace_id,
+        ) {
+            break;
+        }
+
         let mapped_model = token_manager
             .resolve_dynamic_model_for_account(&account_id, &mapped_model)
             .await;
@@ -17,7 +17,8 @@ use crate::proxy::upstream::client::mask_email;
 
 const MAX_RETRY_ATTEMPTS: usize = 3;
 use super::common::{
-    apply_retry_strategy, determine_retry_strategy, should_rotate_account, RetryStrategy,
+    apply_retry_strategy, determine_retry_strategy, rotation_budget_exhausted,
+    should_rotate_account, AccountRotationBudget, RetryStrategy,
 };
 use crate::modules::account;
 use crate::proxy::common::client_adapter::CLIENT_ADAPTERS; // [NEW] Adapter Registry
@@ -179,6 +180,8 @@ pub async fn handle_chat_completions(
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    let mut rotation_budget =
+        AccountRotationBudget::new(state.experimental.read().await.max_accounts_tried);
 
     // 2. 模型路由解析 (移到循环外以支持在所有路径返回 X-Mapped-Model)
     let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
@@ -229,6 +232,15 @@ pub async fn handle_chat_completions(
             }
         };
 
+        if rotation_budget_exhausted(
+            &mut rotation_budget,
+            &account_id,
+            &mut last_error,
+            &trace_id,
+        ) {
+            break;
+        }
+
         // [NEW v4.1.29] 获取完整 Token 对象用于动态规格查询
         let proxy_token = token_manager.get_token_by_id(&account_id);
         let mapped_model = token_manager
@@ -1491,6 +1503,8 @@ pub async fn handle_completions(
 
     let mut last_error = String::new();
     let mut last_email: Option<String> = None;
+    let mut rotation_budget =
+        AccountRotationBudget::new(state.experimental.read().await.max_accounts_tried);
 
     // 2. 模型路由解析 (移到循环外以支持在所有路径返回 X-Mapped-Model)
     let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
@@ -1543,6 +1557,15 @@ pub async fn handle_completions(
             }
         };
 
+        if rotation_budget_exhausted(
+            &mut rotation_budget,
+            &account_id,
+            &mut last_error,
+            &trace_id,
+        ) {
+            break;
+        }
+
         let mapped_model = token_manager
             .resolve_dynamic_model_for_account(&account_id, &mapped_model)
             .await;
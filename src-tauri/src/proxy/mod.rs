@@ -18,6 +18,7 @@ pub mod handlers; // API 端点处理器
 pub mod http_session_store; // HTTP多轮对话会话历史存储
 pub mod mappers; // 协议转换器
 pub mod middleware; // Axum 中间件
+pub mod model_fallback; // 模型降级映射 (主模型被锁定时的备选链)
 pub mod model_specs; // 模型规格管理 (v4.1.29)
 pub mod monitor; // 监控
 pub mod opencode_sync; // OpenCode 配置同步
@@ -0,0 +1,138 @@
+// 模型降级映射: 主模型被配额锁定时按顺序尝试备选模型
+
+use crate::proxy::rate_limit::{AccountKey, RateLimitTracker};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 主模型 -> 有序备选模型列表
+///
+/// 例如 `gemini-2.0-flash` 被模型级锁定时依次尝试 `gemini-1.5-flash`、`gemini-1.0-pro`。
+/// 序列化为普通的 JSON / TOML 映射:
+///
+/// ```toml
+/// "gemini-2.0-flash" = ["gemini-1.5-flash", "gemini-1.0-pro"]
+/// ```
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ModelFallbackMap {
+    chains: HashMap<String, Vec<String>>,
+}
+
+#[allow(dead_code)]
+impl ModelFallbackMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从 JSON 映射加载 (`{"primary": ["fallback1", "fallback2"]}`)
+    pub fn from_json_str(s: &str) -> Result<Self, String> {
+        serde_json::from_str(s).map_err(|e| format!("解析模型降级映射 (JSON) 失败: {}", e))
+    }
+
+    /// 从 TOML 映射加载
+    pub fn from_toml_str(s: &str) -> Result<Self, String> {
+        toml::from_str(s).map_err(|e| format!("解析模型降级映射 (TOML) 失败: {}", e))
+    }
+
+    /// 设置主模型的备选链，空列表表示移除
+    pub fn set_fallbacks(&mut self, primary: &str, fallbacks: Vec<String>) {
+        if fallbacks.is_empty() {
+            self.chains.remove(primary);
+        } else {
+            self.chains.insert(primary.to_string(), fallbacks);
+        }
+    }
+
+    /// 主模型的备选链 (不含主模型本身)
+    pub fn fallbacks(&self, primary: &str) -> &[String] {
+        self.chains
+            .get(primary)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// 依次检查主模型及其备选链，返回账号上第一个未被限流的模型
+    ///
+    /// 账号级锁会让所有模型都不可用，此时返回 None。
+    pub fn next_available_model(
+        &self,
+        account_id: &str,
+        primary_model: &str,
+        tracker: &RateLimitTracker,
+    ) -> Option<String> {
+        std::iter::once(primary_model)
+            .chain(self.fallbacks(primary_model).iter().map(String::as_str))
+            .find(|model| !tracker.is_rate_limited(&AccountKey::global(account_id), Some(model)))
+            .map(str::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::rate_limit::RateLimitReason;
+    use std::time::{Duration, SystemTime};
+
+    fn lock_model(tracker: &RateLimitTracker, account_id: &str, model: &str) {
+        tracker.set_lockout_until(
+            &AccountKey::global(account_id),
+            SystemTime::now() + Duration::from_secs(300),
+            RateLimitReason::QuotaExhausted,
+            Some(model.to_string()),
+        );
+    }
+
+    #[test]
+    fn test_next_available_model_walks_chain() {
+        let map = ModelFallbackMap::from_json_str(
+            r#"{"gemini-2.0-flash": ["gemini-1.5-flash", "gemini-1.0-pro"]}"#,
+        )
+        .unwrap();
+        let tracker = RateLimitTracker::new();
+
+        assert_eq!(
+            map.next_available_model("acc1", "gemini-2.0-flash", &tracker),
+            Some("gemini-2.0-flash".to_string())
+        );
+
+        lock_model(&tracker, "acc1", "gemini-2.0-flash");
+        assert_eq!(
+            map.next_available_model("acc1", "gemini-2.0-flash", &tracker),
+            Some("gemini-1.5-flash".to_string())
+        );
+
+        lock_model(&tracker, "acc1", "gemini-1.5-flash");
+        assert_eq!(
+            map.next_available_model("acc1", "gemini-2.0-flash", &tracker),
+            Some("gemini-1.0-pro".to_string())
+        );
+
+        lock_model(&tracker, "acc1", "gemini-1.0-pro");
+        assert_eq!(
+            map.next_available_model("acc1", "gemini-2.0-flash", &tracker),
+            None
+        );
+
+        // 未配置备选链的模型只检查自身
+        lock_model(&tracker, "acc1", "claude-sonnet");
+        assert_eq!(
+            map.next_available_model("acc1", "claude-sonnet", &tracker),
+            None
+        );
+    }
+
+    #[test]
+    fn test_load_from_toml() {
+        let map = ModelFallbackMap::from_toml_str(
+            r#""gemini-2.0-flash" = ["gemini-1.5-flash", "gemini-1.0-pro"]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            map.fallbacks("gemini-2.0-flash"),
+            ["gemini-1.5-flash", "gemini-1.0-pro"]
+        );
+        assert!(map.fallbacks("unknown").is_empty());
+        assert!(ModelFallbackMap::from_toml_str("not = [valid").is_err());
+    }
+}
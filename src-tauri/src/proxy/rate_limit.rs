@@ -1,9 +1,112 @@
+use axum::http::HeaderMap;
 use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+// 时间字符串解析: "1d12h", "2h1m1s", "30s", "510.790006ms" 等
+static DURATION_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:(\d+)d)?(?:(\d+)h)?(?:(\d+)m)?(?:(\d+(?:\.\d+)?)s)?(?:(\d+(?:\.\d+)?)ms)?")
+        .unwrap()
+});
+
+// ISO 8601 时长: "PT42S", "PT1H30M", "P1DT2H30M0S" (不支持年/月)
+static ISO8601_DURATION_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^P(?:(\d+)W)?(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:(\d+(?:\.\d+)?)S)?)?$")
+        .unwrap()
+});
+
+// 404 body 中的 "模型不存在" 提示，如:
+// - "models/gemini-x is not found for API version v1beta, or is not supported for generateContent"
+// - "Publisher Model `projects/.../models/gemini-x` was not found or your project does not have access to it"
+// - "Unknown model: gemini-x"
+static MODEL_NOT_FOUND_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\bmodels?\b[^\n]{0,120}\b(?:not found|does not exist)\b|\bis not supported for (?:generateContent|streamGenerateContent|countTokens)\b|\bunknown model\b",
+    )
+    .unwrap()
+});
+
+// Gemini 针对项目 / 资源路径的通用 404，与模型无关，轮换账号即可
+const REQUESTED_ENTITY_NOT_FOUND: &str = "requested entity was not found";
+
+// 错误消息中的重试时间 (兜底正则)，秒数允许小数 (如 "1.5s")
+static TRY_AGAIN_MIN_SEC_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)try again in (\d+)m\s*(\d+(?:\.\d+)?)s").unwrap());
+static TRY_AGAIN_SEC_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)(?:try again in|backoff for|wait)\s*(\d+(?:\.\d+)?)s").unwrap());
+static QUOTA_RESET_SEC_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)quota will reset in (\d+(?:\.\d+)?) second").unwrap());
+static RETRY_AFTER_SEC_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)retry after (\d+(?:\.\d+)?) second").unwrap());
+static PAREN_WAIT_SEC_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\(wait (\d+(?:\.\d+)?)s\)").unwrap());
+
+// 速率限制的细分类型: 按 token 还是按请求数
+static TPM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)tokens? per minute|\btpm\b").unwrap());
+static RPM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)requests? per minute|\brpm\b").unwrap());
+
+/// TPM 限流的默认锁定秒数 (token 需随时间滚出窗口，恢复比 RPM 慢)
+const TPM_LOCKOUT_SECS: u64 = 10;
+/// RPM 及未细分的速率限制默认锁定秒数
+const RPM_LOCKOUT_SECS: u64 = 5;
+/// 无法识别限流原因时的默认锁定秒数
+const UNKNOWN_LOCKOUT_SECS: u64 = 60;
+
+/// 会触发锁定的 HTTP 状态码
+const HANDLED_STATUSES: [u16; 5] = [404, 429, 500, 503, 529];
+
+/// Anthropic 限流响应头 (remaining, reset)，reset 为 RFC 3339 时间戳。
+/// 同时接受 `anthropic-ratelimit-reset-{requests,tokens}` 写法
+const ANTHROPIC_RESET_HEADERS: [(&str, &str); 6] = [
+    (
+        "anthropic-ratelimit-requests-remaining",
+        "anthropic-ratelimit-requests-reset",
+    ),
+    (
+        "anthropic-ratelimit-requests-remaining",
+        "anthropic-ratelimit-reset-requests",
+    ),
+    (
+        "anthropic-ratelimit-tokens-remaining",
+        "anthropic-ratelimit-tokens-reset",
+    ),
+    (
+        "anthropic-ratelimit-tokens-remaining",
+        "anthropic-ratelimit-reset-tokens",
+    ),
+    (
+        "anthropic-ratelimit-input-tokens-remaining",
+        "anthropic-ratelimit-input-tokens-reset",
+    ),
+    (
+        "anthropic-ratelimit-output-tokens-remaining",
+        "anthropic-ratelimit-output-tokens-reset",
+    ),
+];
+
+/// error.details[0].reason 到限流原因的内置映射
+const DETAIL_REASON_MAPPINGS: [(&str, RateLimitReason); 3] = [
+    ("QUOTA_EXHAUSTED", RateLimitReason::QuotaExhausted),
+    ("RATE_LIMIT_EXCEEDED", RateLimitReason::RateLimitExceeded),
+    (
+        "MODEL_CAPACITY_EXHAUSTED",
+        RateLimitReason::ModelCapacityExhausted,
+    ),
+];
+
 /// 限流原因类型
-#[derive(Debug, Clone, Copy, PartialEq)]
+///
+/// 序列化为稳定的 snake_case 名称 (如 "quota_exhausted")，
+/// 调整变体顺序不影响前端与已保存的数据。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RateLimitReason {
     /// 配额耗尽 (QUOTA_EXHAUSTED)
     QuotaExhausted,
@@ -13,10 +116,29 @@ pub enum RateLimitReason {
     ModelCapacityExhausted,
     /// 服务器错误 (5xx)
     ServerError,
+    /// 上游过载 (Anthropic 529 overloaded_error)
+    Overloaded,
+    /// 请求的模型不存在或在该账号不可用 (404)，只锁定该模型
+    ModelNotFound,
+    /// 连续失败次数超过 `max_failure_count`，需人工 `re_enable`
+    PermanentlyDisabled,
     /// 未知原因
     Unknown,
 }
 
+/// `RateLimitExceeded` 的细分类型
+///
+/// 上游对 TPM 与 RPM 都返回 RATE_LIMIT_EXCEEDED，但两者恢复方式不同:
+/// TPM 随 token 滚出窗口恢复，RPM 随分钟窗口滑动恢复。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitSubreason {
+    /// 每分钟 token 数 (TPM)
+    TokensPerMinute,
+    /// 每分钟请求数 (RPM)
+    RequestsPerMinute,
+}
+
 /// 限流信息
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
@@ -36,732 +158,8197 @@ pub struct RateLimitInfo {
     /// None 表示账号级别限流,Some(model) 表示特定模型限流
     #[allow(dead_code)] // Used for model-level rate limiting
     pub model: Option<String>,
+    /// 上游返回的当前用量 (error.details[].quotaValue)
+    #[allow(dead_code)] // Used for UI display and logging
+    pub quota_value: Option<u64>,
+    /// 上游返回的配额上限 (error.details[].limitValue)
+    #[allow(dead_code)] // Used for UI display and logging
+    pub limit_value: Option<u64>,
+    /// 触发限流的配额指标 (QuotaFailure.violations[].quotaMetric)，
+    /// 用于区分按天/按分钟、按区域的配额
+    #[allow(dead_code)] // Used for UI display and logging
+    pub quota_metric: Option<String>,
+    /// 速率限制的细分类型 (仅 `RateLimitExceeded` 且消息可识别时有值)
+    #[allow(dead_code)] // Used for UI display and logging
+    pub subreason: Option<RateLimitSubreason>,
+    /// 上游 `x-goog-quota-id` 响应头标识的配额桶 (如 "Requests per day per project")
+    #[allow(dead_code)] // Used for UI display and logging
+    pub quota_id: Option<String>,
 }
 
-/// 失败计数过期时间：1小时（超过此时间未失败则重置计数）
-const FAILURE_COUNT_EXPIRY_SECONDS: u64 = 3600;
+impl RateLimitInfo {
+    /// 截至 `now` 距检测到限流已过去的时间 (时钟回拨时为 0)
+    ///
+    /// `now` 应取自跟踪器的时钟 (`RateLimitTracker::clock`)，测试中冻结时间时结果才确定。
+    #[allow(dead_code)]
+    pub fn time_since_detected_at(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.detected_at).unwrap_or_default()
+    }
 
-/// 限流跟踪器
-pub struct RateLimitTracker {
-    limits: DashMap<String, RateLimitInfo>,
-    /// 连续失败计数（用于智能指数退避），带时间戳用于自动过期
-    failure_counts: DashMap<String, (u32, SystemTime)>,
+    /// 截至 `now` 距限流重置还剩的时间，已过重置时间时返回 None
+    pub fn time_until_reset_at(&self, now: SystemTime) -> Option<Duration> {
+        self.reset_time.duration_since(now).ok()
+    }
 }
 
-impl RateLimitTracker {
-    pub fn new() -> Self {
+/// 账号隔离信息
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct QuarantineInfo {
+    /// 隔离原因 (如 "revoked key")
+    pub reason: String,
+    /// 隔离开始时间
+    pub since: SystemTime,
+}
+
+/// 限流跟踪器整体健康等级
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    #[default]
+    Ok,
+    /// 超过一半的账号被锁定
+    Degraded,
+    /// 所有账号都被锁定
+    Critical,
+}
+
+/// `health_check` 的结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrackerHealthReport {
+    pub total_accounts: usize,
+    /// 账号级锁定或隔离中的账号数 (含永久停用)
+    pub locked_accounts: usize,
+    pub permanently_disabled: usize,
+    /// 连续失败次数达到 `HEALTH_FAILURE_THRESHOLD` 的账号数
+    pub high_failure_accounts: usize,
+    pub status: HealthStatus,
+    /// 等级发生变化时为变化前的等级 (仅由 `check_health_transition` 填充)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_status: Option<HealthStatus>,
+}
+
+/// 健康检查中视为 "失败较多" 的连续失败次数
+const HEALTH_FAILURE_THRESHOLD: u32 = 3;
+
+/// 区域与账号 ID 之间的分隔符
+///
+/// 不使用 `region:account_id`: ':' 已是模型级 Key (`account:model`) 的分隔符，
+/// `notify_update` 与 `get_accounts_by_reason` 依赖它拆出账号部分；
+/// 若区域也用 ':'，`us:acc:model` 将无法区分区域与模型。
+const REGION_SEPARATOR: char = '/';
+
+/// 带区域的账号标识: 同一个 API Key 在不同区域 (us-central1、europe-west1) 有独立配额时，
+/// 各区域分别限流
+///
+/// Tracker 的公开方法 (锁定、查询、隔离、统计、选择器等) 均接收 `&AccountKey`，
+/// 内部编码为 `region/account_id` (全局账号即 `account_id` 本身)。
+/// 不区分区域的调用方使用 `AccountKey::global`。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AccountKey {
+    pub region: Option<String>,
+    pub account_id: String,
+}
+
+impl AccountKey {
+    /// 不区分区域的账号 (与原有的纯账号 ID 等价)
+    pub fn global(account_id: &str) -> Self {
         Self {
-            limits: DashMap::new(),
-            failure_counts: DashMap::new(),
+            region: None,
+            account_id: account_id.to_string(),
         }
     }
 
-    /// 生成限流 Key
-    /// - 账号级: "account_id"
-    /// - 模型级: "account_id:model_id"
-    fn get_limit_key(&self, account_id: &str, model: Option<&str>) -> String {
-        match model {
-            Some(m) if !m.is_empty() => format!("{}:{}", account_id, m),
-            _ => account_id.to_string(),
+    pub fn regional(region: &str, account_id: &str) -> Self {
+        Self {
+            region: Some(region.to_string()).filter(|r| !r.is_empty()),
+            account_id: account_id.to_string(),
         }
     }
 
-    /// 获取账号剩余的等待时间(秒)
-    /// 支持检查账号级和模型级锁
-    pub fn get_remaining_wait(&self, account_id: &str, model: Option<&str>) -> u64 {
-        let now = SystemTime::now();
-
-        // 1. 检查全局账号锁
-        if let Some(info) = self.limits.get(account_id) {
-            if info.reset_time > now {
-                return info
-                    .reset_time
-                    .duration_since(now)
-                    .unwrap_or(Duration::from_secs(0))
-                    .as_secs();
-            }
+    /// 编码后的账号 ID，传给仍以账号 ID 为参数的方法 (选择器、隔离、统计等)
+    pub fn tracker_id(&self) -> String {
+        match &self.region {
+            Some(region) => format!("{}{}{}", region, REGION_SEPARATOR, self.account_id),
+            None => self.account_id.clone(),
         }
+    }
 
-        // 2. 如果指定了模型，检查模型级锁
-        if let Some(m) = model {
-            let key = self.get_limit_key(account_id, Some(m));
-            if let Some(info) = self.limits.get(&key) {
-                if info.reset_time > now {
-                    return info
-                        .reset_time
-                        .duration_since(now)
-                        .unwrap_or(Duration::from_secs(0))
-                        .as_secs();
-                }
-            }
+    /// 从 `tracker_id()` 的结果还原
+    pub fn parse(tracker_id: &str) -> Self {
+        match tracker_id.split_once(REGION_SEPARATOR) {
+            Some((region, account_id)) => Self::regional(region, account_id),
+            None => Self::global(tracker_id),
         }
+    }
+}
 
-        0
+impl std::fmt::Display for AccountKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.tracker_id())
     }
+}
 
-    /// 标记账号请求成功，重置连续失败计数
-    ///
-    /// 当账号成功完成请求后调用此方法，将其失败计数归零，
-    /// 这样下次失败时会从最短的锁定时间（60秒）开始。
-    pub fn mark_success(&self, account_id: &str) {
-        if self.failure_counts.remove(account_id).is_some() {
-            tracing::debug!("账号 {} 请求成功，已重置失败计数", account_id);
-        }
-        // 清除账号级限流
-        self.limits.remove(account_id);
-        // 注意：我们暂时无法清除该账号下的所有模型级锁，因为我们不知道哪些模型被锁了
-        // 除非遍历 limits。考虑到模型级锁通常是 QuotaExhausted，让其自然过期也是可以接受的。
-        // 或者我们可以引入索引，但为了简单，暂时只清除 Account 级锁。
+/// 从错误响应头中提取的信号
+#[derive(Debug, Default)]
+struct HeaderSignals {
+    /// 响应头给出的等待秒数 (Retry-After 等)
+    retry_sec: Option<u64>,
+    /// x-goog-quota-id
+    quota_id: Option<String>,
+}
+
+impl HeaderSignals {
+    fn quota_id_from(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get("x-goog-quota-id")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
     }
+}
 
-    /// 精确锁定账号到指定时间点
-    ///
-    /// 使用账号配额中的 reset_time 来精确锁定账号,
-    /// 这比指数退避更加精准。
-    ///
-    /// # 参数
-    /// - `model`: 可选的模型名称,用于模型级别限流。None 表示账号级别限流
-    pub fn set_lockout_until(
-        &self,
-        account_id: &str,
-        reset_time: SystemTime,
-        reason: RateLimitReason,
-        model: Option<String>,
-    ) {
-        let now = SystemTime::now();
-        let retry_sec = reset_time
-            .duration_since(now)
-            .map(|d| d.as_secs())
-            .unwrap_or(60); // 如果时间已过,使用默认 60 秒
+/// 从错误 body 中提取的配额上下文
+#[derive(Debug, Default)]
+struct QuotaContext {
+    quota_value: Option<u64>,
+    limit_value: Option<u64>,
+    quota_metric: Option<String>,
+}
 
-        let info = RateLimitInfo {
-            reset_time,
-            retry_after_sec: retry_sec,
-            detected_at: now,
-            reason,
-            model: model.clone(), // 🆕 支持模型级别限流
-        };
+/// 永久停用时写入隔离记录的原因 (与 `RateLimitReason::PermanentlyDisabled` 的序列化名一致)
+const PERMANENTLY_DISABLED_TAG: &str = "permanently_disabled";
 
-        let key = self.get_limit_key(account_id, model.as_deref());
-        self.limits.insert(key, info);
+/// `mark_dead` 写入隔离记录的原因；只有这类隔离会被 `auto_revive_on_success` 自动解除
+const DEAD_TAG: &str = "dead";
 
-        if let Some(m) = &model {
-            tracing::info!(
-                "账号 {} 的模型 {} 已精确锁定到配额刷新时间,剩余 {} 秒",
-                account_id,
-                m,
-                retry_sec
-            );
-        } else {
-            tracing::info!(
-                "账号 {} 已精确锁定到配额刷新时间,剩余 {} 秒",
-                account_id,
-                retry_sec
-            );
+/// 表示"永不解除"的时间点
+///
+/// UNIX_EPOCH + u64::MAX 秒超出 SystemTime 的表示范围，溢出时退回到约 100 年后。
+fn far_future(now: SystemTime) -> SystemTime {
+    SystemTime::UNIX_EPOCH
+        .checked_add(Duration::from_secs(u64::MAX))
+        .unwrap_or_else(|| now + Duration::from_secs(100 * 365 * 86400))
+}
+
+/// X-RateLimit-Limit 至少增长到原来的该倍数时视为套餐升级
+const TIER_UPGRADE_FACTOR: u64 = 2;
+
+/// 失败计数过期时间：1小时（超过此时间未失败则重置计数）
+const FAILURE_COUNT_EXPIRY_SECONDS: u64 = 3600;
+
+/// 失败率参与退避决策所需的最少请求样本数
+const MIN_FAILURE_RATE_SAMPLES: u32 = 10;
+
+/// 账号累计请求结果 (与连续失败计数 failure_counts 分开统计)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountStats {
+    pub success_count: u32,
+    pub failure_count: u32,
+    pub last_activity: SystemTime,
+}
+
+/// 锁定事件回调: (account_id, 限流信息)
+pub type LockoutCallback = Arc<dyn Fn(&str, &RateLimitInfo) + Send + Sync>;
+
+/// 自定义分类钩子: (HTTP 状态码, body) -> (限流原因, 可选的锁定秒数)，返回 None 时交给内置逻辑
+pub type ClassificationHook =
+    Arc<dyn Fn(u16, &str) -> Option<(RateLimitReason, Option<u64>)> + Send + Sync>;
+
+/// 已注册的锁定事件监听器
+struct LockoutListener {
+    /// 仅响应这些原因；None 表示响应所有原因
+    reasons: Option<Vec<RateLimitReason>>,
+    callback: LockoutCallback,
+}
+
+/// 按 Key (账号 / 账号:模型) 统计在途请求数的并发限制器
+pub struct ConcurrencyLimiter {
+    in_flight: DashMap<String, Arc<AtomicUsize>>,
+    /// 按模型的并发上限，对每个账号分别生效 (未配置的模型只受账号级上限约束)
+    model_limits: DashMap<String, usize>,
+}
+
+/// 并发许可 (账号级，可能还有模型级)，Drop 时自动归还
+pub struct ConcurrencyPermit {
+    counters: Vec<Arc<AtomicUsize>>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        for counter in &self.counters {
+            counter.fetch_sub(1, Ordering::AcqRel);
         }
     }
+}
 
-    /// 使用 ISO 8601 时间字符串精确锁定账号
-    ///
-    /// 解析类似 "2026-01-08T17:00:00Z" 格式的时间字符串
-    ///
-    /// # 参数
-    /// - `model`: 可选的模型名称,用于模型级别限流
-    pub fn set_lockout_until_iso(
-        &self,
-        account_id: &str,
-        reset_time_str: &str,
-        reason: RateLimitReason,
-        model: Option<String>,
-    ) -> bool {
-        // 尝试解析 ISO 8601 格式
-        match chrono::DateTime::parse_from_rfc3339(reset_time_str) {
-            Ok(dt) => {
-                let reset_time =
-                    SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(dt.timestamp() as u64);
-                self.set_lockout_until(account_id, reset_time, reason, model);
-                true
-            }
-            Err(e) => {
-                tracing::warn!(
-                    "无法解析配额刷新时间 '{}': {},将使用默认退避策略",
-                    reset_time_str,
-                    e
-                );
-                false
-            }
+impl ConcurrencyLimiter {
+    pub fn new() -> Self {
+        Self {
+            in_flight: DashMap::new(),
+            model_limits: DashMap::new(),
         }
     }
 
-    /// 从错误响应解析限流信息
-    ///
-    /// # Arguments
-    /// * `account_id` - 账号 ID
-    /// * `status` - HTTP 状态码
-    /// * `retry_after_header` - Retry-After header 值
-    /// * `body` - 错误响应 body
-    pub fn parse_from_error(
+    /// 设置模型的单账号并发上限 (用于昂贵模型比账号整体更严格的场景)
+    #[allow(dead_code)]
+    pub fn set_model_limit(&self, model: &str, limit: usize) {
+        self.model_limits.insert(model.to_string(), limit);
+    }
+
+    /// 账号在途请求数未达到 `limit`、且 (若配置了) 该账号上此模型的在途请求数
+    /// 未达到模型上限时获取许可，否则返回 None
+    pub fn try_acquire(
         &self,
         account_id: &str,
-        status: u16,
-        retry_after_header: Option<&str>,
-        body: &str,
-        model: Option<String>,
-        backoff_steps: &[u64], // [NEW] 传入退避配置
-    ) -> Option<RateLimitInfo> {
-        // 支持 429 (限流) 以及 500/503/529 (后端故障软避让)
-        if status != 429 && status != 500 && status != 503 && status != 529 && status != 404 {
-            return None;
-        }
-
-        // 1. 解析限流原因类型
-        let reason = if status == 429 {
-            tracing::warn!("Google 429 Error Body: {}", body);
-            self.parse_rate_limit_reason(body)
-        } else if status == 404 {
-            tracing::warn!(
-                "Google 404: model unavailable on this account, short lockout before rotation"
-            );
-            RateLimitReason::ServerError
-        } else {
-            RateLimitReason::ServerError
+        model: Option<&str>,
+        limit: usize,
+    ) -> Option<ConcurrencyPermit> {
+        let mut permit = ConcurrencyPermit {
+            counters: Vec::with_capacity(2),
         };
+        permit
+            .counters
+            .push(self.acquire_counter(account_id, limit)?);
 
-        let mut retry_after_sec = None;
-
-        // 2. 从 Retry-After header 提取
-        if let Some(retry_after) = retry_after_header {
-            if let Ok(seconds) = retry_after.parse::<u64>() {
-                retry_after_sec = Some(seconds);
-            }
+        let model_limit = model.and_then(|m| self.model_limits.get(m).map(|l| (m, *l)));
+        if let Some((model, model_limit)) = model_limit {
+            // 模型级已满时 permit 被丢弃，同时归还账号级计数
+            let key = format!("{}:{}", account_id, model);
+            permit
+                .counters
+                .push(self.acquire_counter(&key, model_limit)?);
         }
 
-        // 3. 从错误消息提取 (优先尝试 JSON 解析，再试正则)
-        if retry_after_sec.is_none() {
-            retry_after_sec = self.parse_retry_time_from_body(body);
-        }
+        Some(permit)
+    }
 
-        // 4. 处理默认值与软避让逻辑（根据限流类型设置不同默认值）
-        let retry_sec = match retry_after_sec {
-            Some(s) => {
-                // 设置安全缓冲区：最小 2 秒，防止极高频无效重试
-                if s < 2 {
-                    2
-                } else {
-                    s
-                }
-            }
-            None => {
-                // 获取连续失败次数，用于指数退避（带自动过期逻辑）
-                // [FIX] ServerError (5xx) 不累加 failure_count，避免污染 429 的退避阶梯
-                let failure_count = if reason != RateLimitReason::ServerError {
-                    // 只有非 ServerError 才累加失败计数（用于指数退避）
-                    let now = SystemTime::now();
-                    // 这里我们使用 account_id 作为 key，不区分模型，
-                    // 因为这里是为了计算连续"账号级"问题的退避。
-                    // 如果需要针对模型的连续失败计数，可能需要改变 failure_counts 的 key。
-                    // 暂时保持 account_id，这样如果一个模型一直挂，也会增加计数，符合逻辑。
-                    let mut entry = self
-                        .failure_counts
-                        .entry(account_id.to_string())
-                        .or_insert((0, now));
-
-                    let elapsed = now
-                        .duration_since(entry.1)
-                        .unwrap_or(Duration::from_secs(0))
-                        .as_secs();
-                    if elapsed > FAILURE_COUNT_EXPIRY_SECONDS {
-                        tracing::debug!(
-                            "账号 {} 失败计数已过期（{}秒），重置为 0",
-                            account_id,
-                            elapsed
-                        );
-                        *entry = (0, now);
-                    }
-                    entry.0 += 1;
-                    entry.1 = now;
-                    entry.0
-                } else {
-                    // ServerError (5xx) 使用固定值 1，不累加，避免污染 429 的退避阶梯
-                    1
-                };
+    fn acquire_counter(&self, key: &str, limit: usize) -> Option<Arc<AtomicUsize>> {
+        let counter = self
+            .in_flight
+            .entry(key.to_string())
+            .or_insert_with(|| Arc::new(AtomicUsize::new(0)))
+            .clone();
 
-                match reason {
-                    RateLimitReason::QuotaExhausted => {
-                        // [智能限流] 根据 failure_count 和配置的 backoff_steps 计算
-                        let index = (failure_count as usize).saturating_sub(1);
-                        let lockout = if index < backoff_steps.len() {
-                            backoff_steps[index]
-                        } else {
-                            *backoff_steps.last().unwrap_or(&7200)
-                        };
+        counter
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < limit).then_some(current + 1)
+            })
+            .ok()
+            .map(|_| counter)
+    }
 
-                        tracing::warn!(
-                            "检测到配额耗尽 (QUOTA_EXHAUSTED)，第{}次连续失败，根据配置锁定 {} 秒",
-                            failure_count,
-                            lockout
-                        );
-                        lockout
-                    }
-                    RateLimitReason::RateLimitExceeded => {
-                        // 速率限制 (TPM/RPM)
-                        tracing::debug!("检测到速率限制 (RATE_LIMIT_EXCEEDED)，使用默认值 5秒");
-                        5
-                    }
-                    RateLimitReason::ModelCapacityExhausted => {
-                        // 模型容量耗尽
-                        let lockout = match failure_count {
-                            1 => 5,
-                            2 => 10,
-                            _ => 15,
-                        };
-                        tracing::warn!(
-                            "检测到模型容量不足 (MODEL_CAPACITY_EXHAUSTED)，第{}次失败，{}秒后重试",
-                            failure_count,
-                            lockout
-                        );
-                        lockout
-                    }
-                    RateLimitReason::ServerError => {
-                        let lockout = if status == 404 { 5 } else { 8 };
-                        tracing::warn!("检测到 {} 错误, 执行 {}s 软避让...", status, lockout);
-                        lockout
-                    }
-                    RateLimitReason::Unknown => {
-                        // 未知原因
-                        tracing::debug!("无法解析 429 限流原因, 使用默认值 60秒");
-                        60
-                    }
-                }
-            }
-        };
+    /// 当前在途请求数
+    #[allow(dead_code)]
+    pub fn in_flight(&self, key: &str) -> usize {
+        self.in_flight
+            .get(key)
+            .map(|c| c.load(Ordering::Acquire))
+            .unwrap_or(0)
+    }
+}
 
-        let info = RateLimitInfo {
-            reset_time: SystemTime::now() + Duration::from_secs(retry_sec),
-            retry_after_sec: retry_sec,
-            detected_at: SystemTime::now(),
-            reason,
-            model: model.clone(),
-        };
+impl Default for ConcurrencyLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        // [FIX] 使用复合 Key 存储 (如果是 Quota 且有 Model)
-        // 只有 QuotaExhausted 适合做模型隔离，其他如 RateLimitExceeded 通常是全账号的 TPM
-        let use_model_key = matches!(reason, RateLimitReason::QuotaExhausted) && model.is_some();
-        let key = if use_model_key {
-            self.get_limit_key(account_id, model.as_deref())
-        } else {
-            // 其他情况（如 RateLimitExceeded, ServerError）通常影响整个账号
-            // 或者我们也可以根据配置决定是否隔离。
-            // 简单起见，只有 QuotaExhausted 做细粒度隔离。
-            account_id.to_string()
-        };
+/// 半开探测闸门: 配额锁到期后的一段时间内，同一时刻只放行一个探测请求
+struct ProbeGate {
+    /// 半开窗口开始时间 (即锁到期时间)
+    opens_at: SystemTime,
+    /// 半开窗口结束时间，之后完全放开
+    closes_at: SystemTime,
+    /// 是否已有探测请求在途
+    in_flight: AtomicBool,
+}
 
-        self.limits.insert(key, info.clone());
+/// 滚动窗口限流计数器默认容量
+const ROLLING_COUNTER_CAPACITY: usize = 4096;
 
-        tracing::warn!(
-            "账号 {} [{}] 限流类型: {:?}, 重置延时: {}秒",
-            account_id,
-            status,
-            reason,
-            retry_sec
-        );
+/// 滚动窗口限流计数器
+///
+/// 以环形缓冲记录每次 `parse_from_error` 的时间与是否触发限流，
+/// 用于计算最近一段时间内被限流请求的比例。
+pub struct RollingRateLimitCounter {
+    capacity: usize,
+    samples: Mutex<VecDeque<(SystemTime, bool)>>,
+}
 
-        Some(info)
+impl RollingRateLimitCounter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            samples: Mutex::new(VecDeque::with_capacity(capacity.max(1))),
+        }
     }
 
-    /// 解析限流原因类型
-    fn parse_rate_limit_reason(&self, body: &str) -> RateLimitReason {
-        // 尝试从 JSON 中提取 reason 字段
-        let trimmed = body.trim();
-        if trimmed.starts_with('{') || trimmed.starts_with('[') {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
-                if let Some(reason_str) = json
-                    .get("error")
-                    .and_then(|e| e.get("details"))
-                    .and_then(|d| d.as_array())
-                    .and_then(|a| a.get(0))
-                    .and_then(|o| o.get("reason"))
-                    .and_then(|v| v.as_str())
-                {
-                    return match reason_str {
-                        "QUOTA_EXHAUSTED" => RateLimitReason::QuotaExhausted,
-                        "RATE_LIMIT_EXCEEDED" => RateLimitReason::RateLimitExceeded,
-                        "MODEL_CAPACITY_EXHAUSTED" => RateLimitReason::ModelCapacityExhausted,
-                        _ => RateLimitReason::Unknown,
-                    };
-                }
-                // [NEW] 尝试从 message 字段进行文本匹配（防止 missed reason）
-                if let Some(msg) = json
-                    .get("error")
-                    .and_then(|e| e.get("message"))
-                    .and_then(|v| v.as_str())
-                {
-                    let msg_lower = msg.to_lowercase();
-                    if msg_lower.contains("per minute") || msg_lower.contains("rate limit") {
-                        return RateLimitReason::RateLimitExceeded;
-                    }
-                }
-            }
+    /// 以 `now` 记录一次请求结果 (`blocked` 表示触发了限流)，时间由跟踪器的时钟提供
+    pub fn record_at(&self, now: SystemTime, blocked: bool) {
+        let mut samples = self.samples.lock();
+        if samples.len() >= self.capacity {
+            samples.pop_front();
         }
+        samples.push_back((now, blocked));
+    }
 
-        // 如果无法从 JSON 解析，尝试从消息文本判断
-        let body_lower = body.to_lowercase();
-        // [FIX] 优先判断分钟级限制，避免将 TPM 误判为 Quota
-        if body_lower.contains("per minute")
-            || body_lower.contains("rate limit")
-            || body_lower.contains("too many requests")
-        {
-            RateLimitReason::RateLimitExceeded
-        } else if body_lower.contains("exhausted") || body_lower.contains("quota") {
-            RateLimitReason::QuotaExhausted
+    /// 截至 `now` 最近 `window` 内被限流的比例 (0.0 - 1.0)，窗口内无记录时为 0.0
+    pub fn throttle_rate_at(&self, now: SystemTime, window: Duration) -> f64 {
+        let since = now.checked_sub(window).unwrap_or(SystemTime::UNIX_EPOCH);
+        let samples = self.samples.lock();
+        let (total, blocked) = samples
+            .iter()
+            .rev()
+            .take_while(|(at, _)| *at >= since)
+            .fold((0usize, 0usize), |(total, blocked), (_, b)| {
+                (total + 1, blocked + usize::from(*b))
+            });
+        if total == 0 {
+            0.0
         } else {
-            RateLimitReason::Unknown
+            blocked as f64 / total as f64
         }
     }
+}
 
-    /// 通用时间解析函数：支持 "2h1m1s" 等所有格式组合
-    fn parse_duration_string(&self, s: &str) -> Option<u64> {
-        tracing::debug!("[时间解析] 尝试解析: '{}'", s);
+impl Default for RollingRateLimitCounter {
+    fn default() -> Self {
+        Self::new(ROLLING_COUNTER_CAPACITY)
+    }
+}
 
-        // 使用正则表达式提取小时、分钟、秒、毫秒
-        // 支持格式："2h1m1s", "1h30m", "5m", "30s", "500ms", "510.790006ms" 等
-        // 🔧 [FIX] 修改 ms 部分支持小数: (\d+)ms -> (\d+(?:\.\d+)?)ms
-        let re = Regex::new(r"(?:(\d+)h)?(?:(\d+)m)?(?:(\d+(?:\.\d+)?)s)?(?:(\d+(?:\.\d+)?)ms)?")
-            .ok()?;
-        let caps = match re.captures(s) {
-            Some(c) => c,
-            None => {
-                tracing::warn!("[时间解析] 正则未匹配: '{}'", s);
-                return None;
-            }
-        };
+/// 写入限流记录的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockOutcome {
+    /// 新建锁 (此前无生效中的锁)
+    Created,
+    /// 延长了已有的锁
+    Extended,
+    /// 已有的锁更晚到期，保留原锁不缩短
+    Ignored,
+}
 
-        let hours = caps
-            .get(1)
-            .and_then(|m| m.as_str().parse::<u64>().ok())
-            .unwrap_or(0);
-        let minutes = caps
-            .get(2)
-            .and_then(|m| m.as_str().parse::<u64>().ok())
-            .unwrap_or(0);
-        let seconds = caps
-            .get(3)
-            .and_then(|m| m.as_str().parse::<f64>().ok())
-            .unwrap_or(0.0);
-        // 🔧 [FIX] 毫秒也支持小数解析
-        let milliseconds = caps
-            .get(4)
-            .and_then(|m| m.as_str().parse::<f64>().ok())
-            .unwrap_or(0.0);
+/// 等待时间相同的候选账号之间的选择策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    /// 按输入顺序 (默认，兼容旧行为)
+    #[default]
+    InputOrder,
+    /// 按 (账号 ID, 选择轮次) 的哈希排序，使同等可用的账号轮流被选中
+    #[allow(dead_code)]
+    Hashed,
+}
 
-        tracing::debug!(
-            "[时间解析] 提取结果: {}h {}m {:.3}s {:.3}ms",
-            hours,
-            minutes,
-            seconds,
-            milliseconds
-        );
+/// `record_error` 的输入: 一次上游错误响应的完整信息
+#[allow(dead_code)]
+pub struct ErrorContext<'a> {
+    pub account: &'a AccountKey,
+    pub status: u16,
+    pub headers: &'a HeaderMap,
+    pub body: &'a str,
+    pub model: Option<String>,
+    pub backoff_steps: &'a [u64],
+}
 
-        // 🔧 [FIX] 计算总秒数，毫秒部分向上取整
-        let total_seconds = hours * 3600
-            + minutes * 60
-            + seconds.ceil() as u64
-            + (milliseconds / 1000.0).ceil() as u64;
+/// 锁定时长的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockoutSource {
+    /// Retry-After / ratelimit 响应头
+    Header,
+    /// 错误响应体中的 quotaResetDelay / retry_after 等
+    Body,
+    /// 未给出时长，按限流类型使用默认退避
+    Default,
+}
 
-        // 如果总秒数为 0，说明解析失败
-        if total_seconds == 0 {
-            tracing::warn!("[时间解析] 失败: '{}' (总秒数为0)", s);
-            None
-        } else {
-            tracing::info!(
-                "[时间解析] ✓ 成功: '{}' => {}秒 ({}h {}m {:.1}s {:.1}ms)",
-                s,
-                total_seconds,
-                hours,
-                minutes,
-                seconds,
-                milliseconds
-            );
-            Some(total_seconds)
+/// 锁定时长直方图的桶上界(秒)，超出最后一档的计入 +Inf
+const LOCK_DURATION_BUCKETS: [u64; 7] = [5, 30, 60, 300, 1800, 3600, 7200];
+
+/// 锁定时长分布 (用于 Prometheus 直方图导出)，各桶为非累计计数
+#[derive(Debug, Default)]
+struct LockDurationHistogram {
+    buckets: [u64; LOCK_DURATION_BUCKETS.len() + 1],
+    sum: u64,
+    count: u64,
+}
+
+impl LockDurationHistogram {
+    fn observe(&mut self, secs: u64) {
+        let idx = LOCK_DURATION_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LOCK_DURATION_BUCKETS.len());
+        self.buckets[idx] += 1;
+        self.sum = self.sum.saturating_add(secs);
+        self.count += 1;
+    }
+}
+
+/// 限流状态变化通知容量，接收方落后超过该条数时会丢弃最旧的通知
+const UPDATE_CHANNEL_CAPACITY: usize = 256;
+
+/// `subscribe` 推送的限流状态变化 (供前端展示倒计时)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitUpdate {
+    pub account_id: String,
+    /// 模型级锁的模型名 (规范名)，账号级锁为 None
+    pub model: Option<String>,
+    /// 新的解锁时间；解锁通知为解除的时间点
+    pub reset_time: SystemTime,
+    pub kind: RateLimitUpdateKind,
+}
+
+/// 限流状态变化的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitUpdateKind {
+    /// 加锁或延长
+    Locked,
+    Unlocked,
+    /// 未到期的锁被另一种原因延长 (紧随对应的 `Locked` 之后推送)
+    ReasonChanged {
+        from: RateLimitReason,
+        to: RateLimitReason,
+    },
+}
+
+/// 一次限流事件记录 (用于排查账号反复被锁的原因)
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct RateLimitEvent {
+    pub timestamp: SystemTime,
+    pub status: u16,
+    pub reason: RateLimitReason,
+    pub retry_sec: u64,
+    pub source: LockoutSource,
+    /// 本次事件覆盖了原因不同的生效中锁时为原来的原因 (如 ServerError -> QuotaExhausted)
+    pub previous_reason: Option<RateLimitReason>,
+}
+
+/// 用户追加的解析规则
+#[derive(Default)]
+struct ParsingRules {
+    /// 额外的等待时间正则，第一个捕获组为秒数
+    retry_patterns: Vec<Regex>,
+    /// 响应体子串 (小写) -> 限流原因，优先于内置判断
+    reason_mappings: Vec<(String, RateLimitReason)>,
+}
+
+/// 当前生效的解析配置 (内置 + 自定义)，供配置检查界面展示
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct ParsingConfigDescription {
+    pub retry_patterns: Vec<String>,
+    pub reason_mappings: Vec<(String, RateLimitReason)>,
+    pub handled_statuses: Vec<u16>,
+}
+
+/// 按限流原因配置的退避阶梯 (秒)
+///
+/// 第 N 次连续失败使用第 N 档，超出后停在最后一档；空阶梯回退到内置默认值。
+/// 5xx 的软避让按状态码配置，见 `BackoffConfig::server_error_lockout_secs`。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReasonLadders {
+    /// QuotaExhausted 阶梯，仅在调用方传入的 `backoff_steps` 为空时使用
+    pub quota_exhausted: Vec<u64>,
+    /// RateLimitExceeded (RPM 及未细分)
+    pub rate_limit_exceeded: Vec<u64>,
+    /// RateLimitExceeded 中的 TPM
+    pub tokens_per_minute: Vec<u64>,
+    /// ModelCapacityExhausted
+    pub model_capacity_exhausted: Vec<u64>,
+    /// Overloaded (使用独立的过载计数)
+    pub overloaded: Vec<u64>,
+    /// Unknown
+    pub unknown: Vec<u64>,
+}
+
+impl Default for ReasonLadders {
+    fn default() -> Self {
+        Self {
+            quota_exhausted: Vec::new(),
+            rate_limit_exceeded: vec![RPM_LOCKOUT_SECS],
+            tokens_per_minute: vec![TPM_LOCKOUT_SECS],
+            model_capacity_exhausted: vec![5, 10, 15],
+            overloaded: vec![2, 5, 10],
+            unknown: vec![UNKNOWN_LOCKOUT_SECS],
         }
     }
+}
 
-    /// 从错误消息 body 中解析重置时间
-    fn parse_retry_time_from_body(&self, body: &str) -> Option<u64> {
-        // A. 优先尝试 JSON 精准解析
-        let trimmed = body.trim();
-        if trimmed.starts_with('{') || trimmed.starts_with('[') {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
-                // 1. Google 常见的 quotaResetDelay 格式 (支持所有格式："2h1m1s", "1h30m", "42s", "500ms" 等)
-                // 路径: error.details[0].metadata.quotaResetDelay
-                if let Some(delay_str) = json
-                    .get("error")
-                    .and_then(|e| e.get("details"))
-                    .and_then(|d| d.as_array())
-                    .and_then(|a| a.get(0))
-                    .and_then(|o| o.get("metadata")) // 添加 metadata 层级
-                    .and_then(|m| m.get("quotaResetDelay"))
-                    .and_then(|v| v.as_str())
-                {
-                    tracing::debug!("[JSON解析] 找到 quotaResetDelay: '{}'", delay_str);
+/// 取阶梯中第 `failure_count` 档 (从 1 开始)，超出时取最后一档，空阶梯返回 `fallback`
+fn ladder_step(ladder: &[u64], failure_count: u32, fallback: u64) -> u64 {
+    let index = (failure_count as usize).saturating_sub(1);
+    ladder
+        .get(index)
+        .or_else(|| ladder.last())
+        .copied()
+        .unwrap_or(fallback)
+}
 
-                    // 使用通用时间解析函数
-                    if let Some(seconds) = self.parse_duration_string(delay_str) {
-                        return Some(seconds);
-                    }
-                }
+/// 退避策略配置
+///
+/// 可序列化为 JSON 保存或分享 (见 `from_json_str` / `to_json_str`)，缺省字段取默认值。
+/// 任何途径的反序列化 (包括嵌在其他配置中) 都会执行 `validate`，非法取值直接报错。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self", default)]
+pub struct BackoffConfig {
+    /// 单次锁定的最长时长，防止配置错误或异常上游值 (如 quotaResetDelay "999h")
+    /// 导致账号被长期锁死。同时作用于 `parse_from_error` 与 `set_lockout_until`
+    pub max_lockout_duration: Duration,
+    /// 退避阶梯的随机抖动比例 (0.0 表示关闭，0.2 表示 ±20%)
+    pub jitter_ratio: f64,
+    /// 是否将抖动限制在 [当前阶梯, 下一阶梯) 区间内，保证阶梯位置可辨识
+    pub clamp_jitter: bool,
+    /// 按 HTTP 状态码配置的软避让时长(秒)，用于未返回 Retry-After 的 404/5xx
+    pub server_error_lockout_secs: HashMap<u16, u64>,
+    /// 404 判定为模型不存在 (`ModelNotFound`) 时该模型的锁定时长(秒)
+    pub model_not_found_lockout_secs: u64,
+    /// 未在 `server_error_lockout_secs` 中配置的 5xx 使用的默认软避让时长(秒)
+    pub default_server_error_lockout_secs: u64,
+    /// `least_limited` 在等待时间相同时的选择策略
+    pub tie_break: TieBreak,
+    /// 单账号的基础并发上限；有连续失败时实际上限为 base / (1 + failures)
+    pub base_concurrency: usize,
+    /// QuotaExhausted 锁到期后的半开窗口时长，窗口内同一时刻只放行一个探测请求
+    /// (Duration::ZERO 表示关闭)
+    pub half_open_window: Duration,
+    /// 每个账号保留的最近限流事件条数 (0 表示不记录)
+    pub history_capacity: usize,
+    /// `clear_all` (乐观重置) 时是否同时清空失败计数，让退避阶梯从头开始
+    pub clear_all_resets_failures: bool,
+    /// 每次失败在退避阶梯上前进的档数 (默认 1)
+    pub escalation_steps: u32,
+    /// 每次成功在退避阶梯上回退的档数 (0 表示直接清零，即原有行为)
+    pub deescalation_steps: u32,
+    /// 将 `Retry-After: 0` 视为立即在同一账号重试 (不加锁)，而不是套用 `min_retry_secs` 安全下限。
+    /// 注意: 上游持续返回 0 时调用方可能陷入紧密重试循环，需配合重试次数上限使用
+    pub honor_zero_retry_after: bool,
+    /// 上游给出的等待时间 (响应头 / body / RetryInfo) 的安全下限(秒)，防止极高频无效重试
+    pub min_retry_secs: u64,
+    /// 每日配额刷新时间 (时, 分)。设置后未给出 quotaResetDelay 的 QuotaExhausted
+    /// 直接锁定到下一个刷新时间点，而不是走 backoff_steps 阶梯
+    pub daily_reset_time: Option<(u8, u8)>,
+    /// `daily_reset_time` 所在时区相对 UTC 的偏移(秒)，默认 -8h (太平洋标准时间，不含夏令时)
+    pub daily_reset_utc_offset_secs: i32,
+    /// 响应头/body 给出的等待时间超过该值时视为异常 (如 Retry-After: 999999999)，
+    /// 忽略该值并回退到按限流类型的默认退避
+    pub implausible_retry_threshold: Duration,
+    /// 账号失败率低于该值时 (样本足够)，QuotaExhausted 不沿阶梯升级，始终使用第一档。
+    /// 避免长期健康的账号因偶发连续失败被长时间锁定 (0.0 表示关闭)
+    pub min_failure_rate_for_escalation: f64,
+    /// `mark_success` 是否同时清除账号的失效标记 (如凭证修复后探测成功)
+    pub auto_revive_on_success: bool,
+    /// 连续失败次数超过该值时永久停用账号 (隔离，需 `re_enable` 恢复)，0 表示不启用
+    pub max_failure_count: u32,
+    /// 按限流原因的退避阶梯
+    pub ladders: ReasonLadders,
+    /// 同一账号连续 5xx 超过该次数后，软避让时长逐次翻倍 (0 表示不升级)
+    pub server_error_escalation_after: u32,
+    /// 5xx 软避让升级后的上限(秒)
+    pub server_error_max_lockout_secs: u64,
+}
 
-                // 2. OpenAI 常见的 retry_after 字段 (数字)
-                if let Some(retry) = json
-                    .get("error")
-                    .and_then(|e| e.get("retry_after"))
-                    .and_then(|v| v.as_u64())
-                {
-                    return Some(retry);
-                }
-            }
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            max_lockout_duration: Duration::from_secs(24 * 3600),
+            jitter_ratio: 0.0,
+            clamp_jitter: false,
+            // 404: 资源路径不存在 (如 "Requested entity was not found")，短暂避让后轮换
+            // 500: 多为偶发错误，快速重试
+            // 503: 已知的暂时性故障，避让稍久
+            server_error_lockout_secs: HashMap::from([(404, 5), (500, 4), (503, 8)]),
+            model_not_found_lockout_secs: 300,
+            default_server_error_lockout_secs: 8,
+            tie_break: TieBreak::InputOrder,
+            base_concurrency: 8,
+            half_open_window: Duration::ZERO,
+            history_capacity: 20,
+            clear_all_resets_failures: false,
+            escalation_steps: 1,
+            deescalation_steps: 0,
+            honor_zero_retry_after: false,
+            min_retry_secs: 2,
+            daily_reset_time: None,
+            daily_reset_utc_offset_secs: -8 * 3600,
+            implausible_retry_threshold: Duration::from_secs(7 * 24 * 3600),
+            min_failure_rate_for_escalation: 0.0,
+            auto_revive_on_success: false,
+            max_failure_count: 0,
+            ladders: ReasonLadders::default(),
+            server_error_escalation_after: 5,
+            server_error_max_lockout_secs: 300,
         }
+    }
+}
 
-        // B. 正则匹配模式 (兜底)
-        // 模式 1: "Try again in 2m 30s"
-        if let Ok(re) = Regex::new(r"(?i)try again in (\d+)m\s*(\d+)s") {
-            if let Some(caps) = re.captures(body) {
-                if let (Ok(m), Ok(s)) = (caps[1].parse::<u64>(), caps[2].parse::<u64>()) {
-                    return Some(m * 60 + s);
-                }
-            }
+// `remote = "Self"` 让派生代码生成同名的关联函数，这里包一层 trait 实现以插入 `validate`
+impl Serialize for BackoffConfig {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BackoffConfig::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BackoffConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let config = BackoffConfig::deserialize(deserializer)?;
+        config.validate().map_err(serde::de::Error::custom)?;
+        Ok(config)
+    }
+}
+
+/// 兼容旧用法: 只设置 QuotaExhausted 阶梯，其余保持默认
+impl From<Vec<u64>> for BackoffConfig {
+    fn from(quota_steps: Vec<u64>) -> Self {
+        Self {
+            ladders: ReasonLadders {
+                quota_exhausted: quota_steps,
+                ..ReasonLadders::default()
+            },
+            ..Self::default()
         }
+    }
+}
 
-        // 模式 2: "Try again in 30s" 或 "backoff for 42s"
-        if let Ok(re) = Regex::new(r"(?i)(?:try again in|backoff for|wait)\s*(\d+)s") {
-            if let Some(caps) = re.captures(body) {
-                if let Ok(s) = caps[1].parse::<u64>() {
-                    return Some(s);
-                }
+impl BackoffConfig {
+    /// 从 JSON 导入配置 (反序列化时已执行 `validate`)
+    #[allow(dead_code)]
+    pub fn from_json_str(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
+    /// 导出为 JSON
+    #[allow(dead_code)]
+    pub fn to_json_str(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// 检查配置取值是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_lockout_duration.is_zero() {
+            return Err("max_lockout_duration 必须大于 0".to_string());
+        }
+        if !self.jitter_ratio.is_finite() || self.jitter_ratio < 0.0 {
+            return Err(format!("jitter_ratio 无效: {}", self.jitter_ratio));
+        }
+        if !(0.0..=1.0).contains(&self.min_failure_rate_for_escalation) {
+            return Err(format!(
+                "min_failure_rate_for_escalation 必须在 0.0 - 1.0 之间: {}",
+                self.min_failure_rate_for_escalation
+            ));
+        }
+        if let Some((hour, minute)) = self.daily_reset_time {
+            if hour >= 24 || minute >= 60 {
+                return Err(format!("daily_reset_time 无效: {:02}:{:02}", hour, minute));
             }
         }
+        if self.daily_reset_utc_offset_secs.abs() >= 24 * 3600 {
+            return Err(format!(
+                "daily_reset_utc_offset_secs 超出范围: {}",
+                self.daily_reset_utc_offset_secs
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 一次乐观重置 (`clear_all`) 的现场记录，用于判断重置是否合理
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct OptimisticResetRecord {
+    pub ts: SystemTime,
+    /// 重置时仍处于锁定中的账号数
+    pub accounts_affected: usize,
+    /// 重置时剩余等待的最小值(秒)
+    pub min_wait_at_reset: u64,
+    /// 重置时剩余等待的最大值(秒)
+    pub max_wait_at_reset: u64,
+}
+
+/// 跟踪器核心状态的内存快照，用于配置热重载时把锁定状态交接给新跟踪器
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default)]
+pub struct TrackerSnapshot {
+    pub limits: HashMap<String, RateLimitInfo>,
+    pub failure_counts: HashMap<String, (u32, SystemTime)>,
+}
+
+/// 过期清理结果统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CleanupStats {
+    /// 清除的过期限流记录数
+    pub limits_removed: usize,
+    /// 清除的过期失败计数记录数
+    pub failure_counts_removed: usize,
+}
+
+/// 当前所有被锁定账号的剩余等待分布 (秒)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AccountStatistics {
+    pub locked_count: usize,
+    pub min_remaining_secs: u64,
+    pub max_remaining_secs: u64,
+    pub mean_remaining_secs: f64,
+    pub median_remaining_secs: f64,
+    /// 最早恢复的账号
+    pub min_account_id: Option<String>,
+    /// 最晚恢复的账号
+    pub max_account_id: Option<String>,
+}
+
+impl CleanupStats {
+    /// 清除的记录总数
+    pub fn total(&self) -> usize {
+        self.limits_removed + self.failure_counts_removed
+    }
+}
+
+/// 限流跟踪器内存占用估算 (字节数为近似值: Key 长度 + 结构体大小)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// 限流记录条数
+    pub limit_entries: usize,
+    /// 限流记录估算字节数
+    pub limits_bytes: usize,
+    /// 失败计数条数
+    pub failure_count_entries: usize,
+    /// 失败计数估算字节数
+    pub failure_counts_bytes: usize,
+    /// 限流事件历史条数
+    pub history_entries: usize,
+    /// 限流事件历史估算字节数
+    pub history_bytes: usize,
+}
+
+impl MemoryReport {
+    /// 估算总字节数
+    #[allow(dead_code)]
+    pub fn total_bytes(&self) -> usize {
+        self.limits_bytes + self.failure_counts_bytes + self.history_bytes
+    }
+}
+
+/// 时间来源，跟踪器内所有 "当前时间" 都经由它获取，测试中可替换为固定时间
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> SystemTime;
+}
+
+/// 真实系统时钟
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// 返回固定时间的时钟，可手动设置或拨快
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+#[allow(dead_code)]
+impl MockClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock()
+    }
+}
+
+/// 限流跟踪器
+pub struct RateLimitTracker<C: Clock = SystemClock> {
+    limits: DashMap<String, RateLimitInfo>,
+    /// 连续失败计数（用于智能指数退避），带时间戳用于自动过期
+    failure_counts: DashMap<String, (u32, SystemTime)>,
+    /// 连续过载计数 (529)，与 failure_counts 分开，避免污染 429 的退避阶梯
+    overload_counts: DashMap<String, (u32, SystemTime)>,
+    /// 连续 5xx 计数，与 failure_counts 分开，只用于升级软避让时长
+    server_error_counts: DashMap<String, (u32, SystemTime)>,
+    /// 退避策略配置
+    backoff_config: RwLock<BackoffConfig>,
+    /// 按账号覆盖的退避策略 (如付费账号使用更温和的阶梯)，未设置时使用全局配置
+    account_policies: DashMap<String, BackoffConfig>,
+    /// 锁定事件监听器
+    lockout_listeners: RwLock<Vec<LockoutListener>>,
+    /// 自定义分类钩子，按注册顺序在内置解析之前调用
+    classification_hooks: RwLock<Vec<ClassificationHook>>,
+    /// `least_limited` 的选择轮次，用于哈希打散
+    selection_round: AtomicU64,
+    /// 按账号的并发限制 (随失败计数收紧)
+    concurrency: ConcurrencyLimiter,
+    /// 半开探测闸门 (account_id -> gate)
+    probe_gates: DashMap<String, ProbeGate>,
+    /// 滚动窗口限流比例统计
+    throttle_counter: RollingRateLimitCounter,
+    /// 按账号的最近限流事件 (环形缓冲)
+    history: DashMap<String, VecDeque<RateLimitEvent>>,
+    /// 自定义解析规则
+    parsing_rules: RwLock<ParsingRules>,
+    /// 最近一次乐观重置的记录
+    last_optimistic_reset: RwLock<Option<OptimisticResetRecord>>,
+    /// 被判定为异常而忽略的上游等待时间次数
+    implausible_retry_count: AtomicU64,
+    /// 按账号的累计请求结果，用于计算失败率
+    request_stats: DashMap<String, AccountStats>,
+    /// 按 "account_id:model" 的请求结果，成功标记时清零
+    model_request_stats: DashMap<String, AccountStats>,
+    /// 自上次导出 StatsD 指标以来，按原因统计的新增锁定次数
+    pending_lockout_metrics: DashMap<RateLimitReason, u64>,
+    /// 按原因统计的累计锁定次数 (Prometheus 计数器，只增不减)
+    lockout_totals: DashMap<RateLimitReason, u64>,
+    /// 新建/延长的锁定时长分布 (不含永久停用)
+    lock_durations: Mutex<LockDurationHistogram>,
+    /// 被隔离的账号 (不随限流过期或乐观重置清除，需人工解除)
+    quarantined: DashMap<String, QuarantineInfo>,
+    /// 运维人员为账号添加的备注 (如 "试用账号，1 月 31 日到期")
+    notes: DashMap<String, String>,
+    /// 每个账号最近观测到的 X-RateLimit-Limit，用于发现套餐升级
+    observed_limits: DashMap<String, u64>,
+    /// 模型别名 -> 规范模型名，别名共享规范名的模型级锁
+    model_aliases: DashMap<String, String>,
+    /// 上一次 `health_check` 得出的等级，用于识别等级变化
+    last_health_status: Mutex<HealthStatus>,
+    /// 限流状态变化广播 (加锁/延长/解锁)
+    updates: tokio::sync::broadcast::Sender<RateLimitUpdate>,
+    /// 时间来源
+    clock: C,
+}
+
+impl RateLimitTracker {
+    pub fn new() -> Self {
+        Self::with_config(BackoffConfig::default())
+    }
+
+    /// 使用指定的退避配置创建跟踪器
+    pub fn with_config(config: BackoffConfig) -> Self {
+        RateLimitTracker::with_clock(config, SystemClock)
+    }
+}
+
+#[cfg(test)]
+impl RateLimitTracker<MockClock> {
+    /// 测试用: 时间冻结在 `now` 的跟踪器，可通过 `clock().advance()` 拨快
+    pub fn with_frozen_time(now: SystemTime) -> Self {
+        RateLimitTracker::with_clock(BackoffConfig::default(), MockClock::new(now))
+    }
+}
+
+impl<C: Clock> RateLimitTracker<C> {
+    /// 使用指定的退避配置与时间来源创建跟踪器
+    pub fn with_clock(config: BackoffConfig, clock: C) -> Self {
+        Self {
+            limits: DashMap::new(),
+            failure_counts: DashMap::new(),
+            overload_counts: DashMap::new(),
+            server_error_counts: DashMap::new(),
+            backoff_config: RwLock::new(config),
+            account_policies: DashMap::new(),
+            lockout_listeners: RwLock::new(Vec::new()),
+            classification_hooks: RwLock::new(Vec::new()),
+            selection_round: AtomicU64::new(0),
+            concurrency: ConcurrencyLimiter::new(),
+            probe_gates: DashMap::new(),
+            throttle_counter: RollingRateLimitCounter::default(),
+            history: DashMap::new(),
+            parsing_rules: RwLock::new(ParsingRules::default()),
+            last_optimistic_reset: RwLock::new(None),
+            implausible_retry_count: AtomicU64::new(0),
+            request_stats: DashMap::new(),
+            model_request_stats: DashMap::new(),
+            pending_lockout_metrics: DashMap::new(),
+            lockout_totals: DashMap::new(),
+            lock_durations: Mutex::new(LockDurationHistogram::default()),
+            quarantined: DashMap::new(),
+            notes: DashMap::new(),
+            observed_limits: DashMap::new(),
+            model_aliases: DashMap::new(),
+            last_health_status: Mutex::new(HealthStatus::Ok),
+            updates: tokio::sync::broadcast::channel(UPDATE_CHANNEL_CAPACITY).0,
+            clock,
+        }
+    }
+
+    /// 跟踪器使用的时钟
+    #[allow(dead_code)]
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    /// 获取当前退避配置
+    #[allow(dead_code)]
+    pub fn backoff_config(&self) -> BackoffConfig {
+        self.backoff_config.read().clone()
+    }
+
+    /// 更新退避配置 (热更新，对后续请求生效)
+    ///
+    /// 已有的限流历史会按新的 `history_capacity` 立即裁剪，设为 0 时释放全部历史。
+    #[allow(dead_code)]
+    pub fn set_backoff_config(&self, config: BackoffConfig) {
+        let capacity = config.history_capacity;
+        *self.backoff_config.write() = config;
+        self.trim_history(capacity);
+    }
+
+    /// 为指定账号设置独立的退避策略，覆盖全局配置
+    #[allow(dead_code)]
+    pub fn set_account_policy(&self, account: &AccountKey, policy: BackoffConfig) {
+        let account_id = &account.tracker_id();
+        self.account_policies.insert(account_id.to_string(), policy);
+    }
+
+    /// 移除账号的独立退避策略，恢复使用全局配置；返回此前是否设置过
+    #[allow(dead_code)]
+    pub fn remove_account_policy(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        self.account_policies.remove(account_id).is_some()
+    }
+
+    /// 账号生效的退避策略: 优先使用账号级策略，否则为全局配置
+    fn policy_for(&self, account_id: &str) -> BackoffConfig {
+        self.policy_value(account_id, BackoffConfig::clone)
+    }
+
+    /// 读取账号生效策略中的单个字段，避免克隆整个配置
+    fn policy_value<T>(&self, account_id: &str, f: impl FnOnce(&BackoffConfig) -> T) -> T {
+        match self.account_policies.get(account_id) {
+            Some(policy) => f(&policy),
+            None => f(&self.backoff_config.read()),
+        }
+    }
+
+    /// 配额耗尽退避阶梯: 账号级策略配置了阶梯时优先于调用方传入的 (全局) 阶梯
+    fn quota_steps_for<'a>(
+        &self,
+        account_id: &str,
+        policy: &'a BackoffConfig,
+        backoff_steps: &'a [u64],
+    ) -> &'a [u64] {
+        if !policy.ladders.quota_exhausted.is_empty()
+            && self.account_policies.contains_key(account_id)
+        {
+            &policy.ladders.quota_exhausted
+        } else {
+            backoff_steps
+        }
+    }
+
+    /// 将每个账号的历史裁剪到 `capacity` 条 (丢弃最旧的)，0 表示清空并释放存储
+    fn trim_history(&self, capacity: usize) {
+        if capacity == 0 {
+            self.history.clear();
+            self.history.shrink_to_fit();
+            return;
+        }
+        for mut events in self.history.iter_mut() {
+            if events.len() > capacity {
+                let excess = events.len() - capacity;
+                events.drain(..excess);
+                events.shrink_to_fit();
+            }
+        }
+    }
+
+    /// 注册锁定事件回调，每次写入限流记录时触发
+    #[allow(dead_code)]
+    pub fn on_lockout<F>(&self, callback: F)
+    where
+        F: Fn(&str, &RateLimitInfo) + Send + Sync + 'static,
+    {
+        self.lockout_listeners.write().push(LockoutListener {
+            reasons: None,
+            callback: Arc::new(callback),
+        });
+    }
+
+    /// 注册只响应指定原因的锁定事件回调 (如只关注 QuotaExhausted，忽略 5xx 软避让)
+    #[allow(dead_code)]
+    pub fn on_lockout_filtered<F>(&self, reasons: &[RateLimitReason], callback: F)
+    where
+        F: Fn(&str, &RateLimitInfo) + Send + Sync + 'static,
+    {
+        self.lockout_listeners.write().push(LockoutListener {
+            reasons: Some(reasons.to_vec()),
+            callback: Arc::new(callback),
+        });
+    }
+
+    /// 注册自定义分类钩子，用于识别其他服务商的错误格式 (可处理任意状态码)
+    ///
+    /// `parse_from_error` 先按注册顺序调用钩子，第一个返回 Some 的结果生效:
+    /// 给出秒数时直接使用，否则按该原因走响应头 / body / 默认退避。
+    #[allow(dead_code)]
+    pub fn add_classification_hook<F>(&self, hook: F)
+    where
+        F: Fn(u16, &str) -> Option<(RateLimitReason, Option<u64>)> + Send + Sync + 'static,
+    {
+        self.classification_hooks.write().push(Arc::new(hook));
+    }
+
+    /// 依次调用自定义分类钩子，返回第一个命中的结果
+    fn run_classification_hooks(
+        &self,
+        status: u16,
+        body: &str,
+    ) -> Option<(RateLimitReason, Option<u64>)> {
+        // 先复制出钩子列表，钩子内部可以安全地再调用跟踪器
+        let hooks: Vec<ClassificationHook> = self.classification_hooks.read().clone();
+        hooks.iter().find_map(|hook| hook(status, body))
+    }
+
+    /// 写入限流记录并通知监听器
+    ///
+    /// 已有更晚到期的锁时不会被更短的退避覆盖 (返回 `Ignored`，也不触发回调)。
+    fn store_lock(&self, key: String, account_id: &str, info: RateLimitInfo) -> LockOutcome {
+        self.replace_lock(key, account_id, info).0
+    }
+
+    /// 同 `store_lock`，额外返回被覆盖的生效中锁的原因 (原因相同或未覆盖时为 None)
+    fn replace_lock(
+        &self,
+        key: String,
+        account_id: &str,
+        mut info: RateLimitInfo,
+    ) -> (LockOutcome, Option<RateLimitReason>) {
+        let mut previous_reason = None;
+        let outcome = match self.limits.entry(key.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(mut existing) => {
+                let current = existing.get().reset_time;
+                if current <= self.now() {
+                    existing.insert(info.clone());
+                    LockOutcome::Created
+                } else if info.reset_time > current {
+                    // 延长锁时保留首次检测时间，"已锁定多久" 的统计不被重置
+                    info.detected_at = existing.get().detected_at;
+                    let old_reason = existing.get().reason;
+                    if old_reason != info.reason {
+                        tracing::info!(
+                            account_id = %account_id,
+                            from = ?old_reason,
+                            to = ?info.reason,
+                            retry_sec = info.retry_after_sec,
+                            "限流原因变化，锁已按新原因延长"
+                        );
+                        previous_reason = Some(old_reason);
+                    }
+                    existing.insert(info.clone());
+                    LockOutcome::Extended
+                } else {
+                    LockOutcome::Ignored
+                }
+            }
+            dashmap::mapref::entry::Entry::Vacant(slot) => {
+                slot.insert(info.clone());
+                LockOutcome::Created
+            }
+        };
+        if outcome == LockOutcome::Ignored {
+            tracing::debug!(account_id = %account_id, "已有更晚到期的锁，保留原锁");
+            return (outcome, None);
+        }
+        self.notify_update(&key, info.reset_time, RateLimitUpdateKind::Locked);
+        if let Some(from) = previous_reason {
+            let to = info.reason;
+            self.notify_update(
+                &key,
+                info.reset_time,
+                RateLimitUpdateKind::ReasonChanged { from, to },
+            );
+        }
+        *self.pending_lockout_metrics.entry(info.reason).or_insert(0) += 1;
+        *self.lockout_totals.entry(info.reason).or_insert(0) += 1;
+        if info.reason != RateLimitReason::PermanentlyDisabled {
+            self.lock_durations.lock().observe(info.retry_after_sec);
+        }
+
+        // 配额锁到期后进入半开窗口，避免流量瞬间涌入导致再次被锁
+        let half_open_window = self.backoff_config.read().half_open_window;
+        if info.reason == RateLimitReason::QuotaExhausted && !half_open_window.is_zero() {
+            self.probe_gates.insert(
+                account_id.to_string(),
+                ProbeGate {
+                    opens_at: info.reset_time,
+                    closes_at: info.reset_time + half_open_window,
+                    in_flight: AtomicBool::new(false),
+                },
+            );
+        }
+
+        // 先复制回调列表再调用，避免回调中注册新监听器时死锁
+        let callbacks: Vec<LockoutCallback> = self
+            .lockout_listeners
+            .read()
+            .iter()
+            .filter(|l| l.reasons.as_ref().is_none_or(|r| r.contains(&info.reason)))
+            .map(|l| l.callback.clone())
+            .collect();
+        for callback in callbacks {
+            callback(account_id, &info);
+        }
+        (outcome, previous_reason)
+    }
+
+    /// 生成限流 Key
+    /// - 账号级: "account_id" (带区域时为 "region/account_id")
+    /// - 模型级: "account_id:model_id" (模型名先按别名表归一)
+    #[allow(dead_code)]
+    pub fn get_limit_key(&self, account: &AccountKey, model: Option<&str>) -> String {
+        self.limit_key(&account.tracker_id(), model)
+    }
+
+    /// `get_limit_key` 的内部实现，`account_id` 为 `AccountKey::tracker_id()`
+    fn limit_key(&self, account_id: &str, model: Option<&str>) -> String {
+        match model {
+            Some(m) if !m.is_empty() => format!("{}:{}", account_id, self.canonical_model(m)),
+            _ => account_id.to_string(),
+        }
+    }
+
+    /// 设置模型别名: 共享同一配额的模型 (如 `gemini-2.0-flash-001` -> `gemini-2.0-flash`)
+    /// 使用同一个模型级锁。`alias` 与 `canonical` 相同时移除该别名
+    #[allow(dead_code)]
+    pub fn set_model_alias(&self, alias: &str, canonical: &str) {
+        if alias == canonical {
+            self.model_aliases.remove(alias);
+        } else {
+            self.model_aliases
+                .insert(alias.to_string(), canonical.to_string());
+        }
+    }
+
+    /// 模型的规范名 (未设置别名时为自身)
+    fn canonical_model(&self, model: &str) -> String {
+        self.model_aliases
+            .get(model)
+            .map(|c| c.clone())
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    /// 订阅限流状态变化 (每次加锁、延长、解锁都会推送一条)
+    ///
+    /// 接收方处理过慢时会收到 `RecvError::Lagged`，可直接忽略并继续接收。
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<RateLimitUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// 广播一条限流 Key 的状态变化；没有订阅者时直接返回，不影响热路径
+    fn notify_update(&self, key: &str, reset_time: SystemTime, kind: RateLimitUpdateKind) {
+        if self.updates.receiver_count() == 0 {
+            return;
+        }
+        let (account_id, model) = match key.split_once(':') {
+            Some((account, model)) => (account, Some(model.to_string())),
+            None => (key, None),
+        };
+        let _ = self.updates.send(RateLimitUpdate {
+            account_id: account_id.to_string(),
+            model,
+            reset_time,
+            kind,
+        });
+    }
+
+    /// 广播一批 Key 被解锁
+    fn notify_unlocked<'a>(&self, keys: impl IntoIterator<Item = &'a String>) {
+        if self.updates.receiver_count() == 0 {
+            return;
+        }
+        let now = self.now();
+        for key in keys {
+            self.notify_update(key, now, RateLimitUpdateKind::Unlocked);
+        }
+    }
+
+    /// 获取账号剩余的等待时间(秒)
+    /// 支持检查账号级和模型级锁
+    pub fn get_remaining_wait(&self, account: &AccountKey, model: Option<&str>) -> u64 {
+        self.remaining_wait_secs(&account.tracker_id(), model)
+    }
+
+    /// 获取账号剩余的等待时间(毫秒)
+    ///
+    /// `get_remaining_wait` 向下取整到秒，按它 sleep 可能提前几百毫秒醒来并再次撞上限流。
+    pub fn get_remaining_wait_millis(&self, account: &AccountKey, model: Option<&str>) -> u128 {
+        self.remaining_wait(&account.tracker_id(), model)
+            .as_millis()
+    }
+
+    /// 获取账号可以恢复请求的最早时间点 (保留亚秒精度)
+    ///
+    /// 取账号级锁与模型级锁中较晚者；未被锁定时返回当前时间，
+    /// 隔离账号返回一个极远的时间点。可直接用于 `tokio::time::sleep_until`。
+    #[allow(dead_code)]
+    pub fn get_next_available_time(&self, account: &AccountKey, model: Option<&str>) -> SystemTime {
+        let account_id = &account.tracker_id();
+        let now = self.now();
+        if self.is_quarantined_by_id(account_id) {
+            return far_future(now);
+        }
+
+        let reset_of = |key: &str| self.limits.get(key).map(|info| info.reset_time);
+        let account_reset = reset_of(account_id);
+        let model_reset = match model {
+            Some(m) if !m.is_empty() => reset_of(&self.limit_key(account_id, Some(m))),
+            _ => None,
+        };
+        let reset = account_reset.max(model_reset).filter(|t| *t > now);
+
+        match reset {
+            Some(t) => t,
+            // 半开窗口内已有探测请求在途，与 get_remaining_wait 一致再等 1 秒
+            None if self.probe_in_flight(account_id, now) => now + Duration::from_secs(1),
+            None => now,
+        }
+    }
+
+    /// 获取账号在指定模型上的剩余等待时间(秒)，取账号级锁与模型级锁中较长者
+    #[allow(dead_code)]
+    pub fn get_model_remaining_wait(&self, account: &AccountKey, model: &str) -> u64 {
+        self.remaining_wait_secs(&account.tracker_id(), Some(model))
+    }
+
+    fn remaining_wait_secs(&self, account_id: &str, model: Option<&str>) -> u64 {
+        self.remaining_wait(account_id, model).as_secs()
+    }
+
+    /// 剩余等待时间的内部实现：账号级锁与模型级锁取最大值
+    fn remaining_wait(&self, account_id: &str, model: Option<&str>) -> Duration {
+        // 0. 隔离账号视为永久锁定
+        if self.is_quarantined_by_id(account_id) {
+            return Duration::from_secs(u64::MAX);
+        }
+        let now = self.now();
+
+        // 1. 检查全局账号锁
+        let account_wait = self.key_remaining(account_id, now);
+
+        // 2. 如果指定了模型，检查模型级锁
+        let model_wait = match model {
+            Some(m) if !m.is_empty() => {
+                let key = self.limit_key(account_id, Some(m));
+                self.key_remaining(&key, now)
+            }
+            _ => Duration::ZERO,
+        };
+
+        let wait = account_wait.max(model_wait);
+
+        // 3. 半开窗口内已有探测请求在途时，其他请求继续等待
+        if wait.is_zero() && self.probe_in_flight(account_id, now) {
+            return Duration::from_secs(1);
+        }
+        wait
+    }
+
+    /// 账号是否处于半开窗口且已有探测请求在途
+    fn probe_in_flight(&self, account_id: &str, now: SystemTime) -> bool {
+        self.probe_gates.get(account_id).is_some_and(|gate| {
+            gate.opens_at <= now && now < gate.closes_at && gate.in_flight.load(Ordering::Acquire)
+        })
+    }
+
+    /// 获取半开探测资格
+    ///
+    /// 账号处于配额锁到期后的半开窗口时，同一时刻只有一个调用者能拿到探测资格；
+    /// 探测成功后调用 `mark_success` 使账号完全放开，探测失败则由新的锁重新进入半开流程。
+    /// 不在半开窗口内时始终返回 true。
+    #[allow(dead_code)]
+    pub fn acquire_probe(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        let now = self.now();
+        match self.probe_gates.get(account_id) {
+            Some(gate) if gate.opens_at <= now && now < gate.closes_at => gate
+                .in_flight
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok(),
+            _ => true,
+        }
+    }
+
+    /// 单个限流 Key 的剩余秒数 (未锁定或已过期返回 0)
+    fn key_remaining_secs(&self, key: &str, now: SystemTime) -> u64 {
+        self.key_remaining(key, now).as_secs()
+    }
+
+    fn key_remaining(&self, key: &str, now: SystemTime) -> Duration {
+        self.limits
+            .get(key)
+            .and_then(|info| info.reset_time.duration_since(now).ok())
+            .unwrap_or_default()
+    }
+
+    /// 从候选账号中选出第一个当前可用 (剩余等待为 0) 的账号
+    #[allow(dead_code)]
+    pub fn pick_available<'a>(
+        &self,
+        candidates: &'a [AccountKey],
+        model: Option<&str>,
+    ) -> Option<&'a AccountKey> {
+        candidates
+            .iter()
+            .find(|account| self.remaining_wait_secs(&account.tracker_id(), model) == 0)
+    }
+
+    /// 检查指定模型是否在任一账号上可用 (账号级与模型级均未被限流)
+    #[allow(dead_code)]
+    pub fn model_available_somewhere(&self, accounts: &[AccountKey], model: &str) -> bool {
+        self.pick_available(accounts, Some(model)).is_some()
+    }
+
+    /// 选出剩余等待时间最短的账号，并返回其等待秒数
+    ///
+    /// 用于所有账号都被限流时决定"等谁"。若存在可用账号则返回其中之一 (等待为 0)；
+    /// 等待时间相同时的选择见 `least_limited`。
+    #[allow(dead_code)]
+    pub fn pick_soonest<'a>(
+        &self,
+        candidates: &'a [AccountKey],
+        model: Option<&str>,
+    ) -> Option<(&'a AccountKey, u64)> {
+        self.least_limited(candidates, model)
+    }
+
+    /// 选出限流最轻 (剩余等待最短) 的账号
+    ///
+    /// 等待时间相同时按 `BackoffConfig::tie_break` 决定：默认按输入顺序，
+    /// `TieBreak::Hashed` 时按 (账号 ID, 选择轮次) 的哈希选择，避免总是命中第一个账号。
+    pub fn least_limited<'a>(
+        &self,
+        candidates: &'a [AccountKey],
+        model: Option<&str>,
+    ) -> Option<(&'a AccountKey, u64)> {
+        let waits = candidates.iter().map(|account| {
+            let wait = self.remaining_wait_secs(&account.tracker_id(), model);
+            (account, wait)
+        });
+
+        match self.backoff_config.read().tie_break {
+            TieBreak::InputOrder => waits.min_by_key(|(_, wait)| *wait),
+            TieBreak::Hashed => {
+                let round = self.selection_round.fetch_add(1, Ordering::Relaxed);
+                waits.min_by_key(|(account, wait)| {
+                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                    account.tracker_id().hash(&mut hasher);
+                    round.hash(&mut hasher);
+                    (*wait, hasher.finish())
+                })
+            }
+        }
+    }
+
+    /// 候选账号是否全部被锁定，是则返回其中最短的等待秒数
+    ///
+    /// 供乐观重置决策使用: 全部锁定且最短等待低于阈值时，调用方可以 `clear_all`
+    /// 而不是干等。存在可用账号或候选为空时返回 None。
+    #[allow(dead_code)]
+    pub fn all_locked(&self, candidates: &[AccountKey], model: Option<&str>) -> Option<u64> {
+        let min_wait = candidates
+            .iter()
+            .map(|account| self.remaining_wait_secs(&account.tracker_id(), model))
+            .min()?;
+        if min_wait == 0 {
+            return None;
+        }
+        tracing::warn!(
+            "候选账号 ({} 个) 已全部被限流，最短等待 {} 秒",
+            candidates.len(),
+            min_wait
+        );
+        Some(min_wait)
+    }
+
+    /// 在所有限流记录中找出最快解除的一条，返回其 Key 与剩余秒数
+    ///
+    /// 用于"下一个账号 N 秒后可用"提示及全局休眠决策。指定 `model` 时只考虑账号级锁
+    /// 与该模型的模型级锁；已过期的记录被跳过，没有任何有效锁时返回 `None`。
+    #[allow(dead_code)]
+    pub fn soonest_reset(&self, model: Option<&str>) -> Option<(String, u64)> {
+        let now = self.now();
+        let model_suffix = model
+            .filter(|m| !m.is_empty())
+            .map(|m| format!(":{}", self.canonical_model(m)));
+        self.limits
+            .iter()
+            .filter(|entry| match &model_suffix {
+                Some(suffix) => !entry.key().contains(':') || entry.key().ends_with(suffix),
+                None => true,
+            })
+            .filter_map(|entry| {
+                let wait = entry.reset_time.duration_since(now).ok()?.as_secs();
+                (wait > 0).then(|| (entry.key().clone(), wait))
+            })
+            .min_by_key(|(_, wait)| *wait)
+    }
+
+    /// 统计所有被锁定账号的剩余等待时间 (最小/最大/平均/中位数)
+    ///
+    /// 一次遍历 `limits`；同一账号有多个锁 (账号级与模型级) 时取最晚到期的一个，
+    /// 即账号完全解锁前的等待时间。没有生效中的锁时各项为 0。
+    #[allow(dead_code)]
+    pub fn get_statistics(&self) -> AccountStatistics {
+        let now = self.now();
+        let mut per_account: HashMap<String, u64> = HashMap::new();
+        for entry in self.limits.iter() {
+            let Some(wait) = entry
+                .reset_time
+                .duration_since(now)
+                .ok()
+                .map(|d| d.as_secs())
+            else {
+                continue;
+            };
+            if wait == 0 {
+                continue;
+            }
+            let account_id = entry.key().split(':').next().unwrap_or_default();
+            let slot = per_account.entry(account_id.to_string()).or_insert(0);
+            *slot = (*slot).max(wait);
+        }
+
+        let mut waits: Vec<(String, u64)> = per_account.into_iter().collect();
+        if waits.is_empty() {
+            return AccountStatistics::default();
+        }
+        // 等待时间相同时按账号 ID 排序，保证结果稳定
+        waits.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+        let n = waits.len();
+        let mean = waits.iter().map(|(_, w)| *w as f64).sum::<f64>() / n as f64;
+        // 奇数个时两个下标相同
+        let median = (waits[(n - 1) / 2].1 + waits[n / 2].1) as f64 / 2.0;
+        AccountStatistics {
+            locked_count: n,
+            min_remaining_secs: waits[0].1,
+            max_remaining_secs: waits[n - 1].1,
+            mean_remaining_secs: mean,
+            median_remaining_secs: median,
+            min_account_id: Some(waits[0].0.clone()),
+            max_account_id: Some(waits[n - 1].0.clone()),
+        }
+    }
+
+    /// 标记账号请求成功，重置连续失败计数
+    ///
+    /// 当账号成功完成请求后调用此方法，将其失败计数归零，
+    /// 这样下次失败时会从最短的锁定时间（60秒）开始。
+    pub fn mark_success(&self, account: &AccountKey) {
+        let account_id = &account.tracker_id();
+        let deescalation = self.backoff_config.read().deescalation_steps;
+        if deescalation == 0 {
+            if self.failure_counts.remove(account_id).is_some() {
+                tracing::debug!(account_id = %account_id, "请求成功，已重置失败计数");
+            }
+        } else {
+            // 非对称退避: 成功只回退若干档，不完全信任短暂恢复
+            let removed = self
+                .failure_counts
+                .remove_if_mut(account_id, |_, entry| {
+                    entry.0 = entry.0.saturating_sub(deescalation);
+                    entry.0 == 0
+                })
+                .is_some();
+            if removed {
+                tracing::debug!(account_id = %account_id, "请求成功，失败计数已回退至 0");
+            }
+        }
+        self.overload_counts.remove(account_id);
+        self.server_error_counts.remove(account_id);
+        if !self.model_request_stats.is_empty() {
+            let prefix = format!("{}:", account_id);
+            self.model_request_stats
+                .retain(|key, _| !key.starts_with(&prefix));
+        }
+        // 半开窗口内探测成功，账号完全放开
+        if self.probe_gates.remove(account_id).is_some() {
+            tracing::debug!(account_id = %account_id, "探测成功，退出半开状态");
+        }
+        // 清除账号级限流
+        if let Some((key, _)) = self.limits.remove(account_id) {
+            self.notify_unlocked([&key]);
+        }
+        // 只自动解除 mark_dead 的失效标记；人工隔离与永久停用必须由人工解除
+        if self.is_dead(account) {
+            if self.backoff_config.read().auto_revive_on_success {
+                self.quarantined
+                    .remove_if(account_id, |_, q| q.reason == DEAD_TAG);
+                tracing::info!(account_id = %account_id, "请求成功，已自动解除失效标记");
+            } else {
+                tracing::debug!(account_id = %account_id, "请求成功，但失效标记需手动解除");
+            }
+        }
+        // 注意：我们暂时无法清除该账号下的所有模型级锁，因为我们不知道哪些模型被锁了
+        // 除非遍历 limits。考虑到模型级锁通常是 QuotaExhausted，让其自然过期也是可以接受的。
+        // 或者我们可以引入索引，但为了简单，暂时只清除 Account 级锁。
+    }
+
+    /// 标记账号在某个模型上请求成功，仅清除该模型级锁
+    ///
+    /// 与 `mark_success` 不同，账号级锁和账号级失败计数保持不变，
+    /// 适用于模型 A 被限流但模型 B 请求成功的场景。
+    #[allow(dead_code)]
+    pub fn mark_partial_success(&self, account: &AccountKey, model: &str) {
+        let account_id = &account.tracker_id();
+        let key = self.limit_key(account_id, Some(model));
+        if key == *account_id {
+            return;
+        }
+        self.failure_counts.remove(&key);
+        self.model_request_stats.remove(&key);
+        if self.limits.remove(&key).is_some() {
+            self.notify_unlocked([&key]);
+            tracing::debug!(
+                account_id = %account_id,
+                model,
+                "模型请求成功，已清除模型级锁"
+            );
+        }
+    }
+
+    /// 手动延长已有的锁 (如运维人员预知故障会持续更久)
+    ///
+    /// 在原 `reset_time` 上增加 `extra` 并相应更新 `retry_after_sec`，不受最长锁定时长限制。
+    /// 不存在生效中的锁时返回 `false`，调用方可改用 `set_lockout_until`。
+    #[allow(dead_code)]
+    pub fn extend_lockout(
+        &self,
+        account: &AccountKey,
+        model: Option<&str>,
+        extra: Duration,
+    ) -> bool {
+        let account_id = &account.tracker_id();
+        let key = self.limit_key(account_id, model);
+        let Some(mut info) = self.limits.get_mut(&key) else {
+            return false;
+        };
+        if info.reset_time <= self.now() {
+            return false;
+        }
+        info.reset_time += extra;
+        info.retry_after_sec = info.retry_after_sec.saturating_add(extra.as_secs());
+        tracing::info!(
+            account_id = %account_id,
+            model,
+            extra_secs = extra.as_secs(),
+            retry_sec = info.retry_after_sec,
+            "手动延长锁定"
+        );
+        let reset_time = info.reset_time;
+        drop(info); // 释放分片写锁后再广播
+        self.notify_update(&key, reset_time, RateLimitUpdateKind::Locked);
+        true
+    }
+
+    /// 精确锁定账号到指定时间点
+    ///
+    /// 使用账号配额中的 reset_time 来精确锁定账号,
+    /// 这比指数退避更加精准。
+    ///
+    /// # 参数
+    /// - `model`: 可选的模型名称,用于模型级别限流。None 表示账号级别限流
+    ///
+    /// # 返回值
+    /// 锁是新建、延长，还是因已有更晚到期的锁而被忽略
+    pub fn set_lockout_until(
+        &self,
+        account: &AccountKey,
+        reset_time: SystemTime,
+        reason: RateLimitReason,
+        model: Option<String>,
+    ) -> LockOutcome {
+        self.lock_until(&account.tracker_id(), reset_time, reason, model)
+    }
+
+    fn lock_until(
+        &self,
+        account_id: &str,
+        reset_time: SystemTime,
+        reason: RateLimitReason,
+        model: Option<String>,
+    ) -> LockOutcome {
+        let now = self.now();
+        let retry_sec = reset_time
+            .duration_since(now)
+            .map(|d| d.as_secs())
+            .unwrap_or(60); // 如果时间已过,使用默认 60 秒
+
+        // [NEW] 对异常遥远的刷新时间同样应用最长锁定时长上限
+        let clamped_sec = self.clamp_lockout(account_id, retry_sec);
+        let reset_time = if clamped_sec < retry_sec {
+            now + Duration::from_secs(clamped_sec)
+        } else {
+            reset_time
+        };
+        let retry_sec = clamped_sec;
+
+        let info = RateLimitInfo {
+            reset_time,
+            retry_after_sec: retry_sec,
+            detected_at: now,
+            reason,
+            model: model.clone(), // 🆕 支持模型级别限流
+            quota_value: None,
+            limit_value: None,
+            quota_metric: None,
+            subreason: None,
+            quota_id: None,
+        };
+
+        let key = self.limit_key(account_id, model.as_deref());
+        let outcome = self.store_lock(key, account_id, info);
+        if outcome == LockOutcome::Ignored {
+            return outcome;
+        }
+
+        tracing::info!(
+            account_id = %account_id,
+            model = model.as_deref().unwrap_or(""),
+            reason = ?reason,
+            retry_sec,
+            "已精确锁定到配额刷新时间"
+        );
+        outcome
+    }
+
+    /// 使用 ISO 8601 时间字符串精确锁定账号
+    ///
+    /// 解析类似 "2026-01-08T17:00:00Z" 格式的时间字符串，
+    /// 也接受 Unix 时间戳字符串 (如 "1735689600")
+    ///
+    /// # 参数
+    /// - `model`: 可选的模型名称,用于模型级别限流
+    pub fn set_lockout_until_iso(
+        &self,
+        account: &AccountKey,
+        reset_time_str: &str,
+        reason: RateLimitReason,
+        model: Option<String>,
+    ) -> bool {
+        let account_id = &account.tracker_id();
+        // 尝试解析 ISO 8601 格式，失败时尝试 Unix 时间戳字符串 (如 "1735689600")
+        let timestamp = match chrono::DateTime::parse_from_rfc3339(reset_time_str) {
+            Ok(dt) => dt.timestamp(),
+            Err(e) => match reset_time_str.trim().parse::<i64>() {
+                Ok(ts) => ts,
+                Err(_) => {
+                    tracing::warn!(
+                        account_id = %account_id,
+                        reset_time = reset_time_str,
+                        error = %e,
+                        "无法解析配额刷新时间，将使用默认退避策略"
+                    );
+                    return false;
+                }
+            },
+        };
+
+        // 早于 1970 或超出 SystemTime 表示范围的时间直接拒绝，避免溢出 panic；
+        // 遥远但合法的时间 (如 9999 年) 由 set_lockout_until 截断到 max_lockout_duration
+        let Some(reset_time) = u64::try_from(timestamp)
+            .ok()
+            .and_then(|ts| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(ts)))
+        else {
+            tracing::warn!(
+                account_id = %account_id,
+                reset_time = reset_time_str,
+                "配额刷新时间超出合理范围，忽略，将使用默认退避策略"
+            );
+            return false;
+        };
+
+        self.lock_until(account_id, reset_time, reason, model);
+        true
+    }
+
+    /// 从错误响应解析限流信息
+    ///
+    /// # Arguments
+    /// * `account` - 账号 (`AccountKey::global` 兼容原有的纯账号 ID)
+    /// * `status` - HTTP 状态码
+    /// * `retry_after_header` - Retry-After header 值 (秒数或 HTTP-date)
+    /// * `body` - 错误响应 body
+    ///
+    /// # Tracing
+    /// 解析过程在 `rate_limit.parse` span 内执行 (字段: account_id, model, status, reason)，
+    /// 父节点为调用处的当前 span。异步 handler 应在请求自身的 span 内调用
+    /// (例如对请求 future 使用 `tracing::Instrument::instrument`)，
+    /// 这样接入 tracing-opentelemetry 后限流决策会挂在触发它的请求下。
+    pub fn parse_from_error(
+        &self,
+        account: &AccountKey,
+        status: u16,
+        retry_after_header: Option<&str>,
+        body: &str,
+        model: Option<String>,
+        backoff_steps: &[u64], // [NEW] 传入退避配置
+    ) -> Option<RateLimitInfo> {
+        self.parse_from_error_with_outcome(
+            account,
+            status,
+            retry_after_header,
+            body,
+            model,
+            backoff_steps,
+        )
+        .map(|(info, _)| info)
+    }
+
+    /// 同 `parse_from_error`，body 为解压后的原始字节
+    ///
+    /// 有损转换为 UTF-8 后去掉 BOM 与首尾的 NUL 填充，避免 JSON 解析静默失败导致分类错误。
+    #[allow(dead_code)]
+    pub fn parse_from_error_bytes(
+        &self,
+        account: &AccountKey,
+        status: u16,
+        retry_after_header: Option<&str>,
+        body: &[u8],
+        model: Option<String>,
+        backoff_steps: &[u64],
+    ) -> Option<RateLimitInfo> {
+        let body = String::from_utf8_lossy(body);
+        let body = body.trim_start_matches('\u{feff}').trim_matches('\0');
+        self.parse_from_error(
+            account,
+            status,
+            retry_after_header,
+            body,
+            model,
+            backoff_steps,
+        )
+    }
+
+    /// 直接从上游错误响应解析限流信息
+    ///
+    /// 内部读取状态码、Retry-After 与 body 并消费响应，
+    /// 避免调用方手动提取 (或在读取 body 前就丢弃了响应)。
+    /// 429 带有 Anthropic 的 reset 时间戳时直接锁定到该时间，不再解析 body。
+    #[allow(dead_code)]
+    pub async fn parse_from_error_response(
+        &self,
+        account: &AccountKey,
+        response: rquest::Response,
+        model: Option<String>,
+        backoff_steps: &[u64],
+    ) -> Option<RateLimitInfo> {
+        let account_id = &account.tracker_id();
+        let status = response.status().as_u16();
+        if let Some(info) = self.lock_from_anthropic_headers(account_id, status, response.headers())
+        {
+            return Some(info);
+        }
+        let signals = HeaderSignals {
+            retry_sec: response
+                .headers()
+                .get("Retry-After")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|v| self.parse_retry_after_value(v)),
+            quota_id: HeaderSignals::quota_id_from(response.headers()),
+        };
+        let body = response
+            .text()
+            .await
+            .unwrap_or_else(|_| format!("HTTP {}", status));
+
+        self.apply_error(account_id, status, signals, &body, model, backoff_steps)
+            .map(|(info, _)| info)
+    }
+
+    /// 同 `parse_from_error`，额外返回锁是新建、延长还是被忽略
+    ///
+    /// 返回的 `RateLimitInfo` 始终是本次解析出的结果；
+    /// 为 `Ignored` 时已有的更晚到期的锁保持不变。
+    pub fn parse_from_error_with_outcome(
+        &self,
+        account: &AccountKey,
+        status: u16,
+        retry_after_header: Option<&str>,
+        body: &str,
+        model: Option<String>,
+        backoff_steps: &[u64],
+    ) -> Option<(RateLimitInfo, LockOutcome)> {
+        self.apply_error(
+            &account.tracker_id(),
+            status,
+            HeaderSignals {
+                retry_sec: retry_after_header.and_then(|v| self.parse_retry_after_value(v)),
+                quota_id: None,
+            },
+            body,
+            model,
+            backoff_steps,
+        )
+    }
+
+    /// 一次性传入完整的错误响应信息，按 响应头 -> body -> 默认退避 的顺序确定锁定时长
+    ///
+    /// 429 带有 Anthropic 的 reset 时间戳时直接锁定到该时间；否则响应头依次尝试
+    /// Retry-After (秒数或 HTTP-date) 与 x-ratelimit-* / ratelimit-*，避免调用方漏传某个信号来源。
+    #[allow(dead_code)]
+    pub fn record_error(&self, ctx: ErrorContext<'_>) -> Option<RateLimitInfo> {
+        self.observe_limit_headers(ctx.account, ctx.headers);
+        let account_id = &ctx.account.tracker_id();
+        if let Some(info) = self.lock_from_anthropic_headers(account_id, ctx.status, ctx.headers) {
+            return Some(info);
+        }
+        let signals = HeaderSignals {
+            retry_sec: ctx
+                .headers
+                .get("retry-after")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| self.parse_retry_after_value(v))
+                .or_else(|| self.ratelimit_headers_reset_secs(ctx.headers)),
+            quota_id: HeaderSignals::quota_id_from(ctx.headers),
+        };
+
+        self.apply_error(
+            account_id,
+            ctx.status,
+            signals,
+            ctx.body,
+            ctx.model,
+            ctx.backoff_steps,
+        )
+        .map(|(info, _)| info)
+    }
+
+    /// 解析 Retry-After 值: delta-seconds 或 HTTP-date (已过去的时间视为 0)
+    fn parse_retry_after_value(&self, value: &str) -> Option<u64> {
+        let value = value.trim();
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(seconds);
+        }
+        let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+        let secs = (date.with_timezone(&chrono::Utc)
+            - chrono::DateTime::<chrono::Utc>::from(self.now()))
+        .num_seconds();
+        Some(secs.max(0) as u64)
+    }
+
+    fn apply_error(
+        &self,
+        account_id: &str,
+        status: u16,
+        signals: HeaderSignals,
+        body: &str,
+        model: Option<String>,
+        backoff_steps: &[u64],
+    ) -> Option<(RateLimitInfo, LockOutcome)> {
+        let span = tracing::info_span!(
+            "rate_limit.parse",
+            account_id,
+            model = model.as_deref(),
+            status,
+            reason = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+
+        let Some((reason, retry_sec, source)) = self.resolve_lockout(
+            account_id,
+            model.as_deref(),
+            status,
+            signals.retry_sec,
+            body,
+            backoff_steps,
+            false,
+        ) else {
+            self.throttle_counter.record_at(self.now(), false);
+            return None;
+        };
+        self.throttle_counter.record_at(self.now(), true);
+        span.record("reason", tracing::field::debug(reason));
+
+        let max_failure_count = self.policy_value(account_id, |c| c.max_failure_count);
+        if max_failure_count > 0 && self.consecutive_failures(account_id) > max_failure_count {
+            self.record_event(account_id, status, reason, retry_sec, source, None);
+            return Some(self.disable_permanently(account_id, model));
+        }
+
+        let quota = self.parse_quota_context_from_body(body);
+        let subreason = match reason {
+            RateLimitReason::RateLimitExceeded => Self::parse_rate_limit_subreason(body),
+            _ => None,
+        };
+
+        let info = RateLimitInfo {
+            reset_time: self.now() + Duration::from_secs(retry_sec),
+            retry_after_sec: retry_sec,
+            detected_at: self.now(),
+            reason,
+            model: model.clone(),
+            quota_value: quota.quota_value,
+            limit_value: quota.limit_value,
+            quota_metric: quota.quota_metric,
+            subreason,
+            quota_id: signals.quota_id,
+        };
+
+        // [FIX] 使用复合 Key 存储 (如果是 Quota 且有 Model)
+        // 只有 QuotaExhausted 适合做模型隔离，其他如 RateLimitExceeded 通常是全账号的 TPM
+        // ModelNotFound 同样只影响该模型，轮换时同一模型可以在其他账号上重试
+        let use_model_key = matches!(
+            reason,
+            RateLimitReason::QuotaExhausted | RateLimitReason::ModelNotFound
+        ) && model.is_some();
+        let key = if use_model_key {
+            self.limit_key(account_id, model.as_deref())
+        } else {
+            // 其他情况（如 RateLimitExceeded, ServerError）通常影响整个账号
+            // 或者我们也可以根据配置决定是否隔离。
+            // 简单起见，只有 QuotaExhausted 做细粒度隔离。
+            account_id.to_string()
+        };
+
+        // Retry-After: 0 且开启 honor_zero_retry_after: 建议立即重试同一账号，不加锁
+        if retry_sec == 0 {
+            tracing::debug!(
+                account_id = %account_id,
+                status,
+                "上游返回 Retry-After: 0，立即重试"
+            );
+            self.record_event(account_id, status, reason, retry_sec, source, None);
+            return Some((info, LockOutcome::Ignored));
+        }
+
+        let (outcome, previous_reason) = self.replace_lock(key, account_id, info.clone());
+        self.record_event(
+            account_id,
+            status,
+            reason,
+            retry_sec,
+            source,
+            previous_reason,
+        );
+
+        // `structured-logging` feature 下以带命名字段的结构化事件代替人类可读的汇总日志，
+        // 便于 Loki / Datadog 等日志聚合工具解析
+        #[cfg(feature = "structured-logging")]
+        self.log_rate_limit_event(account_id, &info, status);
+        #[cfg(not(feature = "structured-logging"))]
+        tracing::warn!(
+            account_id = %account_id,
+            status,
+            reason = ?reason,
+            retry_sec,
+            "账号已限流"
+        );
+
+        Some((info, outcome))
+    }
+
+    /// 输出一条结构化的限流事件 (固定 target 与字段名，供日志聚合使用)
+    #[cfg(feature = "structured-logging")]
+    fn log_rate_limit_event(&self, account_id: &str, info: &RateLimitInfo, status_code: u16) {
+        tracing::warn!(
+            target: "rate_limit_event",
+            account_id,
+            model = info.model.as_deref().unwrap_or(""),
+            reason = ?info.reason,
+            retry_sec = info.retry_after_sec,
+            failure_count = self.consecutive_failures(account_id),
+            status_code,
+            "rate limit applied"
+        );
+    }
+
+    /// 试算: 返回这个响应会导致的限流原因与锁定秒数，不修改任何状态
+    ///
+    /// 不写入 `limits`/`failure_counts`，退避阶梯按 "再失败一次" 推算。
+    /// `model` 与 `parse_from_error` 一致地参与分类 (如 404 是否判定为模型不存在)。
+    #[allow(dead_code)]
+    pub fn simulate_parse_from_error(
+        &self,
+        account: &AccountKey,
+        status: u16,
+        retry_after_header: Option<&str>,
+        body: &str,
+        model: Option<&str>,
+        backoff_steps: &[u64],
+    ) -> Option<(RateLimitReason, u64)> {
+        self.resolve_lockout(
+            &account.tracker_id(),
+            model,
+            status,
+            retry_after_header.and_then(|v| self.parse_retry_after_value(v)),
+            body,
+            backoff_steps,
+            true,
+        )
+        .map(|(reason, retry_sec, _)| (reason, retry_sec))
+    }
+
+    /// 不关联账号的纯分类: 返回限流原因与按 "首次失败" 计算的锁定秒数
+    ///
+    /// 与 `parse_from_error` 走同一套解析分支 (`resolve_lockout`)，但不写入任何状态，
+    /// 用于测试分类逻辑或在 UI 中预览。不会触发锁定的状态码返回 `(Unknown, None)`。
+    #[allow(dead_code)]
+    pub fn classify(
+        &self,
+        status: u16,
+        retry_after_header: Option<&str>,
+        body: &str,
+    ) -> (RateLimitReason, Option<u64>) {
+        // 空账号 ID 没有失败计数，退避按第一档计算
+        match self.resolve_lockout(
+            "",
+            None,
+            status,
+            retry_after_header.and_then(|v| self.parse_retry_after_value(v)),
+            body,
+            &[],
+            true,
+        ) {
+            Some((reason, retry_sec, _)) => (reason, Some(retry_sec)),
+            None => (RateLimitReason::Unknown, None),
+        }
+    }
+
+    /// 解析限流原因并计算锁定时长；`dry_run` 时只读取失败计数而不累加
+    #[allow(clippy::too_many_arguments)]
+    fn resolve_lockout(
+        &self,
+        account_id: &str,
+        model: Option<&str>,
+        status: u16,
+        header_retry_sec: Option<u64>,
+        body: &str,
+        backoff_steps: &[u64],
+        dry_run: bool,
+    ) -> Option<(RateLimitReason, u64, LockoutSource)> {
+        // 自定义钩子优先，可以处理内置逻辑不认识的状态码
+        let hooked = self.run_classification_hooks(status, body);
+
+        // 支持 429 (限流) 以及 500/503/529 (后端故障软避让)
+        if hooked.is_none() && !HANDLED_STATUSES.contains(&status) {
+            return None;
+        }
+
+        let config = &self.policy_for(account_id);
+        let backoff_steps = self.quota_steps_for(account_id, config, backoff_steps);
+
+        // 1. 解析限流原因类型
+        let reason = if let Some((reason, _)) = hooked {
+            tracing::debug!(
+                account_id = %account_id,
+                status,
+                reason = ?reason,
+                "自定义分类钩子命中"
+            );
+            reason
+        } else if status == 429 {
+            tracing::warn!(account_id = %account_id, body, "Google 429 Error Body");
+            self.parse_rate_limit_reason(body)
+        } else if status == 404 {
+            if parse_404_reason(body, model) == RateLimitReason::ModelNotFound {
+                tracing::warn!(
+                    account_id = %account_id,
+                    model,
+                    "Google 404: model not found on this account, locking the model only"
+                );
+                RateLimitReason::ModelNotFound
+            } else {
+                tracing::warn!(
+                    account_id = %account_id,
+                    "Google 404: unknown endpoint or account issue, short account lockout"
+                );
+                RateLimitReason::ServerError
+            }
+        } else if status == 529 && self.parse_rate_limit_reason(body) == RateLimitReason::Overloaded
+        {
+            RateLimitReason::Overloaded
+        } else {
+            RateLimitReason::ServerError
+        };
+
+        let mut retry_after_sec = None;
+        let mut source = LockoutSource::Default;
+
+        // 2. 自定义钩子给出的秒数优先，其次从响应头 (Retry-After / x-ratelimit-*) 提取
+        if let Some(seconds) = hooked.and_then(|(_, secs)| secs) {
+            retry_after_sec = Some(seconds);
+            source = LockoutSource::Body;
+        } else if let Some(seconds) = header_retry_sec {
+            retry_after_sec = Some(seconds);
+            source = LockoutSource::Header;
+        }
+
+        // 3. 从错误消息提取 (优先尝试 JSON 解析，再试正则)
+        if retry_after_sec.is_none() {
+            retry_after_sec = self.parse_retry_time_from_body(body);
+            if retry_after_sec.is_some() {
+                source = LockoutSource::Body;
+            }
+        }
+
+        // 异常的等待时间 (上游 bug / 时钟偏差) 不可信，回退到默认退避
+        let threshold = config.implausible_retry_threshold.as_secs();
+        if let Some(secs) = retry_after_sec.filter(|s| *s > threshold) {
+            tracing::error!(
+                account_id = %account_id,
+                retry_sec = secs,
+                source = ?source,
+                threshold,
+                "上游返回的等待时间超过合理上限，忽略并使用默认退避"
+            );
+            if !dry_run {
+                self.implausible_retry_count.fetch_add(1, Ordering::Relaxed);
+            }
+            retry_after_sec = None;
+            source = LockoutSource::Default;
+        }
+
+        // 4. 处理默认值与软避让逻辑（根据限流类型设置不同默认值）
+        let retry_sec = match retry_after_sec {
+            Some(0) if source == LockoutSource::Header && config.honor_zero_retry_after => 0,
+            // 设置安全缓冲区 (默认最小 2 秒)，防止极高频无效重试
+            Some(s) => s.max(config.min_retry_secs),
+            None => {
+                // 获取连续失败次数，用于指数退避（带自动过期逻辑）
+                // [FIX] ServerError (5xx) 不累加 failure_count，避免污染 429 的退避阶梯
+                // [NEW] Overloaded (529) 使用独立计数，同样不污染 429 的退避阶梯
+                let failure_count = match reason {
+                    // ServerError (5xx) 使用固定值 1，不累加，避免污染 429 的退避阶梯
+                    RateLimitReason::ServerError | RateLimitReason::ModelNotFound => 1,
+                    RateLimitReason::Overloaded if dry_run => {
+                        self.peek_failure_count(&self.overload_counts, account_id) + 1
+                    }
+                    RateLimitReason::Overloaded => {
+                        self.bump_failure_count(&self.overload_counts, account_id, 1)
+                    }
+                    _ if dry_run => {
+                        self.peek_failure_count(&self.failure_counts, account_id)
+                            + config.escalation_steps.max(1)
+                    }
+                    // 这里我们使用 account_id 作为 key，不区分模型，
+                    // 因为这里是为了计算连续"账号级"问题的退避。
+                    // 如果需要针对模型的连续失败计数，可能需要改变 failure_counts 的 key。
+                    // 暂时保持 account_id，这样如果一个模型一直挂，也会增加计数，符合逻辑。
+                    _ => self.bump_failure_count(
+                        &self.failure_counts,
+                        account_id,
+                        config.escalation_steps.max(1),
+                    ),
+                };
+
+                match reason {
+                    RateLimitReason::QuotaExhausted => {
+                        if let Some(lockout) = self.daily_reset_lockout(config) {
+                            // 配置了每日刷新时间: 直接锁到刷新点
+                            tracing::warn!(
+                                account_id = %account_id,
+                                reason = ?reason,
+                                retry_sec = lockout,
+                                "检测到配额耗尽 (QUOTA_EXHAUSTED)，锁定至每日配额刷新"
+                            );
+                            lockout
+                        } else {
+                            // 调用方未传阶梯时使用配置中的 QuotaExhausted 阶梯
+                            let backoff_steps = if backoff_steps.is_empty() {
+                                config.ladders.quota_exhausted.as_slice()
+                            } else {
+                                backoff_steps
+                            };
+                            // [智能限流] 根据 failure_count 和配置的 backoff_steps 计算
+                            // 整体失败率很低的账号不升级，偶发的连续失败不代表账号有问题
+                            let index = if self.failure_rate_below_escalation(
+                                account_id,
+                                config.min_failure_rate_for_escalation,
+                            ) {
+                                0
+                            } else {
+                                (failure_count as usize).saturating_sub(1)
+                            };
+                            let lockout = if index < backoff_steps.len() {
+                                backoff_steps[index]
+                            } else {
+                                *backoff_steps.last().unwrap_or(&7200)
+                            };
+                            let lockout =
+                                Self::apply_jitter(config, lockout, backoff_steps.get(index + 1));
+
+                            tracing::warn!(
+                                account_id = %account_id,
+                                reason = ?reason,
+                                failure_count,
+                                retry_sec = lockout,
+                                "检测到配额耗尽 (QUOTA_EXHAUSTED)，根据配置锁定"
+                            );
+                            lockout
+                        }
+                    }
+                    RateLimitReason::RateLimitExceeded => {
+                        // 速率限制 (TPM/RPM): TPM 恢复较慢，默认多等几秒
+                        let subreason = Self::parse_rate_limit_subreason(body);
+                        let lockout = {
+                            let ladders = &config.ladders;
+                            match subreason {
+                                Some(RateLimitSubreason::TokensPerMinute) => ladder_step(
+                                    &ladders.tokens_per_minute,
+                                    failure_count,
+                                    TPM_LOCKOUT_SECS,
+                                ),
+                                _ => ladder_step(
+                                    &ladders.rate_limit_exceeded,
+                                    failure_count,
+                                    RPM_LOCKOUT_SECS,
+                                ),
+                            }
+                        };
+                        tracing::debug!(
+                            account_id = %account_id,
+                            reason = ?reason,
+                            subreason = ?subreason,
+                            retry_sec = lockout,
+                            "检测到速率限制 (RATE_LIMIT_EXCEEDED)，使用默认值"
+                        );
+                        lockout
+                    }
+                    RateLimitReason::ModelCapacityExhausted => {
+                        // 模型容量耗尽
+                        let lockout = ladder_step(
+                            &config.ladders.model_capacity_exhausted,
+                            failure_count,
+                            15,
+                        );
+                        tracing::warn!(
+                            account_id = %account_id,
+                            reason = ?reason,
+                            failure_count,
+                            retry_sec = lockout,
+                            "检测到模型容量不足 (MODEL_CAPACITY_EXHAUSTED)"
+                        );
+                        lockout
+                    }
+                    RateLimitReason::Overloaded => {
+                        // 上游过载通常很快恢复，使用短时递增退避
+                        let lockout = ladder_step(&config.ladders.overloaded, failure_count, 10);
+                        tracing::warn!(
+                            account_id = %account_id,
+                            reason = ?reason,
+                            failure_count,
+                            retry_sec = lockout,
+                            "检测到上游过载 (overloaded)"
+                        );
+                        lockout
+                    }
+                    RateLimitReason::ServerError => {
+                        // 连续 5xx 单独计数，不影响 429 的退避阶梯
+                        let consecutive = if dry_run {
+                            self.peek_failure_count(&self.server_error_counts, account_id) + 1
+                        } else {
+                            self.bump_failure_count(&self.server_error_counts, account_id, 1)
+                        };
+                        let lockout = {
+                            let base = config
+                                .server_error_lockout_secs
+                                .get(&status)
+                                .copied()
+                                .unwrap_or(config.default_server_error_lockout_secs);
+                            Self::escalate_server_error_lockout(
+                                base,
+                                consecutive,
+                                config.server_error_escalation_after,
+                                config.server_error_max_lockout_secs,
+                            )
+                        };
+                        tracing::warn!(
+                            account_id = %account_id,
+                            status,
+                            retry_sec = lockout,
+                            "检测到服务端错误，执行软避让"
+                        );
+                        lockout
+                    }
+                    RateLimitReason::ModelNotFound => {
+                        // 模型不存在: 只锁该模型，锁定较久以免反复请求坏模型，不做升级
+                        let lockout = config.model_not_found_lockout_secs;
+                        tracing::debug!(
+                            account_id = %account_id,
+                            model,
+                            retry_sec = lockout,
+                            "模型不存在，执行模型级软避让"
+                        );
+                        lockout
+                    }
+                    RateLimitReason::Unknown | RateLimitReason::PermanentlyDisabled => {
+                        // 未知原因
+                        let lockout = ladder_step(
+                            &config.ladders.unknown,
+                            failure_count,
+                            UNKNOWN_LOCKOUT_SECS,
+                        );
+                        tracing::debug!(
+                            account_id = %account_id,
+                            retry_sec = lockout,
+                            "无法解析 429 限流原因，使用默认值"
+                        );
+                        lockout
+                    }
+                }
+            }
+        };
+
+        // [NEW] 最长锁定时长上限，防止失控的退避把账号锁死
+        let retry_sec = self.clamp_lockout(account_id, retry_sec);
+
+        Some((reason, retry_sec, source))
+    }
+
+    /// 连续 5xx 超过 `after` 次后，软避让时长每次翻倍，不超过 `cap` (也不低于 base)
+    fn escalate_server_error_lockout(base: u64, consecutive: u32, after: u32, cap: u64) -> u64 {
+        if after == 0 || consecutive <= after {
+            return base;
+        }
+        let doublings = (consecutive - after).min(63);
+        base.saturating_mul(1u64 << doublings).min(cap.max(base))
+    }
+
+    /// 获取账号当前有效的连续失败次数 (已过期的计数视为 0)
+    pub fn failure_count(&self, account: &AccountKey) -> u32 {
+        self.consecutive_failures(&account.tracker_id())
+    }
+
+    fn consecutive_failures(&self, account_id: &str) -> u32 {
+        self.peek_failure_count(&self.failure_counts, account_id)
+    }
+
+    /// 仅清除账号的连续失败计数，不解除已有的锁定
+    pub fn reset_failure_count(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        self.failure_counts.remove(account_id).is_some()
+    }
+
+    /// 读取未过期的失败计数，不做修改
+    fn peek_failure_count(
+        &self,
+        counts: &DashMap<String, (u32, SystemTime)>,
+        account_id: &str,
+    ) -> u32 {
+        counts
+            .get(account_id)
+            .filter(|entry| {
+                self.now()
+                    .duration_since(entry.1)
+                    .map(|d| d.as_secs() <= FAILURE_COUNT_EXPIRY_SECONDS)
+                    .unwrap_or(true)
+            })
+            .map(|entry| entry.0)
+            .unwrap_or(0)
+    }
+
+    /// 账号当前的有效并发上限: base / (1 + failures)，至少为 1
+    ///
+    /// 账号开始出错时先收紧并发而不是直接锁定，成功后 (`mark_success`) 恢复。
+    #[allow(dead_code)]
+    pub fn effective_concurrency_cap(&self, account: &AccountKey) -> usize {
+        let account_id = &account.tracker_id();
+        let base = self.backoff_config.read().base_concurrency;
+        let failures = self.consecutive_failures(account_id) as usize;
+        (base / (1 + failures)).max(1)
+    }
+
+    /// 按账号的有效并发上限 (及模型上限) 获取一个并发许可，已满时返回 None
+    #[allow(dead_code)]
+    pub fn try_acquire_slot(
+        &self,
+        account: &AccountKey,
+        model: Option<&str>,
+    ) -> Option<ConcurrencyPermit> {
+        let cap = self.effective_concurrency_cap(account);
+        self.concurrency
+            .try_acquire(&account.tracker_id(), model, cap)
+    }
+
+    /// 距下一个每日配额刷新时间点的秒数 (未配置 `daily_reset_time` 时为 None)
+    fn daily_reset_lockout(&self, config: &BackoffConfig) -> Option<u64> {
+        let reset_time = config.daily_reset_time?;
+        secs_until_daily_reset(
+            chrono::DateTime::<chrono::Utc>::from(self.now()),
+            reset_time,
+            config.daily_reset_utc_offset_secs,
+        )
+    }
+
+    /// 累加指定账号的连续失败计数并返回新值 (超过过期时间未失败则从 0 重新计数)
+    fn bump_failure_count(
+        &self,
+        counts: &DashMap<String, (u32, SystemTime)>,
+        account_id: &str,
+        step: u32,
+    ) -> u32 {
+        let now = self.now();
+        let mut entry = counts.entry(account_id.to_string()).or_insert((0, now));
+
+        let elapsed = now
+            .duration_since(entry.1)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs();
+        if elapsed > FAILURE_COUNT_EXPIRY_SECONDS {
+            tracing::debug!(
+                "账号 {} 失败计数已过期（{}秒），重置为 0",
+                account_id,
+                elapsed
+            );
+            *entry = (0, now);
+        }
+        entry.0 = entry.0.saturating_add(step);
+        entry.1 = now;
+        entry.0
+    }
+
+    /// 将锁定时长截断到账号生效策略的最长锁定时长
+    fn clamp_lockout(&self, account_id: &str, retry_sec: u64) -> u64 {
+        let max_lockout = self
+            .policy_value(account_id, |c| c.max_lockout_duration)
+            .as_secs();
+        if retry_sec > max_lockout {
+            tracing::warn!(
+                "账号 {} 计算出的锁定时长 {}秒 超过上限 {}秒，已截断",
+                account_id,
+                retry_sec,
+                max_lockout
+            );
+            max_lockout
+        } else {
+            retry_sec
+        }
+    }
+
+    /// 从 x-ratelimit-* (OpenAI) 与 ratelimit-* (IETF 草案) 响应头中
+    /// 取出已耗尽维度的重置秒数；多个维度都耗尽时以更晚恢复的为准
+    fn ratelimit_headers_reset_secs(&self, headers: &HeaderMap) -> Option<u64> {
+        let header_str = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim())
+        };
+
+        let exhausted = |remaining_name: &str| {
+            header_str(remaining_name).and_then(|v| v.parse::<u64>().ok()) == Some(0)
+        };
+
+        let mut resets: Vec<Option<u64>> = Vec::new();
+        for (remaining_name, reset_name) in [
+            (
+                "x-ratelimit-remaining-requests",
+                "x-ratelimit-reset-requests",
+            ),
+            ("x-ratelimit-remaining-tokens", "x-ratelimit-reset-tokens"),
+        ] {
+            if exhausted(remaining_name) {
+                resets.push(header_str(reset_name).and_then(|v| self.parse_duration_string(v)));
+            }
+        }
+        // IETF 草案: ratelimit-reset 为 delta-seconds
+        if exhausted("ratelimit-remaining") {
+            resets.push(header_str("ratelimit-reset").and_then(|v| v.parse::<u64>().ok()));
+        }
+
+        resets.into_iter().flatten().max()
+    }
+
+    /// 从 Anthropic 的 `anthropic-ratelimit-*-reset` 响应头 (RFC 3339 时间戳) 中取出恢复时间
+    ///
+    /// 对应的 remaining 头为 0 或缺失的维度才参与计算，多个维度以更晚恢复的为准；
+    /// 已过去的时间戳忽略。
+    fn anthropic_reset_time(&self, headers: &HeaderMap) -> Option<SystemTime> {
+        let header_str = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim())
+        };
+        let now = self.now();
+
+        ANTHROPIC_RESET_HEADERS
+            .iter()
+            .filter(|(remaining_name, _)| match header_str(remaining_name) {
+                Some(remaining) => remaining.parse::<u64>().ok() == Some(0),
+                None => true,
+            })
+            .filter_map(|(_, reset_name)| header_str(reset_name))
+            .filter_map(|v| chrono::DateTime::parse_from_rfc3339(v).ok())
+            .map(SystemTime::from)
+            .filter(|reset| *reset > now)
+            .max()
+    }
+
+    /// 429 响应带有 Anthropic 的 reset 时间戳时直接锁定到该时间，跳过 body 解析
+    fn lock_from_anthropic_headers(
+        &self,
+        account_id: &str,
+        status: u16,
+        headers: &HeaderMap,
+    ) -> Option<RateLimitInfo> {
+        if status != 429 {
+            return None;
+        }
+        let reset_time = self.anthropic_reset_time(headers)?;
+        // RateLimitExceeded 影响整个账号，使用账号级 Key
+        self.lock_until(
+            account_id,
+            reset_time,
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        self.lock_info(account_id)
+    }
+
+    /// 记录响应头中的配额上限，发现明显增长 (套餐升级) 时清除该账号的失败计数
+    ///
+    /// 旧套餐下累积的失败计数会让升级后的账号继续被过度惩罚。
+    /// 依次读取 `x-ratelimit-limit`、`x-ratelimit-limit-requests`、`ratelimit-limit`；
+    /// 返回是否判定为升级。
+    pub fn observe_limit_headers(&self, account: &AccountKey, headers: &HeaderMap) -> bool {
+        let account_id = &account.tracker_id();
+        let Some(limit) = [
+            "x-ratelimit-limit",
+            "x-ratelimit-limit-requests",
+            "ratelimit-limit",
+        ]
+        .iter()
+        .find_map(|name| {
+            headers
+                .get(*name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        }) else {
+            return false;
+        };
+
+        let previous = self.observed_limits.insert(account_id.to_string(), limit);
+        let upgraded = previous
+            .is_some_and(|prev| prev > 0 && limit >= prev.saturating_mul(TIER_UPGRADE_FACTOR));
+        if upgraded {
+            self.failure_counts.remove(account_id);
+            self.overload_counts.remove(account_id);
+            tracing::info!(
+                "账号 {} 配额上限从 {} 提升至 {}，判定为套餐升级，已重置失败计数",
+                account_id,
+                previous.unwrap_or_default(),
+                limit
+            );
+        }
+        upgraded
+    }
+
+    /// 从限流响应头解析限流信息
+    ///
+    /// 支持两类响应头，remaining 为 0 时使用对应的 reset 精确锁定账号 (RateLimitExceeded):
+    /// - OpenAI 风格 `x-ratelimit-{remaining,reset}-{requests,tokens}`，reset 为时长 (如 "1s", "6m0s")
+    /// - IETF 草案 `ratelimit-remaining` / `ratelimit-reset`，reset 为 delta-seconds
+    ///   (区别于部分服务商 `X-RateLimit-Reset` 使用的 epoch 时间戳，后者不在此解析)
+    ///
+    /// 响应头缺失或额度未耗尽时返回 None，调用方应回退到 `parse_from_error`。
+    #[allow(dead_code)]
+    pub fn parse_from_headers(
+        &self,
+        account: &AccountKey,
+        headers: &HeaderMap,
+        model: Option<String>,
+    ) -> Option<RateLimitInfo> {
+        let account_id = &account.tracker_id();
+        self.observe_limit_headers(account, headers);
+        let retry_after_sec = self.ratelimit_headers_reset_secs(headers)?;
+
+        // 与 parse_from_error 一致：套用 min_retry_secs 安全缓冲
+        let min_retry_secs = self.policy_value(account_id, |c| c.min_retry_secs);
+        let retry_sec = self.clamp_lockout(account_id, retry_after_sec.max(min_retry_secs));
+        let now = self.now();
+        let info = RateLimitInfo {
+            reset_time: now + Duration::from_secs(retry_sec),
+            retry_after_sec: retry_sec,
+            detected_at: now,
+            reason: RateLimitReason::RateLimitExceeded,
+            model,
+            quota_value: None,
+            limit_value: None,
+            quota_metric: None,
+            subreason: None,
+            quota_id: None,
+        };
+
+        // RateLimitExceeded 影响整个账号，使用账号级 Key
+        let (_, previous_reason) =
+            self.replace_lock(account_id.to_string(), account_id, info.clone());
+        self.record_event(
+            account_id,
+            429,
+            RateLimitReason::RateLimitExceeded,
+            retry_sec,
+            LockoutSource::Header,
+            previous_reason,
+        );
+
+        tracing::warn!(
+            "账号 {} 响应头显示额度耗尽 (ratelimit headers), 锁定 {}秒",
+            account_id,
+            retry_sec
+        );
+
+        Some(info)
+    }
+
+    /// 对退避阶梯值施加随机抖动
+    ///
+    /// 开启 `clamp_jitter` 时结果被限制在 [base, next_step) 内，
+    /// 避免抖动后的值落入下一阶梯的范围。
+    fn apply_jitter(config: &BackoffConfig, base: u64, next_step: Option<&u64>) -> u64 {
+        let (ratio, clamp) = (config.jitter_ratio, config.clamp_jitter);
+        if ratio <= 0.0 || base == 0 {
+            return base;
+        }
+
+        use rand::Rng;
+        let spread = base as f64 * ratio;
+        let offset = rand::thread_rng().gen_range(-spread..=spread);
+        let jittered = (base as f64 + offset).round().max(1.0) as u64;
+
+        if !clamp {
+            return jittered;
+        }
+        match next_step {
+            Some(&next) if next > base => jittered.clamp(base, next - 1),
+            _ => jittered.max(base),
+        }
+    }
+
+    /// 解析限流原因类型
+    fn parse_rate_limit_reason(&self, body: &str) -> RateLimitReason {
+        // 自定义映射优先
+        {
+            let rules = self.parsing_rules.read();
+            if !rules.reason_mappings.is_empty() {
+                let body_lower = body.to_lowercase();
+                if let Some((_, reason)) = rules
+                    .reason_mappings
+                    .iter()
+                    .find(|(needle, _)| body_lower.contains(needle.as_str()))
+                {
+                    return *reason;
+                }
+            }
+        }
+
+        // 尝试从 JSON 中提取 reason 字段
+        let trimmed = body.trim();
+        if trimmed.starts_with('{') || trimmed.starts_with('[') {
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
+                // 缺少 error 包装时 (如 {"message":"Too Many Requests"}) 直接读取顶层字段
+                let error = json.get("error").unwrap_or(&json);
+                // [NEW] Anthropic 过载: {"type":"error","error":{"type":"overloaded_error",...}}
+                let error_type = error
+                    .get("type")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default();
+                let error_msg = error
+                    .get("message")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default();
+                if error_type.contains("overloaded")
+                    || error_msg.to_lowercase().contains("overloaded")
+                {
+                    return RateLimitReason::Overloaded;
+                }
+
+                if let Some(reason_str) = error
+                    .get("details")
+                    .and_then(|d| d.as_array())
+                    .and_then(|a| a.get(0))
+                    .and_then(|o| o.get("reason"))
+                    .and_then(|v| v.as_str())
+                {
+                    return DETAIL_REASON_MAPPINGS
+                        .iter()
+                        .find(|(name, _)| *name == reason_str)
+                        .map(|(_, reason)| *reason)
+                        .unwrap_or(RateLimitReason::Unknown);
+                }
+                // [NEW] 尝试从 message 字段进行文本匹配（防止 missed reason）
+                if let Some(msg) = error.get("message").and_then(|v| v.as_str()) {
+                    let msg_lower = msg.to_lowercase();
+                    if msg_lower.contains("per minute") || msg_lower.contains("rate limit") {
+                        return RateLimitReason::RateLimitExceeded;
+                    }
+                }
+                // [NEW] Vertex AI 错误结构: error.status 为 gRPC 标准状态名
+                if let Some(reason) = error
+                    .get("status")
+                    .and_then(|v| v.as_str())
+                    .and_then(Self::reason_from_grpc_status)
+                {
+                    return reason;
+                }
+            }
+        }
+
+        // 如果无法从 JSON 解析，尝试从消息文本判断
+        let body_lower = body.to_lowercase();
+        // [FIX] 优先判断分钟级限制，避免将 TPM 误判为 Quota
+        if body_lower.contains("per minute")
+            || body_lower.contains("rate limit")
+            || body_lower.contains("too many requests")
+        {
+            RateLimitReason::RateLimitExceeded
+        } else if body_lower.contains("exhausted") || body_lower.contains("quota") {
+            RateLimitReason::QuotaExhausted
+        } else {
+            RateLimitReason::Unknown
+        }
+    }
+
+    /// 从错误消息中识别速率限制的细分类型 (TPM / RPM)，两者都未提及时返回 None
+    fn parse_rate_limit_subreason(body: &str) -> Option<RateLimitSubreason> {
+        if TPM_RE.is_match(body) {
+            Some(RateLimitSubreason::TokensPerMinute)
+        } else if RPM_RE.is_match(body) {
+            Some(RateLimitSubreason::RequestsPerMinute)
+        } else {
+            None
+        }
+    }
+
+    /// 将 gRPC 标准状态名映射为限流原因 (Vertex AI 的 error.status)
+    fn reason_from_grpc_status(status: &str) -> Option<RateLimitReason> {
+        match status {
+            "RESOURCE_EXHAUSTED" => Some(RateLimitReason::QuotaExhausted),
+            "UNAVAILABLE" | "INTERNAL" | "DEADLINE_EXCEEDED" => Some(RateLimitReason::ServerError),
+            _ => None,
+        }
+    }
+
+    /// 解析 ISO 8601 时长为总秒数 (小数秒向上取整)，总时长为 0 或格式不符时返回 None
+    fn parse_iso8601_duration(s: &str) -> Option<u64> {
+        let Some(caps) = ISO8601_DURATION_RE.captures(s) else {
+            tracing::warn!("[时间解析] ISO 8601 格式无效: '{}'", s);
+            return None;
+        };
+        let part = |i: usize| {
+            caps.get(i)
+                .and_then(|m| m.as_str().parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        let seconds = caps
+            .get(5)
+            .and_then(|m| m.as_str().parse::<f64>().ok())
+            .unwrap_or(0.0);
+
+        let total_seconds = part(1) * 7 * 86400
+            + part(2) * 86400
+            + part(3) * 3600
+            + part(4) * 60
+            + seconds.ceil() as u64;
+        (total_seconds > 0).then_some(total_seconds)
+    }
+
+    /// 通用时间解析函数：支持 "1d2h30m", "2h1m1s" 等所有格式组合，以及 ISO 8601 时长 ("PT1H30M")
+    fn parse_duration_string(&self, s: &str) -> Option<u64> {
+        tracing::debug!("[时间解析] 尝试解析: '{}'", s);
+
+        if s.starts_with('P') {
+            return Self::parse_iso8601_duration(s);
+        }
+
+        // 纯毫秒值 (如 "500ms") 需单独处理，否则会被下方正则的分钟分组误匹配为 "500m"
+        if let Some(ms) = s
+            .strip_suffix("ms")
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| *v > 0.0)
+        {
+            return Some((ms / 1000.0).ceil() as u64);
+        }
+
+        // 使用正则表达式提取天、小时、分钟、秒、毫秒
+        // 支持格式："1d12h", "2h1m1s", "1h30m", "5m", "30s", "500ms", "510.790006ms" 等
+        // 🔧 [FIX] 修改 ms 部分支持小数: (\d+)ms -> (\d+(?:\.\d+)?)ms
+        // [NEW] 支持天数 (d)，用于跨天的配额重置 (如月度免费额度)
+        let caps = match DURATION_RE.captures(s) {
+            Some(c) => c,
+            None => {
+                tracing::warn!("[时间解析] 正则未匹配: '{}'", s);
+                return None;
+            }
+        };
+
+        let days = caps
+            .get(1)
+            .and_then(|m| m.as_str().parse::<u64>().ok())
+            .unwrap_or(0);
+        let hours = caps
+            .get(2)
+            .and_then(|m| m.as_str().parse::<u64>().ok())
+            .unwrap_or(0);
+        let minutes = caps
+            .get(3)
+            .and_then(|m| m.as_str().parse::<u64>().ok())
+            .unwrap_or(0);
+        let seconds = caps
+            .get(4)
+            .and_then(|m| m.as_str().parse::<f64>().ok())
+            .unwrap_or(0.0);
+        // 🔧 [FIX] 毫秒也支持小数解析
+        let milliseconds = caps
+            .get(5)
+            .and_then(|m| m.as_str().parse::<f64>().ok())
+            .unwrap_or(0.0);
+
+        tracing::debug!(
+            "[时间解析] 提取结果: {}d {}h {}m {:.3}s {:.3}ms",
+            days,
+            hours,
+            minutes,
+            seconds,
+            milliseconds
+        );
+
+        // 🔧 [FIX] 计算总秒数，毫秒部分向上取整
+        let total_seconds = days * 86400
+            + hours * 3600
+            + minutes * 60
+            + seconds.ceil() as u64
+            + (milliseconds / 1000.0).ceil() as u64;
+
+        // 如果总秒数为 0，说明解析失败
+        if total_seconds == 0 {
+            tracing::warn!("[时间解析] 失败: '{}' (总秒数为0)", s);
+            None
+        } else {
+            tracing::info!(
+                "[时间解析] ✓ 成功: '{}' => {}秒 ({}d {}h {}m {:.1}s {:.1}ms)",
+                s,
+                total_seconds,
+                days,
+                hours,
+                minutes,
+                seconds,
+                milliseconds
+            );
+            Some(total_seconds)
+        }
+    }
+
+    /// 从错误消息 body 中解析重置时间
+    fn parse_retry_time_from_body(&self, body: &str) -> Option<u64> {
+        // A. 优先尝试 JSON 精准解析
+        let trimmed = body.trim();
+        if trimmed.starts_with('{') || trimmed.starts_with('[') {
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
+                // 与 parse_rate_limit_reason 一致: 缺少 error 包装时读取顶层字段，
+                // 找不到结构化字段时继续走下方的正则兜底
+                let error = json.get("error").unwrap_or(&json);
+                let details = error.get("details").and_then(|d| d.as_array());
+
+                // 0. google.rpc.RetryInfo 标准错误详情 (可出现在 details 的任意位置)，优先采用
+                if let Some(delay_str) = details
+                    .into_iter()
+                    .flatten()
+                    .find(|d| {
+                        d.get("@type")
+                            .and_then(|t| t.as_str())
+                            .is_some_and(|t| t.ends_with("google.rpc.RetryInfo"))
+                    })
+                    .and_then(|d| d.get("retryDelay"))
+                    .and_then(|v| v.as_str())
+                {
+                    tracing::debug!("[JSON解析] 找到 RetryInfo.retryDelay: '{}'", delay_str);
+                    if let Some(seconds) = self.parse_duration_string(delay_str) {
+                        return Some(seconds);
+                    }
+                }
+
+                // 1. Google 常见的 quotaResetDelay 格式 (支持所有格式："2h1m1s", "1h30m", "42s", "500ms" 等)
+                // 路径: error.details[0].metadata.quotaResetDelay
+                if let Some(delay_str) = details
+                    .and_then(|a| a.get(0))
+                    .and_then(|o| o.get("metadata")) // 添加 metadata 层级
+                    .and_then(|m| m.get("quotaResetDelay"))
+                    .and_then(|v| v.as_str())
+                {
+                    tracing::debug!("[JSON解析] 找到 quotaResetDelay: '{}'", delay_str);
+
+                    // 使用通用时间解析函数
+                    if let Some(seconds) = self.parse_duration_string(delay_str) {
+                        return Some(seconds);
+                    }
+                }
+
+                // 2. OpenAI 常见的 retry_after 字段 (数字)
+                if let Some(retry) = error.get("retry_after").and_then(|v| v.as_u64()) {
+                    return Some(retry);
+                }
+            }
+        }
+
+        // B. 正则匹配模式 (兜底)
+        // 模式 1: "Try again in 2m 30s"
+        if let Some(caps) = TRY_AGAIN_MIN_SEC_RE.captures(body) {
+            if let (Ok(m), Some(s)) = (caps[1].parse::<u64>(), Self::parse_secs_ceil(&caps[2])) {
+                return Some(m * 60 + s);
+            }
+        }
+
+        // 模式 2: "Try again in 30s" 或 "backoff for 42s"
+        // 模式 3: "quota will reset in X seconds"
+        // 模式 4: OpenAI 风格的 "Retry after (\d+) seconds"
+        // 模式 5: 括号形式 "(wait (\d+)s)"
+        for re in [
+            &*TRY_AGAIN_SEC_RE,
+            &*QUOTA_RESET_SEC_RE,
+            &*RETRY_AFTER_SEC_RE,
+            &*PAREN_WAIT_SEC_RE,
+        ] {
+            if let Some(s) = re
+                .captures(body)
+                .and_then(|caps| Self::parse_secs_ceil(&caps[1]))
+            {
+                return Some(s);
+            }
+        }
+
+        // 模式 6: 用户追加的自定义正则
+        for re in self.parsing_rules.read().retry_patterns.iter() {
+            if let Some(s) = re
+                .captures(body)
+                .and_then(|caps| caps.get(1))
+                .and_then(|m| Self::parse_secs_ceil(m.as_str()))
+            {
+                return Some(s);
+            }
+        }
+
+        None
+    }
+
+    /// 解析秒数，小数向上取整 ("1.5" -> 2)，与 `parse_duration_string` 对亚秒值的处理一致
+    fn parse_secs_ceil(value: &str) -> Option<u64> {
+        let secs = value.trim().parse::<f64>().ok()?;
+        (secs.is_finite() && secs >= 0.0).then(|| secs.ceil() as u64)
+    }
+
+    /// 追加一条等待时间正则，第一个捕获组须为秒数；在内置模式都未命中时使用
+    #[allow(dead_code)]
+    pub fn add_retry_pattern(&self, pattern: &str) -> Result<(), String> {
+        let re = Regex::new(pattern).map_err(|e| format!("无效的正则 {}: {}", pattern, e))?;
+        if re.captures_len() < 2 {
+            return Err(format!("正则 {} 缺少秒数捕获组", pattern));
+        }
+        self.parsing_rules.write().retry_patterns.push(re);
+        Ok(())
+    }
+
+    /// 追加一条响应体子串到限流原因的映射 (不区分大小写)
+    #[allow(dead_code)]
+    pub fn add_reason_mapping(&self, needle: &str, reason: RateLimitReason) {
+        self.parsing_rules
+            .write()
+            .reason_mappings
+            .push((needle.to_lowercase(), reason));
+    }
+
+    /// 列出当前生效的解析配置
+    #[allow(dead_code)]
+    pub fn describe_parsing(&self) -> ParsingConfigDescription {
+        let rules = self.parsing_rules.read();
+
+        let mut retry_patterns: Vec<String> = [
+            &*TRY_AGAIN_MIN_SEC_RE,
+            &*TRY_AGAIN_SEC_RE,
+            &*QUOTA_RESET_SEC_RE,
+            &*RETRY_AFTER_SEC_RE,
+            &*PAREN_WAIT_SEC_RE,
+        ]
+        .iter()
+        .map(|re| re.as_str().to_string())
+        .collect();
+        retry_patterns.extend(
+            rules
+                .retry_patterns
+                .iter()
+                .map(|re| re.as_str().to_string()),
+        );
+
+        let mut reason_mappings = rules.reason_mappings.clone();
+        reason_mappings.extend(
+            DETAIL_REASON_MAPPINGS
+                .iter()
+                .map(|(name, reason)| (name.to_string(), *reason)),
+        );
+
+        ParsingConfigDescription {
+            retry_patterns,
+            reason_mappings,
+            handled_statuses: HANDLED_STATUSES.to_vec(),
+        }
+    }
+
+    /// 从错误 body 的 error.details[] 中提取配额上下文 (quotaValue, limitValue, quotaMetric)
+    ///
+    /// Google 可能将其放在 detail 对象本身、其 metadata 中，
+    /// 或 QuotaFailure 的 violations[] 中，数值为字符串或数字。
+    fn parse_quota_context_from_body(&self, body: &str) -> QuotaContext {
+        let trimmed = body.trim();
+        if !trimmed.starts_with('{') {
+            return QuotaContext::default();
+        }
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) else {
+            return QuotaContext::default();
+        };
+        let Some(details) = json
+            .get("error")
+            .and_then(|e| e.get("details"))
+            .and_then(|d| d.as_array())
+        else {
+            return QuotaContext::default();
+        };
+
+        let find_field = |field: &str| {
+            details.iter().find_map(|detail| {
+                detail
+                    .get(field)
+                    .or_else(|| detail.get("metadata").and_then(|m| m.get(field)))
+                    .or_else(|| {
+                        detail
+                            .get("violations")
+                            .and_then(|v| v.as_array())
+                            .and_then(|v| v.iter().find_map(|violation| violation.get(field)))
+                    })
+            })
+        };
+        let find_value = |field: &str| {
+            find_field(field).and_then(|value| {
+                value
+                    .as_u64()
+                    .or_else(|| value.as_str().and_then(|v| v.trim().parse::<u64>().ok()))
+            })
+        };
+
+        QuotaContext {
+            quota_value: find_value("quotaValue"),
+            limit_value: find_value("limitValue"),
+            quota_metric: find_field("quotaMetric")
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string()),
+        }
+    }
+
+    /// 获取账号的限流信息
+    pub fn get(&self, account: &AccountKey) -> Option<RateLimitInfo> {
+        self.lock_info(&account.tracker_id())
+    }
+
+    fn lock_info(&self, account_id: &str) -> Option<RateLimitInfo> {
+        self.limits.get(account_id).map(|r| r.clone())
+    }
+
+    /// 检查账号是否仍在限流中
+    /// 检查账号是否仍在限流中 (支持模型级)
+    pub fn is_rate_limited(&self, account: &AccountKey, model: Option<&str>) -> bool {
+        // Checking using remaining_wait_secs which handles both global and model keys
+        self.remaining_wait_secs(&account.tracker_id(), model) > 0
+    }
+
+    /// 仅检查模型级锁 (`account_id:model`)，忽略账号级锁与隔离状态
+    ///
+    /// 与 `is_rate_limited` 不同: 后者返回账号级锁与模型级锁的"或"，
+    /// 本方法只回答"这个模型自身是否被锁"，用于区分预览模型等拥有独立配额的情况。
+    #[allow(dead_code)]
+    pub fn is_model_locked(&self, account: &AccountKey, model: &str) -> bool {
+        if model.is_empty() {
+            return false;
+        }
+        let key = self.limit_key(&account.tracker_id(), Some(model));
+        self.key_remaining_secs(&key, self.now()) > 0
+    }
+
+    /// 隔离账号 (如密钥被吊销、项目被禁用)
+    ///
+    /// 与定时锁不同，隔离不会过期，`clear_all` 也不会解除；隔离期间
+    /// `get_remaining_wait` 返回 `u64::MAX`。需调用 `unquarantine` 人工解除。
+    #[allow(dead_code)]
+    pub fn quarantine(&self, account: &AccountKey, reason: &str) {
+        self.quarantine_by_id(&account.tracker_id(), reason);
+    }
+
+    fn quarantine_by_id(&self, account_id: &str, reason: &str) {
+        self.quarantined.insert(
+            account_id.to_string(),
+            QuarantineInfo {
+                reason: reason.to_string(),
+                since: self.now(),
+            },
+        );
+        tracing::warn!("账号 {} 已被隔离: {}", account_id, reason);
+    }
+
+    /// 解除账号隔离，返回账号此前是否处于隔离状态
+    #[allow(dead_code)]
+    pub fn unquarantine(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        let removed = self.quarantined.remove(account_id).is_some();
+        if removed {
+            tracing::info!("账号 {} 已解除隔离", account_id);
+        }
+        removed
+    }
+
+    /// 账号是否处于隔离状态
+    pub fn is_quarantined(&self, account: &AccountKey) -> bool {
+        self.is_quarantined_by_id(&account.tracker_id())
+    }
+
+    fn is_quarantined_by_id(&self, account_id: &str) -> bool {
+        self.quarantined.contains_key(account_id)
+    }
+
+    /// 获取账号的隔离信息
+    #[allow(dead_code)]
+    pub fn quarantine_info(&self, account: &AccountKey) -> Option<QuarantineInfo> {
+        let account_id = &account.tracker_id();
+        self.quarantined.get(account_id).map(|q| q.clone())
+    }
+
+    /// 永久停用账号: 隔离并写入 `PermanentlyDisabled` 记录
+    fn disable_permanently(
+        &self,
+        account_id: &str,
+        model: Option<String>,
+    ) -> (RateLimitInfo, LockOutcome) {
+        let now = self.now();
+        let info = RateLimitInfo {
+            reset_time: far_future(now),
+            retry_after_sec: u64::MAX,
+            detected_at: now,
+            reason: RateLimitReason::PermanentlyDisabled,
+            model,
+            quota_value: None,
+            limit_value: None,
+            quota_metric: None,
+            subreason: None,
+            quota_id: None,
+        };
+        tracing::error!(
+            "账号 {} 连续失败 {} 次，超过上限，已永久停用",
+            account_id,
+            self.consecutive_failures(account_id)
+        );
+        self.quarantine_by_id(account_id, PERMANENTLY_DISABLED_TAG);
+        let outcome = self.store_lock(account_id.to_string(), account_id, info.clone());
+        (info, outcome)
+    }
+
+    /// 账号是否因超过 `max_failure_count` 被永久停用
+    #[allow(dead_code)]
+    pub fn is_permanently_disabled(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        self.quarantined
+            .get(account_id)
+            .is_some_and(|q| q.reason == PERMANENTLY_DISABLED_TAG)
+    }
+
+    /// 统计给定账号集合的限流健康状况
+    ///
+    /// 全部账号被锁定为 `Critical`，超过一半为 `Degraded`。模型级锁不计入锁定数。
+    /// 纯读取，不记录等级；等级变化的检测见 `check_health_transition`。
+    pub fn health_check(&self, accounts: &[AccountKey]) -> TrackerHealthReport {
+        let total_accounts = accounts.len();
+        let locked_accounts = accounts
+            .iter()
+            .filter(|account| self.remaining_wait_secs(&account.tracker_id(), None) > 0)
+            .count();
+        let permanently_disabled = accounts
+            .iter()
+            .filter(|account| self.is_permanently_disabled(account))
+            .count();
+        let high_failure_accounts = accounts
+            .iter()
+            .filter(|account| self.failure_count(account) >= HEALTH_FAILURE_THRESHOLD)
+            .count();
+
+        let status = if total_accounts > 0 && locked_accounts == total_accounts {
+            HealthStatus::Critical
+        } else if locked_accounts * 2 > total_accounts {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Ok
+        };
+
+        TrackerHealthReport {
+            total_accounts,
+            locked_accounts,
+            permanently_disabled,
+            high_failure_accounts,
+            status,
+            previous_status: None,
+        }
+    }
+
+    /// 重新计算健康等级并与上次记录的等级比较；发生变化时返回报告 (`previous_status` 为变化前的等级)
+    ///
+    /// 应在锁状态变化时调用 (见 `subscribe`)，而不是在查询路径上。
+    pub fn check_health_transition(&self, accounts: &[AccountKey]) -> Option<TrackerHealthReport> {
+        let mut report = self.health_check(accounts);
+        let previous = std::mem::replace(&mut *self.last_health_status.lock(), report.status);
+        if previous == report.status {
+            return None;
+        }
+        tracing::warn!(
+            from = ?previous,
+            to = ?report.status,
+            locked_accounts = report.locked_accounts,
+            total_accounts = report.total_accounts,
+            "限流健康等级变化"
+        );
+        report.previous_status = Some(previous);
+        Some(report)
+    }
+
+    /// 人工恢复被永久停用的账号: 解除隔离、清除停用记录与失败计数
+    ///
+    /// 返回账号此前是否处于永久停用状态。
+    #[allow(dead_code)]
+    pub fn re_enable(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        if !self.is_permanently_disabled(account) {
+            return false;
+        }
+        self.unquarantine(account);
+        if let Some((key, _)) = self.limits.remove_if(account_id, |_, info| {
+            info.reason == RateLimitReason::PermanentlyDisabled
+        }) {
+            self.notify_unlocked([&key]);
+        }
+        self.failure_counts.remove(account_id);
+        self.overload_counts.remove(account_id);
+        self.server_error_counts.remove(account_id);
+        tracing::info!("账号 {} 已人工恢复", account_id);
+        true
+    }
+
+    /// 将账号标记为失效，等同于以 "dead" 为原因隔离
+    #[allow(dead_code)]
+    pub fn mark_dead(&self, account: &AccountKey) {
+        self.quarantine(account, DEAD_TAG);
+    }
+
+    /// 账号是否被标记为失效 (其他原因的隔离不算)
+    #[allow(dead_code)]
+    pub fn is_dead(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        self.quarantined
+            .get(account_id)
+            .is_some_and(|q| q.reason == DEAD_TAG)
+    }
+
+    /// 手动解除账号的失效标记，返回是否存在该标记 (不影响其他原因的隔离)
+    #[allow(dead_code)]
+    pub fn revive(&self, account: &AccountKey) -> bool {
+        let account_id = &account.tracker_id();
+        let revived = self
+            .quarantined
+            .remove_if(account_id, |_, q| q.reason == DEAD_TAG)
+            .is_some();
+        if revived {
+            tracing::info!(account_id = %account_id, "已解除失效标记");
+        }
+        revived
+    }
+
+    /// 获取距离限流重置还有多少秒
+    pub fn get_reset_seconds(&self, account: &AccountKey) -> Option<u64> {
+        self.get(account)
+            .and_then(|info| info.time_until_reset_at(self.now()))
+            .map(|d| d.as_secs())
+    }
+
+    /// 清除过期的限流记录与过期的失败计数
+    ///
+    /// 失败计数超过 `FAILURE_COUNT_EXPIRY_SECONDS` 未更新即视为过期，
+    /// 避免长期运行时 failure_counts 随轮换账号无限增长。
+    #[allow(dead_code)]
+    pub fn cleanup_expired(&self) -> CleanupStats {
+        let now = self.now();
+        let mut stats = CleanupStats {
+            limits_removed: self.drain_expired().len(),
+            ..CleanupStats::default()
+        };
+
+        self.probe_gates.retain(|_k, gate| gate.closes_at > now);
+
+        for counts in [
+            &self.failure_counts,
+            &self.overload_counts,
+            &self.server_error_counts,
+        ] {
+            counts.retain(|_k, (_count, last_failure)| {
+                let elapsed = now
+                    .duration_since(*last_failure)
+                    .unwrap_or(Duration::from_secs(0))
+                    .as_secs();
+                if elapsed > FAILURE_COUNT_EXPIRY_SECONDS {
+                    stats.failure_counts_removed += 1;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        if stats.total() > 0 {
+            tracing::debug!(
+                "清除了 {} 个过期的限流记录, {} 个过期的失败计数",
+                stats.limits_removed,
+                stats.failure_counts_removed
+            );
+        }
+
+        stats
+    }
+
+    /// 移除并返回所有已到期的限流记录 (用于审计日志等需要具体条目的场景)
+    ///
+    /// 删除时再次检查到期时间，期间被续期的记录不会被移除。
+    pub fn drain_expired(&self) -> Vec<(String, RateLimitInfo)> {
+        let now = self.now();
+        let expired_keys: Vec<String> = self
+            .limits
+            .iter()
+            .filter(|entry| entry.value().reset_time <= now)
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let drained: Vec<(String, RateLimitInfo)> = expired_keys
+            .into_iter()
+            .filter_map(|key| {
+                self.limits
+                    .remove_if(&key, |_, info| info.reset_time <= now)
+            })
+            .collect();
+        self.notify_unlocked(drained.iter().map(|(key, _)| key));
+        drained
+    }
+
+    /// 启动后台清理任务，按固定间隔调用 `cleanup_expired` (同时清理过期限流记录与失败计数)
+    ///
+    /// 必须在 tokio 运行时中调用。注意 tokio 的 `JoinHandle` 被 drop 时任务不会停止，
+    /// 需在关闭时显式 `abort()`。
+    pub fn start_cleanup_task(self: Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let stats = self.cleanup_expired();
+                if stats.total() > 0 {
+                    tracing::info!(
+                        limits_removed = stats.limits_removed,
+                        failure_counts_removed = stats.failure_counts_removed,
+                        "Auto-cleanup: removed expired rate limit state"
+                    );
+                }
+            }
+        })
+    }
+
+    /// 合并另一个跟踪器的状态 (用于多个 worker 各自维护 tracker 的场景)
+    ///
+    /// 冲突时限流记录保留 reset_time 更晚的一方，失败计数保留次数更高的一方
+    /// (次数相同时保留更新的时间戳)。
+    #[allow(dead_code)]
+    pub fn merge_from(&mut self, other: RateLimitTracker) {
+        for (key, info) in other.limits {
+            let reset_time = info.reset_time;
+            let updated = match self.limits.entry(key.clone()) {
+                dashmap::mapref::entry::Entry::Occupied(mut existing) => {
+                    if info.reset_time > existing.get().reset_time {
+                        existing.insert(info);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                dashmap::mapref::entry::Entry::Vacant(slot) => {
+                    slot.insert(info);
+                    true
+                }
+            };
+            if updated {
+                self.notify_update(&key, reset_time, RateLimitUpdateKind::Locked);
+            }
+        }
+
+        for (key, (count, last_failure)) in other.failure_counts {
+            match self.failure_counts.entry(key) {
+                dashmap::mapref::entry::Entry::Occupied(mut existing) => {
+                    let (cur_count, cur_time) = *existing.get();
+                    if count > cur_count || (count == cur_count && last_failure > cur_time) {
+                        existing.insert((count, last_failure));
+                    }
+                }
+                dashmap::mapref::entry::Entry::Vacant(slot) => {
+                    slot.insert((count, last_failure));
+                }
+            }
+        }
+    }
+
+    /// 导出限流记录与失败计数的快照 (不含历史、统计等辅助状态)
+    #[allow(dead_code)]
+    pub fn snapshot(&self) -> TrackerSnapshot {
+        TrackerSnapshot {
+            limits: self
+                .limits
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect(),
+            failure_counts: self
+                .failure_counts
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect(),
+        }
+    }
+
+    /// 用快照整体替换限流记录与失败计数
+    ///
+    /// 与 `merge_from` 不同，快照中不存在的锁会被移除。两张表分别替换，
+    /// 期间并发写入的状态可能被覆盖。
+    #[allow(dead_code)]
+    pub fn restore(&self, snapshot: TrackerSnapshot) {
+        let removed: Vec<String> = self
+            .limits
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|key| !snapshot.limits.contains_key(key))
+            .collect();
+        for key in &removed {
+            self.limits.remove(key);
+        }
+        self.notify_unlocked(&removed);
+
+        for (key, info) in snapshot.limits {
+            let reset_time = info.reset_time;
+            self.limits.insert(key.clone(), info);
+            self.notify_update(&key, reset_time, RateLimitUpdateKind::Locked);
+        }
+
+        self.failure_counts.clear();
+        for (key, entry) in snapshot.failure_counts {
+            self.failure_counts.insert(key, entry);
+        }
+        tracing::info!(
+            locks = self.limits.len(),
+            failure_counts = self.failure_counts.len(),
+            "Restored rate limit state from snapshot"
+        );
+    }
+
+    /// 追加一条限流事件，超出 `history_capacity` 时丢弃最旧的
+    fn record_event(
+        &self,
+        account_id: &str,
+        status: u16,
+        reason: RateLimitReason,
+        retry_sec: u64,
+        source: LockoutSource,
+        previous_reason: Option<RateLimitReason>,
+    ) {
+        let capacity = self.backoff_config.read().history_capacity;
+        if capacity == 0 {
+            return;
+        }
+
+        let mut events = self.history.entry(account_id.to_string()).or_default();
+        while events.len() >= capacity {
+            events.pop_front();
+        }
+        events.push_back(RateLimitEvent {
+            timestamp: self.now(),
+            status,
+            reason,
+            retry_sec,
+            source,
+            previous_reason,
+        });
+    }
+
+    /// 设置账号备注，空字符串表示删除备注
+    #[allow(dead_code)]
+    pub fn set_note(&self, account: &AccountKey, note: String) {
+        let account_id = &account.tracker_id();
+        if note.trim().is_empty() {
+            self.notes.remove(account_id);
+        } else {
+            self.notes.insert(account_id.to_string(), note);
+        }
+    }
+
+    /// 获取账号备注
+    #[allow(dead_code)]
+    pub fn get_note(&self, account: &AccountKey) -> Option<String> {
+        let account_id = &account.tracker_id();
+        self.notes.get(account_id).map(|n| n.clone())
+    }
+
+    /// 账号状态的调试视图 (锁定状态、失败计数、隔离信息与备注)，供 UI 展示
+    #[allow(dead_code)]
+    pub fn account_debug_json(&self, account: &AccountKey) -> serde_json::Value {
+        let account_id = &account.tracker_id();
+        let lock = self.lock_info(account_id);
+        let remaining_wait_secs = self.remaining_wait_secs(account_id, None);
+        serde_json::json!({
+            "account_id": account_id,
+            "rate_limited": remaining_wait_secs > 0,
+            "remaining_wait_secs": remaining_wait_secs,
+            "reason": lock.as_ref().map(|info| info.reason),
+            "quota_id": lock.as_ref().and_then(|info| info.quota_id.clone()),
+            "failure_count": self.consecutive_failures(account_id),
+            "quarantine_reason": self.quarantine_info(account).map(|q| q.reason),
+            "note": self.get_note(account),
+        })
+    }
+
+    /// 账号最近的限流事件，按时间从旧到新
+    #[allow(dead_code)]
+    pub fn history(&self, account: &AccountKey) -> Vec<RateLimitEvent> {
+        let account_id = &account.tracker_id();
+        self.history
+            .get(account_id)
+            .map(|events| events.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// 记录一次请求结果 (成功/失败)，用于累计失败率
+    #[allow(dead_code)]
+    pub fn record_request_outcome(&self, account: &AccountKey, success: bool) {
+        let account_id = &account.tracker_id();
+        self.bump_request_stats(&self.request_stats, account_id, success);
+    }
+
+    /// 记录某个模型在账号上的一次请求结果，用于计算模型级失败率
+    #[allow(dead_code)]
+    pub fn record_model_request(&self, account: &AccountKey, model: &str, success: bool) {
+        let account_id = &account.tracker_id();
+        let key = self.limit_key(account_id, Some(model));
+        self.bump_request_stats(&self.model_request_stats, &key, success);
+    }
+
+    /// 模型在账号上的失败率 (0.0 - 1.0)，无记录时为 0.0
+    ///
+    /// 计数在 `mark_success` (该账号所有模型) 或 `mark_partial_success` (该模型) 时清零。
+    #[allow(dead_code)]
+    pub fn model_failure_rate(&self, account: &AccountKey, model: &str) -> f64 {
+        let account_id = &account.tracker_id();
+        let key = self.limit_key(account_id, Some(model));
+        Self::stats_failure_rate(&self.model_request_stats, &key)
+    }
+
+    fn bump_request_stats(&self, map: &DashMap<String, AccountStats>, key: &str, success: bool) {
+        let now = self.now();
+        let mut stats = map.entry(key.to_string()).or_insert(AccountStats {
+            success_count: 0,
+            failure_count: 0,
+            last_activity: now,
+        });
+        if success {
+            stats.success_count = stats.success_count.saturating_add(1);
+        } else {
+            stats.failure_count = stats.failure_count.saturating_add(1);
+        }
+        stats.last_activity = now;
+    }
+
+    /// 账号累计请求结果
+    #[allow(dead_code)]
+    pub fn account_stats(&self, account: &AccountKey) -> Option<AccountStats> {
+        let account_id = &account.tracker_id();
+        self.request_stats.get(account_id).map(|s| *s)
+    }
+
+    /// 账号累计失败率 (0.0 - 1.0)，无记录时为 0.0
+    pub fn failure_rate(&self, account: &AccountKey) -> f64 {
+        let account_id = &account.tracker_id();
+        Self::stats_failure_rate(&self.request_stats, account_id)
+    }
+
+    fn stats_failure_rate(map: &DashMap<String, AccountStats>, key: &str) -> f64 {
+        map.get(key)
+            .map(|s| {
+                let total = s.success_count as f64 + s.failure_count as f64;
+                if total == 0.0 {
+                    0.0
+                } else {
+                    s.failure_count as f64 / total
+                }
+            })
+            .unwrap_or(0.0)
+    }
+
+    /// 是否因整体失败率足够低而不升级退避阶梯
+    fn failure_rate_below_escalation(&self, account_id: &str, threshold: f64) -> bool {
+        if threshold <= 0.0 {
+            return false;
+        }
+        let enough_samples = self.request_stats.get(account_id).is_some_and(|s| {
+            s.success_count.saturating_add(s.failure_count) >= MIN_FAILURE_RATE_SAMPLES
+        });
+        enough_samples && Self::stats_failure_rate(&self.request_stats, account_id) < threshold
+    }
+
+    /// 因超过 `implausible_retry_threshold` 而被忽略的上游等待时间次数
+    #[allow(dead_code)]
+    pub fn implausible_retry_count(&self) -> u64 {
+        self.implausible_retry_count.load(Ordering::Relaxed)
+    }
+
+    /// 导出 StatsD (DogStatsD 标签格式) 指标行，供推送到 StatsD / Datadog agent
+    ///
+    /// 计数器 (`|c`) 为自上次调用以来的增量，调用后清零；
+    /// 仪表 (`|g`) 为当前值。
+    #[allow(dead_code)]
+    pub fn statsd_metrics(&self, prefix: &str) -> Vec<String> {
+        let mut lockouts: Vec<(String, u64)> = Vec::new();
+        for mut entry in self.pending_lockout_metrics.iter_mut() {
+            let count = std::mem::take(entry.value_mut());
+            if count > 0 {
+                lockouts.push((Self::reason_label(*entry.key()), count));
+            }
+        }
+        lockouts.sort();
+
+        let mut lines: Vec<String> = lockouts
+            .into_iter()
+            .map(|(reason, count)| {
+                format!(
+                    "{}.rate_limit.lockouts:{}|c|#reason:{}",
+                    prefix, count, reason
+                )
+            })
+            .collect();
+        lines.push(format!(
+            "{}.rate_limit.active_limits:{}|g",
+            prefix,
+            self.limits.len()
+        ));
+        lines.push(format!(
+            "{}.rate_limit.failure_counts:{}|g",
+            prefix,
+            self.failure_counts.len()
+        ));
+        lines.push(format!(
+            "{}.rate_limit.implausible_retries_total:{}|g",
+            prefix,
+            self.implausible_retry_count()
+        ));
+        lines
+    }
+
+    /// 导出 Prometheus 文本格式 (exposition format) 指标，由应用层挂到 HTTP 端点供抓取
+    ///
+    /// 标签只使用限流原因，不按账号打标签，避免基数随账号数增长。
+    #[allow(dead_code)]
+    pub fn metrics_text(&self) -> String {
+        use std::fmt::Write;
+
+        let now = self.now();
+        let mut locked: Vec<String> = self
+            .limits
+            .iter()
+            .filter(|entry| entry.reset_time > now)
+            .map(|entry| {
+                entry
+                    .key()
+                    .split(':')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect();
+        locked.sort_unstable();
+        locked.dedup();
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP antigravity_rate_limit_locked_accounts Accounts with at least one active lock"
+        );
+        let _ = writeln!(out, "# TYPE antigravity_rate_limit_locked_accounts gauge");
+        let _ = writeln!(
+            out,
+            "antigravity_rate_limit_locked_accounts {}",
+            locked.len()
+        );
+
+        let mut totals: Vec<(String, u64)> = self
+            .lockout_totals
+            .iter()
+            .map(|entry| (Self::reason_label(*entry.key()), *entry.value()))
+            .collect();
+        totals.sort();
+        let _ = writeln!(
+            out,
+            "# HELP antigravity_rate_limit_lockouts_total Lockouts created or extended, by reason"
+        );
+        let _ = writeln!(out, "# TYPE antigravity_rate_limit_lockouts_total counter");
+        for (reason, count) in totals {
+            let _ = writeln!(
+                out,
+                "antigravity_rate_limit_lockouts_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+
+        let histogram = self.lock_durations.lock();
+        let _ = writeln!(
+            out,
+            "# HELP antigravity_rate_limit_lock_duration_seconds Lock duration applied per lockout"
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE antigravity_rate_limit_lock_duration_seconds histogram"
+        );
+        let mut cumulative = 0;
+        for (bound, count) in LOCK_DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "antigravity_rate_limit_lock_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "antigravity_rate_limit_lock_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "antigravity_rate_limit_lock_duration_seconds_sum {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "antigravity_rate_limit_lock_duration_seconds_count {}",
+            histogram.count
+        );
+        out
+    }
+
+    /// 将 `metrics_text` 的指标连同按账号的 gauge 写入 `out`，供无界面部署的 Prometheus 直接抓取
+    ///
+    /// 额外导出 `rate_limit_locked_accounts_total`、`rate_limit_failure_count{account_id}` 与
+    /// `rate_limit_remaining_seconds{account_id,model}` (账号级锁的 model 为空)，
+    /// 基数随账号数增长。需启用 `prometheus` feature。
+    #[cfg(feature = "prometheus")]
+    #[allow(dead_code)]
+    pub fn export_prometheus_metrics(&self, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        out.write_all(self.metrics_text().as_bytes())?;
+
+        let now = self.now();
+        let mut remaining: Vec<(String, String, u64)> = self
+            .limits
+            .iter()
+            .filter_map(|entry| {
+                let secs = entry.reset_time.duration_since(now).ok()?.as_secs();
+                let (account_id, model) = entry
+                    .key()
+                    .split_once(':')
+                    .unwrap_or((entry.key().as_str(), ""));
+                Some((account_id.to_string(), model.to_string(), secs))
+            })
+            .collect();
+        remaining.sort();
+
+        let mut locked: Vec<&str> = remaining.iter().map(|(id, _, _)| id.as_str()).collect();
+        locked.dedup();
+        writeln!(
+            out,
+            "# HELP rate_limit_locked_accounts_total Accounts with at least one active lock"
+        )?;
+        writeln!(out, "# TYPE rate_limit_locked_accounts_total gauge")?;
+        writeln!(out, "rate_limit_locked_accounts_total {}", locked.len())?;
+
+        let mut failures: Vec<(String, u32)> = self
+            .failure_counts
+            .iter()
+            .map(|entry| entry.key().clone())
+            .map(|account_id| {
+                let count = self.consecutive_failures(&account_id);
+                (account_id, count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        failures.sort();
+        writeln!(
+            out,
+            "# HELP rate_limit_failure_count Consecutive failures per account"
+        )?;
+        writeln!(out, "# TYPE rate_limit_failure_count gauge")?;
+        for (account_id, count) in failures {
+            writeln!(
+                out,
+                "rate_limit_failure_count{{account_id=\"{}\"}} {}",
+                escape_label_value(&account_id),
+                count
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP rate_limit_remaining_seconds Seconds until a lock expires"
+        )?;
+        writeln!(out, "# TYPE rate_limit_remaining_seconds gauge")?;
+        for (account_id, model, secs) in remaining {
+            writeln!(
+                out,
+                "rate_limit_remaining_seconds{{account_id=\"{}\",model=\"{}\"}} {}",
+                escape_label_value(&account_id),
+                escape_label_value(&model),
+                secs
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 指标标签使用的原因名 (与 serde 序列化名一致，如 "quota_exhausted")
+    fn reason_label(reason: RateLimitReason) -> String {
+        serde_json::to_value(reason)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// 最近 `window` 内 `parse_from_error` 触发限流的比例 (0.0 - 1.0)
+    pub fn throttle_rate(&self, window: Duration) -> f64 {
+        self.throttle_counter.throttle_rate_at(self.now(), window)
+    }
+
+    /// 当前跟踪的限流记录数 (含账号级与模型级)
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.limits.len()
+    }
+
+    /// 当前跟踪的失败计数记录数
+    #[allow(dead_code)]
+    pub fn failure_count_len(&self) -> usize {
+        self.failure_counts.len()
+    }
+
+    /// 是否没有任何限流记录
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// 估算跟踪器当前的内存占用，用于长期运行时发现泄漏
+    #[allow(dead_code)]
+    pub fn memory_estimate(&self) -> MemoryReport {
+        let mut report = MemoryReport::default();
+
+        for entry in self.limits.iter() {
+            report.limit_entries += 1;
+            report.limits_bytes += std::mem::size_of::<String>()
+                + entry.key().len()
+                + std::mem::size_of::<RateLimitInfo>()
+                + entry.value().model.as_ref().map_or(0, |m| m.len());
+        }
+
+        for entry in self.failure_counts.iter() {
+            report.failure_count_entries += 1;
+            report.failure_counts_bytes += std::mem::size_of::<String>()
+                + entry.key().len()
+                + std::mem::size_of::<(u32, SystemTime)>();
+        }
+
+        for entry in self.history.iter() {
+            report.history_entries += entry.value().len();
+            report.history_bytes += std::mem::size_of::<String>()
+                + entry.key().len()
+                + entry.value().capacity() * std::mem::size_of::<RateLimitEvent>();
+        }
+
+        report
+    }
+
+    /// 清除指定账号的限流记录
+    pub fn clear(&self, account: &AccountKey) -> bool {
+        match self.limits.remove(&account.tracker_id()) {
+            Some((key, _)) => {
+                self.notify_unlocked([&key]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 返回当前因指定原因被锁定的账号 (含模型级锁，已去重并去掉模型后缀)
+    #[allow(dead_code)]
+    pub fn get_accounts_by_reason(&self, reason: RateLimitReason) -> Vec<String> {
+        let now = self.now();
+        let mut accounts: Vec<String> = Vec::new();
+        for entry in self.limits.iter() {
+            if entry.reason != reason || entry.reset_time <= now {
+                continue;
+            }
+            let account = entry.key().split(':').next().unwrap_or_default();
+            if !accounts.iter().any(|a| a == account) {
+                accounts.push(account.to_string());
+            }
+        }
+        accounts.sort();
+        accounts
+    }
+
+    /// 解除所有因指定原因产生的锁 (如计费问题修复后只解除 QuotaExhausted)，返回移除的记录数
+    #[allow(dead_code)]
+    pub fn unlock_by_reason(&self, reason: RateLimitReason) -> usize {
+        let mut removed_keys = Vec::new();
+        self.limits.retain(|key, info| {
+            let keep = info.reason != reason;
+            if !keep {
+                removed_keys.push(key.clone());
+            }
+            keep
+        });
+        self.notify_unlocked(&removed_keys);
+        let removed = removed_keys.len();
+        if removed > 0 {
+            tracing::info!("已解除 {} 条 {:?} 限流记录", removed, reason);
+        }
+        removed
+    }
+
+    /// 清除所有限流记录 (乐观重置策略)
+    ///
+    /// 用于乐观重置机制,当所有账号都被限流但等待时间很短时,
+    /// 清除所有限流记录以解决时序竞争条件
+    pub fn clear_all(&self) {
+        let record = self.snapshot_for_reset();
+        let count = self.limits.len();
+        if self.updates.receiver_count() > 0 {
+            let keys: Vec<String> = self.limits.iter().map(|e| e.key().clone()).collect();
+            self.limits.clear();
+            self.notify_unlocked(&keys);
+        } else {
+            self.limits.clear();
+        }
+        tracing::warn!(
+            "🔄 Optimistic reset: Cleared all {} rate limit record(s), {} account(s) locked, wait {}-{}s",
+            count,
+            record.accounts_affected,
+            record.min_wait_at_reset,
+            record.max_wait_at_reset
+        );
+        *self.last_optimistic_reset.write() = Some(record);
+        if self.backoff_config.read().clear_all_resets_failures {
+            self.reset_all_failure_counts();
+        }
+    }
+
+    /// 统计当前仍在锁定中的账号及其剩余等待范围
+    fn snapshot_for_reset(&self) -> OptimisticResetRecord {
+        let now = self.now();
+        let mut accounts = std::collections::HashSet::new();
+        let mut min_wait = u64::MAX;
+        let mut max_wait = 0;
+
+        for entry in self.limits.iter() {
+            let Ok(remaining) = entry.value().reset_time.duration_since(now) else {
+                continue;
+            };
+            let wait = remaining.as_secs();
+            // Key 为 "account" 或 "account:model"
+            let account = entry.key().split(':').next().unwrap_or_default();
+            accounts.insert(account.to_string());
+            min_wait = min_wait.min(wait);
+            max_wait = max_wait.max(wait);
+        }
+
+        OptimisticResetRecord {
+            ts: now,
+            accounts_affected: accounts.len(),
+            min_wait_at_reset: if accounts.is_empty() { 0 } else { min_wait },
+            max_wait_at_reset: max_wait,
+        }
+    }
+
+    /// 最近一次乐观重置时的现场记录 (从未重置过时为 None)
+    #[allow(dead_code)]
+    pub fn last_optimistic_reset_detail(&self) -> Option<OptimisticResetRecord> {
+        self.last_optimistic_reset.read().clone()
+    }
+
+    /// 清空所有账号的失败计数 (含过载与 5xx 计数)，下一次失败从第一档退避开始
+    pub fn reset_all_failure_counts(&self) {
+        let count =
+            self.failure_counts.len() + self.overload_counts.len() + self.server_error_counts.len();
+        self.failure_counts.clear();
+        self.overload_counts.clear();
+        self.server_error_counts.clear();
+        tracing::info!("已重置 {} 条失败计数", count);
+    }
+}
+
+impl Default for RateLimitTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 计算 `now` 到下一个 (时, 分) 刷新点的秒数，刷新点按 `utc_offset_secs` 时区解释
+///
+/// 恰好位于刷新点时返回一整天；时间或偏移非法时返回 None。
+fn secs_until_daily_reset(
+    now: chrono::DateTime<chrono::Utc>,
+    (hour, minute): (u8, u8),
+    utc_offset_secs: i32,
+) -> Option<u64> {
+    let tz = chrono::FixedOffset::east_opt(utc_offset_secs)?;
+    let local_now = now.with_timezone(&tz);
+    let today_reset = local_now
+        .date_naive()
+        .and_hms_opt(hour as u32, minute as u32, 0)?
+        .and_local_timezone(tz)
+        .single()?;
+    let next_reset = if today_reset > local_now {
+        today_reset
+    } else {
+        today_reset + chrono::Duration::days(1)
+    };
+    u64::try_from((next_reset - local_now).num_seconds()).ok()
+}
+
+/// 供 `benches/rate_limit_parse.rs` 调用 `parse_retry_time_from_body`，仅在 `bench` feature 下导出
+#[cfg(feature = "bench")]
+pub fn bench_parse_retry_time_from_body(body: &str) -> Option<u64> {
+    static TRACKER: Lazy<RateLimitTracker> = Lazy::new(RateLimitTracker::new);
+    TRACKER.parse_retry_time_from_body(body)
+}
+
+/// 区分 404 的两类含义: 模型不存在 (`ModelNotFound`，锁模型) 与其他资源不存在 (`ServerError`，短暂锁账号)
+///
+/// 明确的模型类提示优先；"Requested entity was not found" 视为与模型无关；
+/// 其余情况下 body 提到了请求的模型名也按模型不存在处理。
+pub fn parse_404_reason(body: &str, model: Option<&str>) -> RateLimitReason {
+    if MODEL_NOT_FOUND_RE.is_match(body) {
+        return RateLimitReason::ModelNotFound;
+    }
+    let body_lower = body.to_lowercase();
+    if body_lower.contains(REQUESTED_ENTITY_NOT_FOUND) {
+        return RateLimitReason::ServerError;
+    }
+    if model
+        .filter(|m| !m.is_empty())
+        .is_some_and(|m| body_lower.contains(&m.to_lowercase()))
+    {
+        RateLimitReason::ModelNotFound
+    } else {
+        RateLimitReason::ServerError
+    }
+}
+
+/// 转义 Prometheus 标签值中的反斜杠、双引号与换行
+#[cfg(feature = "prometheus")]
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 移除某个账号后账号池容量的变化 (负数表示下降)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CapacityDelta {
+    /// 当前可用账号数的变化
+    pub available_delta: i64,
+    /// 可用账号剩余并发槽位总数的变化
+    pub headroom_delta: i64,
+}
+
+/// 统计账号池当前的 (可用账号数, 剩余并发槽位)
+fn pool_capacity<'a>(
+    accounts: impl Iterator<Item = &'a AccountKey>,
+    tracker: &RateLimitTracker,
+    concurrency: &ConcurrencyLimiter,
+    model: Option<&str>,
+) -> (i64, i64) {
+    accounts
+        .filter(|account| tracker.remaining_wait_secs(&account.tracker_id(), model) == 0)
+        .fold((0, 0), |(available, headroom), account| {
+            let free = tracker
+                .effective_concurrency_cap(account)
+                .saturating_sub(concurrency.in_flight(&account.tracker_id()));
+            (available + 1, headroom + free as i64)
+        })
+}
+
+/// 容量规划: 估算移除 `remove` 账号后可用账号数与并发余量的变化
+///
+/// 纯计算，不修改任何状态。被限流的账号本就不提供容量，移除它的变化为 0。
+#[allow(dead_code)]
+pub fn simulate_without(
+    accounts: &[AccountKey],
+    tracker: &RateLimitTracker,
+    concurrency: &ConcurrencyLimiter,
+    model: Option<&str>,
+    remove: &AccountKey,
+) -> CapacityDelta {
+    let (available, headroom) = pool_capacity(accounts.iter(), tracker, concurrency, model);
+    let (available_without, headroom_without) = pool_capacity(
+        accounts.iter().filter(|account| *account != remove),
+        tracker,
+        concurrency,
+        model,
+    );
+
+    CapacityDelta {
+        available_delta: available_without - available,
+        headroom_delta: headroom_without - headroom,
+    }
+}
+
+/// 根据账号池健康度推荐的全局并发数
+///
+/// 对未被限流的账号累加其有效并发上限 (随失败次数收紧，见 `effective_concurrency_cap`)，
+/// 可直接下发给客户端驱动自适应并发。
+#[allow(dead_code)]
+pub fn recommended_global_concurrency(
+    accounts: &[AccountKey],
+    tracker: &RateLimitTracker,
+    model: Option<&str>,
+) -> u32 {
+    let total: usize = accounts
+        .iter()
+        .filter(|account| tracker.remaining_wait_secs(&account.tracker_id(), model) == 0)
+        .map(|account| tracker.effective_concurrency_cap(account))
+        .sum();
+    u32::try_from(total).unwrap_or(u32::MAX)
+}
+
+/// 按健康度加权随机选择账号: 权重为 1 / (failure_count + 1)
+///
+/// 与轮询相比，健康账号被优先使用，失败较多的账号获得恢复时间，
+/// 但仍有机会被选中 (不会被完全饿死)。
+#[allow(dead_code)]
+pub struct AccountSelector<'a> {
+    accounts: &'a [AccountKey],
+    tracker: &'a RateLimitTracker,
+}
+
+#[allow(dead_code)]
+impl<'a> AccountSelector<'a> {
+    pub fn new(accounts: &'a [AccountKey], tracker: &'a RateLimitTracker) -> Self {
+        Self { accounts, tracker }
+    }
+
+    /// 在当前可用 (未被限流) 的账号中按权重随机选出一个；全部不可用时返回 None
+    pub fn next_by_priority(&self, model: Option<&str>) -> Option<&'a AccountKey> {
+        let weighted: Vec<(&'a AccountKey, f64)> = self
+            .accounts
+            .iter()
+            .filter(|account| {
+                self.tracker
+                    .remaining_wait_secs(&account.tracker_id(), model)
+                    == 0
+            })
+            .map(|account| {
+                let failures = self.tracker.failure_count(account) as f64;
+                (account, 1.0 / (failures + 1.0))
+            })
+            .collect();
+
+        if weighted.is_empty() {
+            return None;
+        }
+        let total: f64 = weighted.iter().map(|(_, w)| w).sum();
+
+        use rand::Rng;
+        let mut roll = rand::thread_rng().gen_range(0.0..total);
+        for (id, weight) in &weighted {
+            if roll < *weight {
+                return Some(id);
+            }
+            roll -= weight;
+        }
+        // 浮点误差兜底
+        weighted.last().map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_time_minutes_seconds() {
+        let tracker = RateLimitTracker::new();
+        let body = "Rate limit exceeded. Try again in 2m 30s";
+        let time = tracker.parse_retry_time_from_body(body);
+        assert_eq!(time, Some(150));
+    }
+
+    #[test]
+    fn test_parse_google_json_delay() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{
+            "error": {
+                "details": [
+                    { 
+                        "metadata": {
+                            "quotaResetDelay": "42s" 
+                        }
+                    }
+                ]
+            }
+        }"#;
+        let time = tracker.parse_retry_time_from_body(body);
+        assert_eq!(time, Some(42));
+    }
+
+    #[test]
+    fn test_parse_duration_with_days() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.parse_duration_string("1d"), Some(86400));
+        assert_eq!(tracker.parse_duration_string("1d12h"), Some(129600));
+        assert_eq!(
+            tracker.parse_duration_string("2d3h15m42s"),
+            Some(2 * 86400 + 3 * 3600 + 15 * 60 + 42)
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after_ignore_case() {
+        let tracker = RateLimitTracker::new();
+        let body = "Quota limit hit. Retry After 99 Seconds";
+        let time = tracker.parse_retry_time_from_body(body);
+        assert_eq!(time, Some(99));
+    }
+
+    #[test]
+    fn test_get_remaining_wait() {
+        let tracker = RateLimitTracker::new();
+        tracker.parse_from_error(&AccountKey::global("acc1"), 429, Some("30"), "", None, &[]);
+        let wait = tracker.get_remaining_wait(&AccountKey::global("acc1"), None);
+        assert!(wait > 25 && wait <= 30);
+    }
+
+    #[test]
+    fn test_safety_buffer() {
+        let tracker = RateLimitTracker::new();
+        // 如果 API 返回 1s，我们强制设为 2s
+        tracker.parse_from_error(&AccountKey::global("acc1"), 429, Some("1"), "", None, &[]);
+        let wait = tracker.get_remaining_wait(&AccountKey::global("acc1"), None);
+        // Due to time passing, it might be 1 or 2
+        assert!(wait >= 1 && wait <= 2);
+    }
+
+    #[test]
+    fn test_tpm_exhausted_is_rate_limit_exceeded() {
+        let tracker = RateLimitTracker::new();
+        // 模拟真实世界的 TPM 错误，同时包含 "Resource exhausted" 和 "per minute"
+        let body = "Resource has been exhausted (e.g. check quota). Quota limit 'Tokens per minute' exceeded.";
+        let reason = tracker.parse_rate_limit_reason(body);
+        // 应该被识别为 RateLimitExceeded，而不是 QuotaExhausted
+        assert_eq!(reason, RateLimitReason::RateLimitExceeded);
+    }
+
+    #[test]
+    fn test_server_error_does_not_accumulate_failure_count() {
+        let tracker = RateLimitTracker::new();
+        let backoff_steps = vec![60, 300, 1800, 7200];
+
+        // 模拟连续 5 次 5xx 错误
+        for i in 1..=5 {
+            let info = tracker.parse_from_error(
+                &AccountKey::global("acc1"),
+                503,
+                None,
+                "Service Unavailable",
+                None,
+                &backoff_steps,
+            );
+            assert!(info.is_some(), "第 {} 次 5xx 应该返回 RateLimitInfo", i);
+            let info = info.unwrap();
+            // 5xx 应该始终锁定 8 秒，不受 failure_count 影响
+            assert_eq!(info.retry_after_sec, 8, "5xx 第 {} 次应该锁定 8 秒", i);
+        }
+
+        // 现在触发一次 429 QuotaExhausted（没有 quotaResetDelay）
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let info = tracker.parse_from_error(
+            &AccountKey::global("acc1"),
+            429,
+            None,
+            quota_body,
+            None,
+            &backoff_steps,
+        );
+        assert!(info.is_some());
+        let info = info.unwrap();
+
+        // 关键断言：429 应该从第 1 次开始（锁 60 秒），而不是继承 5xx 的计数
+        assert_eq!(
+            info.retry_after_sec, 60,
+            "429 应该从第 1 次退避开始(60秒),而不是被 5xx 污染"
+        );
+    }
+
+    #[test]
+    fn test_quota_exhausted_does_accumulate_failure_count() {
+        let tracker = RateLimitTracker::new();
+        let backoff_steps = vec![60, 300, 1800, 7200];
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        // 第 1 次 429 → 60 秒
+        let info = tracker.parse_from_error(
+            &AccountKey::global("acc2"),
+            429,
+            None,
+            quota_body,
+            None,
+            &backoff_steps,
+        );
+        assert_eq!(info.unwrap().retry_after_sec, 60);
+
+        // 第 2 次 429 → 300 秒
+        let info = tracker.parse_from_error(
+            &AccountKey::global("acc2"),
+            429,
+            None,
+            quota_body,
+            None,
+            &backoff_steps,
+        );
+        assert_eq!(info.unwrap().retry_after_sec, 300);
+
+        // 第 3 次 429 → 1800 秒
+        let info = tracker.parse_from_error(
+            &AccountKey::global("acc2"),
+            429,
+            None,
+            quota_body,
+            None,
+            &backoff_steps,
+        );
+        assert_eq!(info.unwrap().retry_after_sec, 1800);
+
+        // 第 4 次 429 → 7200 秒
+        let info = tracker.parse_from_error(
+            &AccountKey::global("acc2"),
+            429,
+            None,
+            quota_body,
+            None,
+            &backoff_steps,
+        );
+        assert_eq!(info.unwrap().retry_after_sec, 7200);
+    }
+
+    #[test]
+    fn test_cleanup_expired_purges_stale_failure_counts() {
+        let tracker = RateLimitTracker::new();
+        let stale = SystemTime::now() - Duration::from_secs(FAILURE_COUNT_EXPIRY_SECONDS + 60);
+        tracker
+            .failure_counts
+            .insert("acc_stale".to_string(), (3, stale));
+        tracker
+            .failure_counts
+            .insert("acc_fresh".to_string(), (1, SystemTime::now()));
+
+        let stats = tracker.cleanup_expired();
+        assert_eq!(stats.failure_counts_removed, 1);
+        assert_eq!(stats.limits_removed, 0);
+        assert!(!tracker.failure_counts.contains_key("acc_stale"));
+        assert!(tracker.failure_counts.contains_key("acc_fresh"));
+    }
+
+    #[test]
+    fn test_len_and_is_empty() {
+        let tracker = RateLimitTracker::new();
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.len(), 0);
+        assert_eq!(tracker.failure_count_len(), 0);
+
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        tracker.parse_from_error(
+            &AccountKey::global("acc1"),
+            429,
+            None,
+            quota_body,
+            None,
+            &[60],
+        );
+        tracker.parse_from_error(&AccountKey::global("acc2"), 503, None, "", None, &[60]);
+
+        assert!(!tracker.is_empty());
+        assert_eq!(tracker.len(), 2);
+        // 5xx 不累加失败计数
+        assert_eq!(tracker.failure_count_len(), 1);
+    }
+
+    #[test]
+    fn test_vertex_error_status_classification() {
+        let tracker = RateLimitTracker::new();
+
+        let exhausted = r#"{
+            "error": {
+                "code": 429,
+                "message": "Resource has been exhausted.",
+                "status": "RESOURCE_EXHAUSTED"
+            }
+        }"#;
+        assert_eq!(
+            tracker.parse_rate_limit_reason(exhausted),
+            RateLimitReason::QuotaExhausted
+        );
+
+        let unavailable = r#"{
+            "error": {
+                "code": 503,
+                "message": "The service is currently unavailable.",
+                "status": "UNAVAILABLE"
+            }
+        }"#;
+        assert_eq!(
+            tracker.parse_rate_limit_reason(unavailable),
+            RateLimitReason::ServerError
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sweeper_removes_expired_entries() {
+        let tracker = Arc::new(RateLimitTracker::new());
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_sweep"),
+            SystemTime::now() + Duration::from_millis(100),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        assert_eq!(tracker.len(), 1);
+
+        let handle = tracker
+            .clone()
+            .start_cleanup_task(Duration::from_millis(50));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        handle.abort();
+
+        assert!(
+            tracker.is_empty(),
+            "expired entry should be swept automatically"
+        );
+    }
+
+    #[test]
+    fn test_pick_available_and_soonest() {
+        let tracker = RateLimitTracker::new();
+        let accounts: Vec<AccountKey> = ["acc_a", "acc_b", "acc_c"]
+            .iter()
+            .map(|id| AccountKey::global(id))
+            .collect();
+
+        tracker.parse_from_error(
+            &AccountKey::global("acc_a"),
+            429,
+            Some("120"),
+            "",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(&AccountKey::global("acc_b"), 429, Some("30"), "", None, &[]);
+
+        assert_eq!(tracker.pick_available(&accounts, None), Some(&accounts[2]));
+        assert_eq!(
+            tracker
+                .pick_soonest(&accounts, None)
+                .map(|(account, _)| account),
+            Some(&accounts[2])
+        );
+
+        tracker.parse_from_error(&AccountKey::global("acc_c"), 429, Some("60"), "", None, &[]);
+        assert_eq!(tracker.pick_available(&accounts, None), None);
+
+        let (account, wait) = tracker.pick_soonest(&accounts, None).unwrap();
+        assert_eq!(account, &accounts[1]);
+        assert!(wait > 25 && wait <= 30);
+
+        assert_eq!(tracker.pick_soonest(&[], None), None);
+    }
+
+    #[test]
+    fn test_memory_estimate_counts_entries() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.memory_estimate(), MemoryReport::default());
+
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        for i in 0..3 {
+            let account = format!("acc_mem_{}", i);
+            tracker.parse_from_error(
+                &AccountKey::global(&account),
+                429,
+                None,
+                quota_body,
+                None,
+                &[60],
+            );
+        }
+        tracker.parse_from_error(
+            &AccountKey::global("acc_mem_5xx"),
+            500,
+            None,
+            "",
+            None,
+            &[60],
+        );
+
+        let report = tracker.memory_estimate();
+        assert_eq!(report.limit_entries, 4);
+        assert_eq!(report.failure_count_entries, 3);
+        assert!(report.limits_bytes > 0);
+        assert_eq!(report.history_entries, 4);
+        assert_eq!(
+            report.total_bytes(),
+            report.limits_bytes + report.failure_counts_bytes + report.history_bytes
+        );
+    }
+
+    #[test]
+    fn test_merge_from_keeps_later_reset_and_higher_count() {
+        let mut main = RateLimitTracker::new();
+        let worker = RateLimitTracker::new();
+        let now = SystemTime::now();
+
+        main.set_lockout_until(
+            &AccountKey::global("acc_shared"),
+            now + Duration::from_secs(30),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        worker.set_lockout_until(
+            &AccountKey::global("acc_shared"),
+            now + Duration::from_secs(300),
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        worker.set_lockout_until(
+            &AccountKey::global("acc_worker_only"),
+            now + Duration::from_secs(60),
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-2.5-pro".to_string()),
+        );
+        main.failure_counts
+            .insert("acc_shared".to_string(), (3, now));
+        worker
+            .failure_counts
+            .insert("acc_shared".to_string(), (1, now));
+        worker
+            .failure_counts
+            .insert("acc_worker_only".to_string(), (2, now));
+
+        main.merge_from(worker);
+
+        let shared = main.get(&AccountKey::global("acc_shared")).unwrap();
+        assert_eq!(shared.reason, RateLimitReason::QuotaExhausted);
+        assert!(main.get_remaining_wait(&AccountKey::global("acc_shared"), None) > 60);
+        assert!(main.is_rate_limited(
+            &AccountKey::global("acc_worker_only"),
+            Some("gemini-2.5-pro")
+        ));
+        assert_eq!(main.failure_counts.get("acc_shared").unwrap().0, 3);
+        assert_eq!(main.failure_counts.get("acc_worker_only").unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_max_lockout_duration_clamps_retry() {
+        let tracker = RateLimitTracker::new();
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_clamp"),
+                429,
+                Some("100000"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 86400);
+
+        tracker.clear(&AccountKey::global("acc_clamp"));
+        tracker.set_backoff_config(BackoffConfig {
+            max_lockout_duration: Duration::from_secs(600),
+            ..Default::default()
+        });
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_clamp"),
+                429,
+                Some("100000"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 600);
+        assert!(tracker.get_remaining_wait(&AccountKey::global("acc_clamp"), None) <= 600);
+    }
+
+    #[test]
+    fn test_clamped_jitter_stays_within_step() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            jitter_ratio: 10.0,
+            clamp_jitter: true,
+            ..Default::default()
+        });
+        let backoff_steps = vec![60, 300];
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        for _ in 0..50 {
+            tracker.failure_counts.clear();
+            let info = tracker
+                .parse_from_error(
+                    &AccountKey::global("acc_jitter"),
+                    429,
+                    None,
+                    quota_body,
+                    None,
+                    &backoff_steps,
+                )
+                .unwrap();
+            assert!(
+                (60..300).contains(&info.retry_after_sec),
+                "jittered first step {} must stay within [60, 300)",
+                info.retry_after_sec
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_plain_milliseconds() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.parse_duration_string("500ms"), Some(1));
+        assert_eq!(tracker.parse_duration_string("1500ms"), Some(2));
+        assert_eq!(tracker.parse_duration_string("6m0s"), Some(360));
+    }
+
+    #[test]
+    fn test_parse_from_openai_headers() {
+        let tracker = RateLimitTracker::new();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-limit-requests", "500".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset-requests", "6m0s".parse().unwrap());
+        headers.insert("x-ratelimit-limit-tokens", "30000".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-tokens", "29000".parse().unwrap());
+        headers.insert("x-ratelimit-reset-tokens", "20ms".parse().unwrap());
+
+        let info = tracker
+            .parse_from_headers(
+                &AccountKey::global("acc_oai"),
+                &headers,
+                Some("gpt-4o".to_string()),
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+        assert_eq!(info.retry_after_sec, 360);
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_oai"), None));
+
+        // 额度未耗尽或缺少响应头时不锁定，交由 parse_from_error 处理
+        headers.insert("x-ratelimit-remaining-requests", "10".parse().unwrap());
+        assert!(tracker
+            .parse_from_headers(&AccountKey::global("acc_oai_ok"), &headers, None)
+            .is_none());
+        assert!(tracker
+            .parse_from_headers(&AccountKey::global("acc_oai_ok"), &HeaderMap::new(), None)
+            .is_none());
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_oai_ok"), None));
+    }
+
+    #[test]
+    fn test_get_model_remaining_wait_takes_max() {
+        let tracker = RateLimitTracker::new();
+        let now = SystemTime::now();
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_max"),
+            now + Duration::from_secs(10),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_max"),
+            now + Duration::from_secs(600),
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-2.5-pro".to_string()),
+        );
+
+        let model_wait =
+            tracker.get_model_remaining_wait(&AccountKey::global("acc_max"), "gemini-2.5-pro");
+        assert!(model_wait > 590 && model_wait <= 600);
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc_max"), Some("gemini-2.5-pro")),
+            model_wait
+        );
+
+        let other_wait =
+            tracker.get_model_remaining_wait(&AccountKey::global("acc_max"), "gemini-2.5-flash");
+        assert!(other_wait > 5 && other_wait <= 10);
+    }
+
+    #[test]
+    fn test_model_available_somewhere() {
+        let tracker = RateLimitTracker::new();
+        let accounts: Vec<AccountKey> = ["acc_x", "acc_y", "acc_z"]
+            .iter()
+            .map(|id| AccountKey::global(id))
+            .collect();
+        let model = "gemini-2.5-pro";
+        let reset = SystemTime::now() + Duration::from_secs(300);
+
+        for account in &accounts[..2] {
+            tracker.set_lockout_until(
+                account,
+                reset,
+                RateLimitReason::QuotaExhausted,
+                Some(model.to_string()),
+            );
+        }
+        assert!(tracker.model_available_somewhere(&accounts, model));
+
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_z"),
+            reset,
+            RateLimitReason::QuotaExhausted,
+            Some(model.to_string()),
+        );
+        assert!(!tracker.model_available_somewhere(&accounts, model));
+        // 其他模型不受影响
+        assert!(tracker.model_available_somewhere(&accounts, "gemini-2.5-flash"));
+    }
+
+    #[test]
+    fn test_anthropic_529_overloaded() {
+        let tracker = RateLimitTracker::new();
+        let backoff_steps = vec![60, 300, 1800, 7200];
+        let body = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+
+        let expected = [2, 5, 10, 10];
+        for (i, lockout) in expected.iter().enumerate() {
+            let info = tracker
+                .parse_from_error(
+                    &AccountKey::global("acc_529"),
+                    529,
+                    None,
+                    body,
+                    None,
+                    &backoff_steps,
+                )
+                .unwrap();
+            assert_eq!(info.reason, RateLimitReason::Overloaded);
+            assert_eq!(info.retry_after_sec, *lockout, "overload #{}", i + 1);
+        }
+
+        // 过载计数不影响 429 的退避阶梯
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_529"),
+                429,
+                None,
+                quota_body,
+                None,
+                &backoff_steps,
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 60);
+
+        // 非 overloaded 的 529 仍按 ServerError 处理
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_529_other"),
+                529,
+                None,
+                "upstream error",
+                None,
+                &backoff_steps,
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::ServerError);
+    }
+
+    #[test]
+    fn test_on_lockout_filtered_by_reason() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tracker = RateLimitTracker::new();
+        let quota_hits = Arc::new(AtomicUsize::new(0));
+        let all_hits = Arc::new(AtomicUsize::new(0));
+
+        let counter = quota_hits.clone();
+        tracker.on_lockout_filtered(&[RateLimitReason::QuotaExhausted], move |_, info| {
+            assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = all_hits.clone();
+        tracker.on_lockout(move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        tracker.parse_from_error(&AccountKey::global("acc_evt"), 503, None, "", None, &[60]);
+        assert_eq!(quota_hits.load(Ordering::SeqCst), 0);
+        assert_eq!(all_hits.load(Ordering::SeqCst), 1);
+
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        tracker.parse_from_error(
+            &AccountKey::global("acc_evt"),
+            429,
+            None,
+            quota_body,
+            None,
+            &[60],
+        );
+        assert_eq!(quota_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(all_hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_500_vs_503_lockout_duration() {
+        let tracker = RateLimitTracker::new();
+
+        let info_500 = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_500"),
+                500,
+                None,
+                "Internal Server Error",
+                None,
+                &[],
+            )
+            .unwrap();
+        let info_503 = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_503"),
+                503,
+                None,
+                "Service Unavailable",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info_500.retry_after_sec, 4);
+        assert_eq!(info_503.retry_after_sec, 8);
+
+        let mut config = BackoffConfig::default();
+        config.server_error_lockout_secs.insert(500, 1);
+        config.server_error_lockout_secs.insert(503, 30);
+        tracker.set_backoff_config(config);
+
+        let info_500 = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_500"),
+                500,
+                None,
+                "Internal Server Error",
+                None,
+                &[],
+            )
+            .unwrap();
+        let info_503 = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_503"),
+                503,
+                None,
+                "Service Unavailable",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info_500.retry_after_sec, 1);
+        assert_eq!(info_503.retry_after_sec, 30);
+    }
+
+    #[test]
+    fn test_absurd_quota_reset_delay_is_capped() {
+        let tracker = RateLimitTracker::new();
+        // 低于异常阈值 (7 天) 但超过最长锁定时长，被截断
+        let body = r#"{"error":{"details":[{"metadata":{"quotaResetDelay":"100h"}}]}}"#;
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_100h"), 429, None, body, None, &[])
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 86400);
+
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_far"),
+            SystemTime::now() + Duration::from_secs(1000 * 3600),
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        assert!(tracker.get_remaining_wait(&AccountKey::global("acc_far"), None) <= 86400);
+    }
+
+    #[test]
+    fn test_least_limited_hashed_tiebreak_spreads_choices() {
+        let accounts: Vec<AccountKey> = (0..4)
+            .map(|i| AccountKey::global(&format!("acc_tie_{}", i)))
+            .collect();
+
+        // 默认按输入顺序
+        let tracker = RateLimitTracker::new();
+        for _ in 0..20 {
+            assert_eq!(
+                tracker.least_limited(&accounts, None),
+                Some((&accounts[0], 0))
+            );
+        }
+
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            tie_break: TieBreak::Hashed,
+            ..Default::default()
+        });
+        let mut picked = HashMap::new();
+        for _ in 0..200 {
+            let (id, wait) = tracker.least_limited(&accounts, None).unwrap();
+            assert_eq!(wait, 0);
+            *picked.entry(id).or_insert(0) += 1;
+        }
+        assert_eq!(
+            picked.len(),
+            accounts.len(),
+            "all accounts should be chosen"
+        );
+
+        // 等待时间仍优先于哈希打散
+        tracker.parse_from_error(
+            &AccountKey::global("acc_tie_0"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc_tie_1"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc_tie_2"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+        for _ in 0..20 {
+            assert_eq!(
+                tracker.least_limited(&accounts, None),
+                Some((&accounts[3], 0))
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_quota_and_limit_values() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{
+            "error": {
+                "code": 429,
+                "message": "Quota exceeded for quota metric 'Generate Content API requests per day'",
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                        "reason": "QUOTA_EXHAUSTED",
+                        "metadata": { "quotaResetDelay": "42s" }
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                        "quotaValue": "1000",
+                        "limitValue": 1000
+                    }
+                ]
+            }
+        }"#;
+
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_qv"), 429, None, body, None, &[60])
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 42);
+        assert_eq!(info.quota_value, Some(1000));
+        assert_eq!(info.limit_value, Some(1000));
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_qv_none"),
+                429,
+                Some("10"),
+                "Too Many Requests",
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.quota_value, None);
+        assert_eq!(info.limit_value, None);
+    }
+
+    #[test]
+    fn test_mark_partial_success_keeps_account_lock() {
+        let tracker = RateLimitTracker::new();
+        let reset = SystemTime::now() + Duration::from_secs(300);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_ps"),
+            reset,
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_ps"),
+            reset,
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-2.5-pro".to_string()),
+        );
+        assert_eq!(tracker.len(), 2);
+
+        tracker.mark_partial_success(&AccountKey::global("acc_ps"), "gemini-2.5-pro");
+
+        assert_eq!(tracker.len(), 1);
+        assert!(
+            tracker.get(&AccountKey::global("acc_ps")).is_some(),
+            "account lock must be kept"
+        );
+        assert!(!tracker.limits.contains_key("acc_ps:gemini-2.5-pro"));
+
+        // 空模型名不应误删账号级锁
+        tracker.mark_partial_success(&AccountKey::global("acc_ps"), "");
+        assert!(tracker.get(&AccountKey::global("acc_ps")).is_some());
+    }
+
+    #[test]
+    fn test_concurrency_cap_shrinks_with_failures() {
+        let tracker = RateLimitTracker::new();
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        assert_eq!(
+            tracker.effective_concurrency_cap(&AccountKey::global("acc_cc")),
+            8
+        );
+
+        let mut caps = Vec::new();
+        for _ in 0..4 {
+            tracker.parse_from_error(
+                &AccountKey::global("acc_cc"),
+                429,
+                None,
+                quota_body,
+                None,
+                &[60],
+            );
+            caps.push(tracker.effective_concurrency_cap(&AccountKey::global("acc_cc")));
+        }
+        assert_eq!(caps, vec![4, 2, 2, 1]);
+
+        // 上限收紧后只允许 1 个在途请求
+        let permit = tracker.try_acquire_slot(&AccountKey::global("acc_cc"), None);
+        assert!(permit.is_some());
+        assert!(tracker
+            .try_acquire_slot(&AccountKey::global("acc_cc"), None)
+            .is_none());
+        assert_eq!(tracker.concurrency.in_flight("acc_cc"), 1);
+        drop(permit);
+        assert_eq!(tracker.concurrency.in_flight("acc_cc"), 0);
+        assert!(tracker
+            .try_acquire_slot(&AccountKey::global("acc_cc"), None)
+            .is_some());
+
+        tracker.mark_success(&AccountKey::global("acc_cc"));
+        assert_eq!(
+            tracker.effective_concurrency_cap(&AccountKey::global("acc_cc")),
+            8
+        );
+    }
+
+    #[test]
+    fn test_lock_outcome_created_extended_ignored() {
+        let tracker = RateLimitTracker::new();
+        let now = SystemTime::now();
+
+        let outcome = tracker.set_lockout_until(
+            &AccountKey::global("acc_lo"),
+            now + Duration::from_secs(60),
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        assert_eq!(outcome, LockOutcome::Created);
+
+        let outcome = tracker.set_lockout_until(
+            &AccountKey::global("acc_lo"),
+            now + Duration::from_secs(600),
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        assert_eq!(outcome, LockOutcome::Extended);
+
+        // 更短的退避不会缩短已有的锁
+        let (info, outcome) = tracker
+            .parse_from_error_with_outcome(&AccountKey::global("acc_lo"), 503, None, "", None, &[])
+            .unwrap();
+        assert_eq!(outcome, LockOutcome::Ignored);
+        assert_eq!(info.retry_after_sec, 8);
+        assert!(tracker.get_remaining_wait(&AccountKey::global("acc_lo"), None) > 590);
+        assert_eq!(
+            tracker.get(&AccountKey::global("acc_lo")).unwrap().reason,
+            RateLimitReason::QuotaExhausted
+        );
+    }
+
+    #[test]
+    fn test_parse_from_draft_ratelimit_headers() {
+        let tracker = RateLimitTracker::new();
+        let mut headers = HeaderMap::new();
+        headers.insert("RateLimit-Limit", "100".parse().unwrap());
+        headers.insert("RateLimit-Remaining", "0".parse().unwrap());
+        headers.insert("RateLimit-Reset", "30".parse().unwrap());
+
+        let info = tracker
+            .parse_from_headers(&AccountKey::global("acc_draft"), &headers, None)
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 30);
+        let wait = tracker.get_remaining_wait(&AccountKey::global("acc_draft"), None);
+        assert!(wait > 25 && wait <= 30);
+
+        // 仍有余量时不预先锁定
+        headers.insert("RateLimit-Remaining", "5".parse().unwrap());
+        assert!(tracker
+            .parse_from_headers(&AccountKey::global("acc_draft_ok"), &headers, None)
+            .is_none());
+
+        // epoch 形式的 X-RateLimit-Reset 不会被当作 delta-seconds
+        let mut epoch_headers = HeaderMap::new();
+        epoch_headers.insert("X-RateLimit-Remaining", "0".parse().unwrap());
+        epoch_headers.insert("X-RateLimit-Reset", "1735689600".parse().unwrap());
+        assert!(tracker
+            .parse_from_headers(&AccountKey::global("acc_epoch"), &epoch_headers, None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_half_open_probe_sequence() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            half_open_window: Duration::from_secs(60),
+            ..Default::default()
+        });
+
+        // 配额锁刚刚到期，进入半开窗口
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_probe"),
+            SystemTime::now() - Duration::from_secs(1),
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc_probe"), None),
+            0
+        );
+
+        // 只有一个探测请求能通过，其他请求继续等待
+        assert!(tracker.acquire_probe(&AccountKey::global("acc_probe")));
+        assert!(!tracker.acquire_probe(&AccountKey::global("acc_probe")));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_probe"), None));
+
+        // 探测成功后完全放开
+        tracker.mark_success(&AccountKey::global("acc_probe"));
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_probe"), None));
+        assert!(tracker.acquire_probe(&AccountKey::global("acc_probe")));
+        assert!(tracker.acquire_probe(&AccountKey::global("acc_probe")));
+
+        // 未开启半开窗口时不做限制
+        let tracker = RateLimitTracker::new();
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_probe"),
+            SystemTime::now() - Duration::from_secs(1),
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        assert!(tracker.acquire_probe(&AccountKey::global("acc_probe")));
+        assert!(tracker.acquire_probe(&AccountKey::global("acc_probe")));
+    }
+
+    #[test]
+    fn test_throttle_rate_rolling_window() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.throttle_rate(Duration::from_secs(60)), 0.0);
+
+        tracker.parse_from_error(&AccountKey::global("acc_tr"), 429, Some("5"), "", None, &[]);
+        tracker.parse_from_error(
+            &AccountKey::global("acc_tr"),
+            400,
+            None,
+            "Bad Request",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc_tr"),
+            401,
+            None,
+            "Unauthorized",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(&AccountKey::global("acc_tr"), 503, None, "", None, &[]);
+
+        let rate = tracker.throttle_rate(Duration::from_secs(60));
+        assert!((rate - 0.5).abs() < f64::EPSILON);
+
+        // 环形缓冲只保留最近的样本
+        let counter = RollingRateLimitCounter::new(2);
+        let now = SystemTime::now();
+        counter.record_at(now, true);
+        counter.record_at(now, false);
+        counter.record_at(now, false);
+        assert_eq!(counter.throttle_rate_at(now, Duration::from_secs(60)), 0.0);
+        // 窗口之外的样本不计入
+        counter.record_at(now, true);
+        assert_eq!(counter.throttle_rate_at(now, Duration::from_secs(60)), 0.5);
+        assert_eq!(
+            counter.throttle_rate_at(now + Duration::from_secs(61), Duration::from_secs(60)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_simulate_parse_from_error_does_not_mutate() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let steps = [60, 300, 1800];
+
+        for _ in 0..2 {
+            let sim = tracker.simulate_parse_from_error(
+                &AccountKey::global("acc_sim"),
+                429,
+                None,
+                body,
+                None,
+                &steps,
+            );
+            assert_eq!(sim, Some((RateLimitReason::QuotaExhausted, 60)));
+        }
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.failure_count_len(), 0);
+        assert!(tracker
+            .simulate_parse_from_error(&AccountKey::global("acc_sim"), 400, None, "", None, &steps)
+            .is_none());
+
+        // 真实解析后，试算推进到下一档
+        tracker.parse_from_error(
+            &AccountKey::global("acc_sim"),
+            429,
+            None,
+            body,
+            None,
+            &steps,
+        );
+        let sim = tracker.simulate_parse_from_error(
+            &AccountKey::global("acc_sim"),
+            429,
+            None,
+            body,
+            None,
+            &steps,
+        );
+        assert_eq!(sim, Some((RateLimitReason::QuotaExhausted, 300)));
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn test_simulate_matches_real_parse_for_model_specific_404() {
+        let tracker = RateLimitTracker::new();
+        let body = "Resource gemini-exp-1206 missing";
+        let model = "gemini-exp-1206";
+
+        let sim = tracker.simulate_parse_from_error(
+            &AccountKey::global("acc_sim404"),
+            404,
+            None,
+            body,
+            Some(model),
+            &[],
+        );
+        assert_eq!(sim, Some((RateLimitReason::ModelNotFound, 300)));
+        assert!(tracker.is_empty());
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_sim404"),
+                404,
+                None,
+                body,
+                Some(model.to_string()),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(sim, Some((info.reason, info.retry_after_sec)));
+
+        // 不传模型时按通用 404 试算
+        assert_eq!(
+            tracker.simulate_parse_from_error(
+                &AccountKey::global("acc_sim404"),
+                404,
+                None,
+                body,
+                None,
+                &[]
+            ),
+            Some((RateLimitReason::ServerError, 5))
+        );
+    }
+
+    #[test]
+    fn test_history_records_events_in_order() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            history_capacity: 3,
+            ..BackoffConfig::default()
+        });
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        tracker.parse_from_error(
+            &AccountKey::global("acc_hist"),
+            429,
+            Some("30"),
+            "",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(&AccountKey::global("acc_hist"), 503, None, "", None, &[]);
+        tracker.parse_from_error(
+            &AccountKey::global("acc_hist"),
+            429,
+            None,
+            "Please try again in 45s",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc_hist"),
+            429,
+            None,
+            quota_body,
+            None,
+            &[60],
+        );
+
+        // 容量为 3，最旧的一条被丢弃
+        let events = tracker.history(&AccountKey::global("acc_hist"));
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].status, 503);
+        assert_eq!(events[0].source, LockoutSource::Default);
+        assert_eq!(events[1].retry_sec, 45);
+        assert_eq!(events[1].source, LockoutSource::Body);
+        assert_eq!(events[2].reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(events[2].retry_sec, 60);
+        assert!(events[1].timestamp <= events[2].timestamp);
+
+        assert!(tracker.history(&AccountKey::global("acc_other")).is_empty());
+
+        let disabled = RateLimitTracker::with_config(BackoffConfig {
+            history_capacity: 0,
+            ..BackoffConfig::default()
+        });
+        disabled.parse_from_error(
+            &AccountKey::global("acc_hist"),
+            429,
+            Some("30"),
+            "",
+            None,
+            &[],
+        );
+        assert!(disabled.history(&AccountKey::global("acc_hist")).is_empty());
+    }
+
+    #[test]
+    fn test_describe_parsing_includes_custom_rules() {
+        let tracker = RateLimitTracker::new();
+        assert!(tracker.add_retry_pattern(r"cool down for (").is_err());
+        assert!(tracker.add_retry_pattern(r"cool down").is_err());
+        tracker
+            .add_retry_pattern(r"cool down for (\d+) sec")
+            .unwrap();
+        tracker.add_reason_mapping("Tier Limit", RateLimitReason::QuotaExhausted);
+
+        let desc = tracker.describe_parsing();
+        assert!(desc
+            .retry_patterns
+            .contains(&r"cool down for (\d+) sec".to_string()));
+        assert!(desc
+            .retry_patterns
+            .contains(&TRY_AGAIN_SEC_RE.as_str().to_string()));
+        assert!(desc
+            .reason_mappings
+            .contains(&("tier limit".to_string(), RateLimitReason::QuotaExhausted)));
+        assert!(desc.reason_mappings.contains(&(
+            "QUOTA_EXHAUSTED".to_string(),
+            RateLimitReason::QuotaExhausted
+        )));
+        assert_eq!(desc.handled_statuses, vec![404, 429, 500, 503, 529]);
+
+        // 自定义规则参与实际解析
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_custom"),
+                429,
+                None,
+                "tier limit reached, cool down for 90 sec",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(info.retry_after_sec, 90);
+    }
+
+    #[test]
+    fn test_failure_count_fresh_accumulated_and_expired() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_fc")), 0);
+
+        for _ in 0..3 {
+            tracker.parse_from_error(&AccountKey::global("acc_fc"), 429, None, body, None, &[60]);
+        }
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_fc")), 3);
+
+        // 只清计数，锁定保留
+        assert!(tracker.reset_failure_count(&AccountKey::global("acc_fc")));
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_fc")), 0);
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_fc"), None));
+        assert!(!tracker.reset_failure_count(&AccountKey::global("acc_fc")));
+
+        let stale = SystemTime::now() - Duration::from_secs(FAILURE_COUNT_EXPIRY_SECONDS + 10);
+        tracker
+            .failure_counts
+            .insert("acc_fc_old".to_string(), (5, stale));
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_fc_old")), 0);
+    }
+
+    #[test]
+    fn test_set_lockout_until_iso_accepts_unix_timestamp() {
+        let tracker = RateLimitTracker::new();
+        let future = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600;
+
+        assert!(tracker.set_lockout_until_iso(
+            &AccountKey::global("acc_ts"),
+            &future.to_string(),
+            RateLimitReason::QuotaExhausted,
+            None
+        ));
+        let wait = tracker.get_remaining_wait(&AccountKey::global("acc_ts"), None);
+        assert!(wait > 590 && wait <= 600);
+
+        let rfc3339 = chrono::DateTime::from_timestamp(future as i64, 0)
+            .unwrap()
+            .to_rfc3339();
+        assert!(tracker.set_lockout_until_iso(
+            &AccountKey::global("acc_rfc"),
+            &rfc3339,
+            RateLimitReason::QuotaExhausted,
+            None
+        ));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_rfc"), None));
+
+        assert!(!tracker.set_lockout_until_iso(
+            &AccountKey::global("acc_bad"),
+            "next tuesday",
+            RateLimitReason::QuotaExhausted,
+            None
+        ));
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_bad"), None));
+    }
+
+    #[test]
+    fn test_reset_all_failure_counts_restarts_ladder() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let steps = [60, 300, 1800];
+        let accounts = ["acc_r1", "acc_r2", "acc_r3"];
+
+        for account in accounts {
+            tracker.parse_from_error(&AccountKey::global(account), 429, None, body, None, &steps);
+            tracker.parse_from_error(&AccountKey::global(account), 429, None, body, None, &steps);
+            assert_eq!(tracker.failure_count(&AccountKey::global(account)), 2);
+        }
+
+        tracker.reset_all_failure_counts();
+        tracker.clear_all();
+        for account in accounts {
+            let info = tracker
+                .parse_from_error(&AccountKey::global(account), 429, None, body, None, &steps)
+                .unwrap();
+            assert_eq!(info.retry_after_sec, 60);
+        }
+
+        // 默认 clear_all 保留计数；开启开关后一并清空
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_r1")), 1);
+        tracker.clear_all();
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_r1")), 1);
+        let mut config = tracker.backoff_config();
+        config.clear_all_resets_failures = true;
+        tracker.set_backoff_config(config);
+        tracker.clear_all();
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_r1")), 0);
+    }
+
+    #[test]
+    fn test_asymmetric_backoff_escalates_fast_decays_slow() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            escalation_steps: 2,
+            deescalation_steps: 1,
+            ..BackoffConfig::default()
+        });
+        let body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let steps = [10, 20, 30, 40, 50];
+        let lockout = || {
+            tracker
+                .parse_from_error(
+                    &AccountKey::global("acc_asym"),
+                    429,
+                    None,
+                    body,
+                    None,
+                    &steps,
+                )
+                .unwrap()
+                .retry_after_sec
+        };
+
+        // 每次失败前进两档
+        assert_eq!(lockout(), 20);
+        assert_eq!(lockout(), 40);
+
+        // 一次成功只回退一档
+        tracker.mark_success(&AccountKey::global("acc_asym"));
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_asym")), 3);
+        assert_eq!(lockout(), 50);
+
+        for _ in 0..5 {
+            tracker.mark_success(&AccountKey::global("acc_asym"));
+        }
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_asym")), 0);
+        assert_eq!(tracker.failure_count_len(), 0);
+    }
+
+    #[test]
+    fn test_drain_expired_returns_removed_entries() {
+        let tracker = RateLimitTracker::new();
+        let past = SystemTime::now() - Duration::from_secs(5);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_d1"),
+            past,
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_d2"),
+            past,
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-pro".to_string()),
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc_d3"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+
+        let drained = tracker.drain_expired();
+        assert_eq!(drained.len(), 2);
+        let now = SystemTime::now();
+        assert!(drained.iter().all(|(_, info)| info.reset_time <= now));
+        assert!(drained.iter().any(|(key, _)| key == "acc_d2:gemini-pro"));
+
+        assert_eq!(tracker.len(), 1);
+        assert!(tracker.drain_expired().is_empty());
+    }
+
+    #[test]
+    fn test_simulate_without_available_vs_locked_account() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            base_concurrency: 4,
+            ..BackoffConfig::default()
+        });
+        let concurrency = ConcurrencyLimiter::new();
+        let accounts: Vec<AccountKey> = ["acc_p1", "acc_p2", "acc_p3"]
+            .iter()
+            .map(|id| AccountKey::global(id))
+            .collect();
+
+        tracker.parse_from_error(
+            &AccountKey::global("acc_p3"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+        let _permit = concurrency.try_acquire("acc_p1", None, 4).unwrap();
+
+        // acc_p1: 可用，剩余 3 个槽位
+        let delta = simulate_without(&accounts, &tracker, &concurrency, None, &accounts[0]);
+        assert_eq!(
+            delta,
+            CapacityDelta {
+                available_delta: -1,
+                headroom_delta: -3,
+            }
+        );
+
+        // acc_p3: 已被锁定，移除不影响当前容量
+        let delta = simulate_without(&accounts, &tracker, &concurrency, None, &accounts[2]);
+        assert_eq!(delta, CapacityDelta::default());
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_google_quota_failure_metric() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{
+            "error": {
+                "code": 429,
+                "message": "You exceeded your current quota, please check your plan and billing details.",
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                        "violations": [
+                            {
+                                "quotaMetric": "generativelanguage.googleapis.com/generate_content_free_tier_requests",
+                                "quotaId": "GenerateRequestsPerDayPerProjectPerModel-FreeTier",
+                                "quotaDimensions": {
+                                    "location": "global",
+                                    "model": "gemini-2.0-flash"
+                                },
+                                "quotaValue": "200"
+                            }
+                        ]
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                        "retryDelay": "43s"
+                    }
+                ]
+            }
+        }"#;
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_metric"),
+                429,
+                None,
+                body,
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(
+            info.quota_metric.as_deref(),
+            Some("generativelanguage.googleapis.com/generate_content_free_tier_requests")
+        );
+        assert_eq!(info.quota_value, Some(200));
+    }
+
+    #[test]
+    fn test_retry_after_zero_floor_and_honor() {
+        // 默认: 仍套用 2 秒下限
+        let tracker = RateLimitTracker::new();
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_zero"),
+                429,
+                Some("0"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 2);
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_zero"), None));
+
+        // 开启后: 不加锁，建议立即重试
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            honor_zero_retry_after: true,
+            ..BackoffConfig::default()
+        });
+        let (info, outcome) = tracker
+            .parse_from_error_with_outcome(
+                &AccountKey::global("acc_zero"),
+                429,
+                Some("0"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 0);
+        assert_eq!(outcome, LockOutcome::Ignored);
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_zero"), None));
+
+        // 响应体里的 0 不受此开关影响
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_zero"),
+                429,
+                None,
+                "Please try again in 0s",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 2);
+    }
+
+    #[test]
+    fn test_daily_reset_boundary_with_fixed_clock() {
+        use chrono::TimeZone;
+        let pacific = -8 * 3600;
+
+        // 太平洋时间 23:55，距午夜 5 分钟
+        let near = chrono::Utc.with_ymd_and_hms(2026, 1, 8, 7, 55, 0).unwrap();
+        assert_eq!(secs_until_daily_reset(near, (0, 0), pacific), Some(300));
+
+        // 刚过午夜，等到次日
+        let after = chrono::Utc.with_ymd_and_hms(2026, 1, 8, 8, 0, 30).unwrap();
+        assert_eq!(
+            secs_until_daily_reset(after, (0, 0), pacific),
+            Some(86400 - 30)
+        );
+
+        assert_eq!(secs_until_daily_reset(near, (25, 0), pacific), None);
+
+        // 配置后 QuotaExhausted 不再走 backoff_steps；显式的 quotaResetDelay 仍优先
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            daily_reset_time: Some((0, 0)),
+            ..BackoffConfig::default()
+        });
+        let body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let expected = secs_until_daily_reset(chrono::Utc::now(), (0, 0), pacific).unwrap();
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_daily"),
+                429,
+                None,
+                body,
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert!(info.retry_after_sec.abs_diff(expected) <= 1);
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_daily2"),
+                429,
+                Some("30"),
+                body,
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 30);
+    }
+
+    #[test]
+    fn test_model_concurrency_cap_hits_before_account_cap() {
+        let limiter = ConcurrencyLimiter::new();
+        limiter.set_model_limit("claude-opus", 1);
+
+        let opus = limiter.try_acquire("acc_m", Some("claude-opus"), 4);
+        assert!(opus.is_some());
+        // 模型级已满，账号级计数不应残留
+        assert!(limiter
+            .try_acquire("acc_m", Some("claude-opus"), 4)
+            .is_none());
+        assert_eq!(limiter.in_flight("acc_m"), 1);
+
+        // 其他模型仍可使用账号剩余的并发
+        let flash = limiter.try_acquire("acc_m", Some("gemini-flash"), 4);
+        assert!(flash.is_some());
+        assert_eq!(limiter.in_flight("acc_m"), 2);
+        // 模型上限按账号分别计算
+        assert!(limiter
+            .try_acquire("acc_n", Some("claude-opus"), 4)
+            .is_some());
+
+        drop(opus);
+        assert_eq!(limiter.in_flight("acc_m"), 1);
+        assert_eq!(limiter.in_flight("acc_m:claude-opus"), 0);
+        assert!(limiter
+            .try_acquire("acc_m", Some("claude-opus"), 4)
+            .is_some());
+    }
+
+    #[test]
+    fn test_account_selector_prefers_healthy_accounts() {
+        let tracker = RateLimitTracker::new();
+        let accounts: Vec<AccountKey> = ["acc_ok", "acc_flaky", "acc_locked"]
+            .iter()
+            .map(|id| AccountKey::global(id))
+            .collect();
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        // acc_flaky: 9 次失败后解锁 (计数保留)，权重 0.1
+        for _ in 0..9 {
+            tracker.parse_from_error(
+                &AccountKey::global("acc_flaky"),
+                429,
+                None,
+                quota_body,
+                None,
+                &[60],
+            );
+        }
+        tracker.clear(&AccountKey::global("acc_flaky"));
+        tracker.parse_from_error(
+            &AccountKey::global("acc_locked"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+
+        let selector = AccountSelector::new(&accounts, &tracker);
+        let mut ok_count = 0;
+        for _ in 0..1000 {
+            match selector
+                .next_by_priority(None)
+                .map(|a| a.account_id.as_str())
+            {
+                Some("acc_ok") => ok_count += 1,
+                Some("acc_flaky") => {}
+                other => panic!("unexpected selection: {:?}", other),
+            }
+        }
+        // 期望约 91%
+        assert!(ok_count > 800, "healthy account picked {} / 1000", ok_count);
+
+        tracker.parse_from_error(
+            &AccountKey::global("acc_ok"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc_flaky"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
+        );
+        assert!(selector.next_by_priority(None).is_none());
+    }
+
+    #[test]
+    fn test_parse_fractional_try_again_seconds() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(
+            tracker.parse_retry_time_from_body("Rate limited. Try again in 1.5s"),
+            Some(2)
+        );
+        assert_eq!(
+            tracker.parse_retry_time_from_body("Please retry after 0.8 seconds"),
+            Some(1)
+        );
+        assert_eq!(
+            tracker.parse_retry_time_from_body("Try again in 2m 30.2s"),
+            Some(151)
+        );
+        assert_eq!(
+            tracker.parse_retry_time_from_body("Try again in 30s"),
+            Some(30)
+        );
+
+        // 最终锁定仍受 2 秒下限约束
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_frac"),
+                429,
+                None,
+                "Try again in 1.5s",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 2);
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_frac2"),
+                429,
+                None,
+                "Try again in 0.3s",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 2);
+    }
+
+    #[tokio::test]
+    async fn test_parse_from_error_response_extracts_status_header_and_body() {
+        let tracker = RateLimitTracker::new();
+        let http_response = axum::http::Response::builder()
+            .status(429)
+            .header("Retry-After", "42")
+            .body(r#"{"error":{"details":[{"reason":"RATE_LIMIT_EXCEEDED"}]}}"#)
+            .unwrap();
+
+        let info = tracker
+            .parse_from_error_response(
+                &AccountKey::global("acc_resp"),
+                http_response.into(),
+                None,
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 42);
+        assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+
+        let ok_response = axum::http::Response::builder()
+            .status(200)
+            .body("{}")
+            .unwrap();
+        assert!(tracker
+            .parse_from_error_response(
+                &AccountKey::global("acc_resp_ok"),
+                ok_response.into(),
+                None,
+                &[]
+            )
+            .await
+            .is_none());
+    }
+
+    #[test]
+    fn test_record_error_tries_headers_then_body_then_default() {
+        let tracker = RateLimitTracker::new();
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let accounts = ["acc_ctx1", "acc_ctx2", "acc_ctx3", "acc_ctx4"].map(AccountKey::global);
+        let ctx = |account, headers, body| ErrorContext {
+            account,
+            status: 429,
+            headers,
+            body,
+            model: None,
+            backoff_steps: &[60],
+        };
+
+        // Retry-After 为 HTTP-date
+        let mut headers = HeaderMap::new();
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(120)).to_rfc2822();
+        headers.insert("retry-after", date.parse().unwrap());
+        let info = tracker
+            .record_error(ctx(&accounts[0], &headers, quota_body))
+            .unwrap();
+        assert!(info.retry_after_sec >= 118 && info.retry_after_sec <= 120);
+
+        // 无 Retry-After 时使用 x-ratelimit 头
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset-requests", "45s".parse().unwrap());
+        let info = tracker
+            .record_error(ctx(&accounts[1], &headers, quota_body))
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 45);
+
+        // 无响应头时使用 body，再退回默认阶梯
+        let empty = HeaderMap::new();
+        let info = tracker
+            .record_error(ctx(&accounts[2], &empty, "Try again in 30s"))
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 30);
+        let info = tracker
+            .record_error(ctx(&accounts[3], &empty, quota_body))
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 60);
+
+        let history = tracker.history(&AccountKey::global("acc_ctx2"));
+        assert_eq!(history[0].source, LockoutSource::Header);
+    }
+
+    #[test]
+    fn test_optimistic_reset_records_detail() {
+        let tracker = RateLimitTracker::new();
+        assert!(tracker.last_optimistic_reset_detail().is_none());
+
+        let now = SystemTime::now();
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_or1"),
+            now + Duration::from_secs(10),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_or2"),
+            now + Duration::from_secs(300),
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-pro".to_string()),
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_or2"),
+            now + Duration::from_secs(60),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        // 已过期的记录不计入
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_or3"),
+            now - Duration::from_secs(5),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+
+        tracker.clear_all();
+        let detail = tracker.last_optimistic_reset_detail().unwrap();
+        assert_eq!(detail.accounts_affected, 2);
+        assert!(detail.min_wait_at_reset >= 9 && detail.min_wait_at_reset <= 10);
+        assert!(detail.max_wait_at_reset >= 299 && detail.max_wait_at_reset <= 300);
+        assert!(detail.ts >= now);
+    }
+
+    #[test]
+    fn test_set_lockout_until_iso_guards_absurd_times() {
+        let tracker = RateLimitTracker::new();
+        let ceiling = tracker.backoff_config().max_lockout_duration.as_secs();
+
+        assert!(tracker.set_lockout_until_iso(
+            &AccountKey::global("acc_9999"),
+            "9999-12-31T23:59:59Z",
+            RateLimitReason::QuotaExhausted,
+            None
+        ));
+        let wait = tracker.get_remaining_wait(&AccountKey::global("acc_9999"), None);
+        assert!(wait > 0 && wait <= ceiling);
+
+        // 溢出或早于 1970 的时间被拒绝 (不 panic)
+        for bad in ["18446744073709551615", "-5", "1960-01-01T00:00:00Z"] {
+            assert!(!tracker.set_lockout_until_iso(
+                &AccountKey::global("acc_bad_ts"),
+                bad,
+                RateLimitReason::QuotaExhausted,
+                None
+            ));
+        }
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_bad_ts"), None));
+    }
+
+    #[test]
+    fn test_implausible_retry_after_falls_back_to_default() {
+        let tracker = RateLimitTracker::new();
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_skew"),
+                429,
+                Some("999999999"),
+                quota_body,
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 60);
+        assert_eq!(tracker.implausible_retry_count(), 1);
+        assert_eq!(
+            tracker.history(&AccountKey::global("acc_skew"))[0].source,
+            LockoutSource::Default
+        );
+
+        // 合理范围内的值照常使用
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_skew2"),
+                429,
+                Some("3600"),
+                quota_body,
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 3600);
+        assert_eq!(tracker.implausible_retry_count(), 1);
+    }
+
+    #[test]
+    fn test_failure_rate_and_rate_aware_backoff() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            min_failure_rate_for_escalation: 0.1,
+            ..BackoffConfig::default()
+        });
+        assert_eq!(tracker.failure_rate(&AccountKey::global("acc_rate")), 0.0);
+
+        for _ in 0..98 {
+            tracker.record_request_outcome(&AccountKey::global("acc_rate"), true);
+        }
+        tracker.record_request_outcome(&AccountKey::global("acc_rate"), false);
+        tracker.record_request_outcome(&AccountKey::global("acc_rate"), false);
+        assert!((tracker.failure_rate(&AccountKey::global("acc_rate")) - 0.02).abs() < 1e-9);
+        let stats = tracker
+            .account_stats(&AccountKey::global("acc_rate"))
+            .unwrap();
+        assert_eq!((stats.success_count, stats.failure_count), (98, 2));
+
+        // 失败率 2% 低于阈值: 连续失败也停留在第一档
+        let body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let steps = [60, 300, 1800];
+        for _ in 0..3 {
+            let info = tracker
+                .parse_from_error(
+                    &AccountKey::global("acc_rate"),
+                    429,
+                    None,
+                    body,
+                    None,
+                    &steps,
+                )
+                .unwrap();
+            assert_eq!(info.retry_after_sec, 60);
+        }
+
+        // 失败率高的账号照常升级
+        tracker.record_request_outcome(&AccountKey::global("acc_bad_rate"), true);
+        for _ in 0..9 {
+            tracker.record_request_outcome(&AccountKey::global("acc_bad_rate"), false);
+        }
+        tracker.parse_from_error(
+            &AccountKey::global("acc_bad_rate"),
+            429,
+            None,
+            body,
+            None,
+            &steps,
+        );
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_bad_rate"),
+                429,
+                None,
+                body,
+                None,
+                &steps,
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 300);
+    }
+
+    #[test]
+    fn test_rate_limit_reason_serde_round_trip() {
+        let cases = [
+            (RateLimitReason::QuotaExhausted, "quota_exhausted"),
+            (RateLimitReason::RateLimitExceeded, "rate_limit_exceeded"),
+            (
+                RateLimitReason::ModelCapacityExhausted,
+                "model_capacity_exhausted",
+            ),
+            (RateLimitReason::ServerError, "server_error"),
+            (RateLimitReason::Overloaded, "overloaded"),
+            (RateLimitReason::ModelNotFound, "model_not_found"),
+            (RateLimitReason::PermanentlyDisabled, "permanently_disabled"),
+            (RateLimitReason::Unknown, "unknown"),
+        ];
+        for (reason, name) in cases {
+            let json = serde_json::to_string(&reason).unwrap();
+            assert_eq!(json, format!("\"{}\"", name));
+            let parsed: RateLimitReason = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, reason);
+        }
+        assert!(serde_json::from_str::<RateLimitReason>("\"QuotaExhausted\"").is_err());
+    }
+
+    #[test]
+    fn test_statsd_metrics_lines() {
+        let tracker = RateLimitTracker::new();
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        tracker.parse_from_error(
+            &AccountKey::global("acc1"),
+            429,
+            Some("60"),
+            quota,
+            None,
+            &[],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc2"),
+            429,
+            Some("60"),
+            quota,
+            None,
+            &[],
+        );
+        tracker.parse_from_error(&AccountKey::global("acc3"), 503, None, "", None, &[]);
+
+        let lines = tracker.statsd_metrics("antigravity");
+        assert!(lines
+            .contains(&"antigravity.rate_limit.lockouts:2|c|#reason:quota_exhausted".to_string()));
+        assert!(
+            lines.contains(&"antigravity.rate_limit.lockouts:1|c|#reason:server_error".to_string())
+        );
+        assert!(lines.contains(&"antigravity.rate_limit.active_limits:3|g".to_string()));
+        for line in &lines {
+            let (name, rest) = line.split_once(':').unwrap();
+            assert!(name.starts_with("antigravity.rate_limit."));
+            let mut parts = rest.split('|');
+            assert!(parts.next().unwrap().parse::<u64>().is_ok());
+            assert!(matches!(parts.next(), Some("c") | Some("g")));
+        }
+
+        // 计数器导出后清零
+        let lines = tracker.statsd_metrics("antigravity");
+        assert!(!lines.iter().any(|l| l.contains("lockouts")));
+    }
+
+    #[test]
+    fn test_mark_success_auto_revive_policy() {
+        // 默认: 成功不会解除失效标记
+        let tracker = RateLimitTracker::new();
+        tracker.mark_dead(&AccountKey::global("acc1"));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc1"), None));
+        tracker.mark_success(&AccountKey::global("acc1"));
+        assert!(tracker.is_dead(&AccountKey::global("acc1")));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc1"), None));
+        assert!(tracker.revive(&AccountKey::global("acc1")));
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc1"), None));
+
+        // 开启后成功即复活
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            auto_revive_on_success: true,
+            ..BackoffConfig::default()
+        });
+        tracker.mark_dead(&AccountKey::global("acc1"));
+        tracker.mark_success(&AccountKey::global("acc1"));
+        assert!(!tracker.is_dead(&AccountKey::global("acc1")));
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc1"), None));
+    }
+
+    #[test]
+    fn test_auto_revive_keeps_operator_quarantine_and_permanent_disable() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            auto_revive_on_success: true,
+            max_failure_count: 2,
+            ..BackoffConfig::default()
+        });
+
+        // 人工隔离 (如密钥被吊销) 不会因一次成功而解除
+        tracker.quarantine(&AccountKey::global("acc_revoked"), "revoked key");
+        tracker.mark_success(&AccountKey::global("acc_revoked"));
+        assert!(tracker.is_quarantined(&AccountKey::global("acc_revoked")));
+        assert!(!tracker.is_dead(&AccountKey::global("acc_revoked")));
+        assert!(!tracker.revive(&AccountKey::global("acc_revoked")));
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc_revoked"), None),
+            u64::MAX
+        );
+
+        // 永久停用只能由 re_enable 解除
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        for _ in 0..3 {
+            tracker.parse_from_error(
+                &AccountKey::global("acc_disabled"),
+                429,
+                None,
+                quota,
+                None,
+                &[60],
+            );
+        }
+        assert!(tracker.is_permanently_disabled(&AccountKey::global("acc_disabled")));
+        tracker.mark_success(&AccountKey::global("acc_disabled"));
+        assert!(tracker.is_permanently_disabled(&AccountKey::global("acc_disabled")));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_disabled"), None));
+        assert!(tracker.re_enable(&AccountKey::global("acc_disabled")));
+        assert!(!tracker.is_permanently_disabled(&AccountKey::global("acc_disabled")));
+    }
+
+    #[test]
+    fn test_accounts_by_reason_and_unlock() {
+        let tracker = RateLimitTracker::new();
+        let future = SystemTime::now() + Duration::from_secs(300);
+        let quota = RateLimitReason::QuotaExhausted;
+        tracker.set_lockout_until(&AccountKey::global("acc1"), future, quota, None);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc1"),
+            future,
+            quota,
+            Some("gemini-pro".to_string()),
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc2"),
+            future,
+            quota,
+            Some("claude".to_string()),
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc3"),
+            future,
+            RateLimitReason::ServerError,
+            None,
+        );
+
+        assert_eq!(
+            tracker.get_accounts_by_reason(quota),
+            vec!["acc1".to_string(), "acc2".to_string()]
+        );
+        assert!(tracker
+            .get_accounts_by_reason(RateLimitReason::Overloaded)
+            .is_empty());
+
+        assert_eq!(tracker.unlock_by_reason(quota), 3);
+        assert!(tracker.get_accounts_by_reason(quota).is_empty());
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc1"), Some("gemini-pro")));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc3"), None));
+    }
+
+    #[test]
+    fn test_soonest_reset() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.soonest_reset(None), None);
+
+        let now = SystemTime::now();
+        let reason = RateLimitReason::RateLimitExceeded;
+        tracker.set_lockout_until(
+            &AccountKey::global("acc1"),
+            now + Duration::from_secs(300),
+            reason,
+            None,
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc2"),
+            now + Duration::from_secs(120),
+            reason,
+            Some("gemini-pro".to_string()),
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc3"),
+            now + Duration::from_secs(60),
+            reason,
+            Some("claude".to_string()),
+        );
+        // 已过期的记录被跳过
+        tracker.limits.insert(
+            "acc4".to_string(),
+            RateLimitInfo {
+                reset_time: now - Duration::from_secs(10),
+                retry_after_sec: 10,
+                detected_at: now - Duration::from_secs(20),
+                reason,
+                model: None,
+                quota_value: None,
+                limit_value: None,
+                quota_metric: None,
+                subreason: None,
+                quota_id: None,
+            },
+        );
+
+        let (key, wait) = tracker.soonest_reset(None).unwrap();
+        assert_eq!(key, "acc3:claude");
+        assert!(wait > 50 && wait <= 60);
+
+        let (key, wait) = tracker.soonest_reset(Some("gemini-pro")).unwrap();
+        assert_eq!(key, "acc2:gemini-pro");
+        assert!(wait > 110 && wait <= 120);
+
+        let (key, _) = tracker.soonest_reset(Some("other-model")).unwrap();
+        assert_eq!(key, "acc1");
+    }
+
+    #[test]
+    fn test_quarantine_lifecycle() {
+        let tracker = RateLimitTracker::new();
+        let future = SystemTime::now() + Duration::from_secs(60);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc1"),
+            future,
+            RateLimitReason::ServerError,
+            None,
+        );
+        tracker.quarantine(&AccountKey::global("acc1"), "revoked key");
+        tracker.quarantine(&AccountKey::global("acc2"), "project disabled");
+
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc2"), None),
+            u64::MAX
+        );
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc2"), Some("gemini-pro")),
+            u64::MAX
+        );
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc2"), None));
+        assert_eq!(
+            tracker
+                .quarantine_info(&AccountKey::global("acc2"))
+                .unwrap()
+                .reason,
+            "project disabled"
+        );
+
+        // clear_all 只清除定时锁，不解除隔离
+        tracker.clear_all();
+        assert!(tracker.is_quarantined(&AccountKey::global("acc1")));
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc1"), None),
+            u64::MAX
+        );
+
+        // 解除隔离后回到定时锁的状态 (已被 clear_all 清除)
+        assert!(tracker.unquarantine(&AccountKey::global("acc1")));
+        assert!(!tracker.unquarantine(&AccountKey::global("acc1")));
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc1"), None),
+            0
+        );
+
+        // 解除隔离后仍有定时锁时照常等待
+        tracker.set_lockout_until(
+            &AccountKey::global("acc2"),
+            future,
+            RateLimitReason::ServerError,
+            None,
+        );
+        tracker.unquarantine(&AccountKey::global("acc2"));
+        let wait = tracker.get_remaining_wait(&AccountKey::global("acc2"), None);
+        assert!(wait > 0 && wait <= 60);
+    }
+
+    #[test]
+    fn test_recommended_global_concurrency_shrinks() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            base_concurrency: 4,
+            ..BackoffConfig::default()
+        });
+        let accounts: Vec<AccountKey> = ["acc1", "acc2", "acc3"]
+            .iter()
+            .map(|id| AccountKey::global(id))
+            .collect();
+        assert_eq!(
+            recommended_global_concurrency(&accounts, &tracker, None),
+            12
+        );
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        // acc1 失败一次后被锁定，不再贡献并发
+        tracker.parse_from_error(
+            &AccountKey::global("acc1"),
+            429,
+            None,
+            quota_body,
+            None,
+            &[60],
+        );
+        assert_eq!(recommended_global_concurrency(&accounts, &tracker, None), 8);
+
+        // acc2 的锁解除后失败计数仍在，并发上限收紧为 4 / 2
+        tracker.parse_from_error(
+            &AccountKey::global("acc2"),
+            429,
+            None,
+            quota_body,
+            None,
+            &[60],
+        );
+        tracker.clear(&AccountKey::global("acc2"));
+        assert_eq!(recommended_global_concurrency(&accounts, &tracker, None), 6);
+
+        // 模型级锁只影响该模型
+        tracker.set_lockout_until(
+            &AccountKey::global("acc3"),
+            SystemTime::now() + Duration::from_secs(60),
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-pro".to_string()),
+        );
+        assert_eq!(
+            recommended_global_concurrency(&accounts, &tracker, Some("gemini-pro")),
+            2
+        );
+    }
+
+    #[test]
+    fn test_max_failure_count_permanently_disables() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            max_failure_count: 3,
+            ..BackoffConfig::default()
+        });
+        let body = r#"{"error":{"details":[{"reason":"RATE_LIMIT_EXCEEDED"}]}}"#;
+        for _ in 0..3 {
+            let info = tracker
+                .parse_from_error(
+                    &AccountKey::global("acc_perm"),
+                    429,
+                    None,
+                    body,
+                    None,
+                    &[60],
+                )
+                .unwrap();
+            assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+        }
+        assert!(!tracker.is_permanently_disabled(&AccountKey::global("acc_perm")));
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_perm"),
+                429,
+                None,
+                body,
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::PermanentlyDisabled);
+        assert!(tracker.is_permanently_disabled(&AccountKey::global("acc_perm")));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_perm"), None));
+
+        // 乐观重置不会恢复永久停用的账号
+        tracker.clear_all();
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_perm"), None));
+
+        assert!(tracker.re_enable(&AccountKey::global("acc_perm")));
+        assert!(!tracker.re_enable(&AccountKey::global("acc_perm")));
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_perm"), None));
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_perm")), 0);
+    }
+
+    #[test]
+    fn test_rate_limit_info_age_accessors() {
+        let now = SystemTime::now();
+        let mut info = RateLimitInfo {
+            reset_time: now + Duration::from_secs(60),
+            retry_after_sec: 60,
+            detected_at: now - Duration::from_secs(30),
+            reason: RateLimitReason::RateLimitExceeded,
+            model: None,
+            quota_value: None,
+            limit_value: None,
+            quota_metric: None,
+            subreason: None,
+            quota_id: None,
+        };
+        assert_eq!(info.time_since_detected_at(now), Duration::from_secs(30));
+        assert_eq!(info.time_until_reset_at(now), Some(Duration::from_secs(60)));
+
+        info.reset_time = now - Duration::from_secs(1);
+        assert_eq!(info.time_until_reset_at(now), None);
+    }
+
+    #[test]
+    fn test_json_without_error_wrapper_falls_back() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{"message":"Too Many Requests, retry after 15 seconds"}"#;
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_plain"), 429, None, body, None, &[])
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+        assert_eq!(info.retry_after_sec, 15);
+
+        // 顶层 retry_after 字段同样识别
+        let body = r#"{"message":"slow down","retry_after":20}"#;
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_plain2"),
+                429,
+                None,
+                body,
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 20);
+    }
+
+    #[test]
+    fn test_rate_limit_subreason_tpm_vs_rpm() {
+        let tracker = RateLimitTracker::new();
+        let tpm = r#"{"error":{"message":"Rate limit exceeded: tokens per minute (TPM) for model","details":[{"reason":"RATE_LIMIT_EXCEEDED"}]}}"#;
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_tpm"), 429, None, tpm, None, &[])
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+        assert_eq!(info.subreason, Some(RateLimitSubreason::TokensPerMinute));
+        assert_eq!(info.retry_after_sec, TPM_LOCKOUT_SECS);
+
+        let rpm = r#"{"error":{"message":"Too many requests per minute (RPM)","details":[{"reason":"RATE_LIMIT_EXCEEDED"}]}}"#;
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_rpm"), 429, None, rpm, None, &[])
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+        assert_eq!(info.subreason, Some(RateLimitSubreason::RequestsPerMinute));
+        assert_eq!(info.retry_after_sec, RPM_LOCKOUT_SECS);
+
+        // 其他原因不带细分类型
+        let quota =
+            r#"{"error":{"message":"quota per minute","details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_q"), 429, None, quota, None, &[60])
+            .unwrap();
+        assert_eq!(info.subreason, None);
+    }
+
+    #[test]
+    fn test_account_note_in_debug_json() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.get_note(&AccountKey::global("acc_note")), None);
+
+        tracker.set_note(
+            &AccountKey::global("acc_note"),
+            "试用账号，1 月 31 日到期".to_string(),
+        );
+        assert_eq!(
+            tracker.get_note(&AccountKey::global("acc_note")).as_deref(),
+            Some("试用账号，1 月 31 日到期")
+        );
+
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_note"),
+            SystemTime::now() + Duration::from_secs(60),
+            RateLimitReason::QuotaExhausted,
+            None,
+        );
+        let json = tracker.account_debug_json(&AccountKey::global("acc_note"));
+        assert_eq!(json["note"], "试用账号，1 月 31 日到期");
+        assert_eq!(json["reason"], "quota_exhausted");
+        assert_eq!(json["rate_limited"], true);
+
+        tracker.set_note(&AccountKey::global("acc_note"), String::new());
+        assert!(tracker.account_debug_json(&AccountKey::global("acc_note"))["note"].is_null());
+    }
+
+    #[test]
+    fn test_is_model_locked_ignores_account_lock() {
+        let tracker = RateLimitTracker::new();
+        let future = SystemTime::now() + Duration::from_secs(120);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_m"),
+            future,
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-preview".to_string()),
+        );
+
+        // 账号本身可用，只有预览模型被锁
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_m"), None));
+        assert!(tracker.is_model_locked(&AccountKey::global("acc_m"), "gemini-preview"));
+        assert!(!tracker.is_model_locked(&AccountKey::global("acc_m"), "gemini-pro"));
+
+        // 账号级锁不影响 is_model_locked
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_a"),
+            future,
+            RateLimitReason::ServerError,
+            None,
+        );
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_a"), Some("gemini-pro")));
+        assert!(!tracker.is_model_locked(&AccountKey::global("acc_a"), "gemini-pro"));
+    }
+
+    #[test]
+    fn test_backoff_config_json_round_trip() {
+        let config = BackoffConfig {
+            jitter_ratio: 0.2,
+            tie_break: TieBreak::Hashed,
+            daily_reset_time: Some((0, 30)),
+            max_failure_count: 20,
+            ..BackoffConfig::default()
+        };
+        let json = config.to_json_str();
+        assert!(json.contains("\"hashed\""));
+
+        let parsed = BackoffConfig::from_json_str(&json).unwrap();
+        assert_eq!(parsed.jitter_ratio, 0.2);
+        assert_eq!(parsed.tie_break, TieBreak::Hashed);
+        assert_eq!(parsed.daily_reset_time, Some((0, 30)));
+        assert_eq!(parsed.max_failure_count, 20);
+        assert_eq!(parsed.server_error_lockout_secs.get(&503), Some(&8));
+
+        // 缺省字段取默认值
+        let partial = BackoffConfig::from_json_str(r#"{"base_concurrency": 2}"#).unwrap();
+        assert_eq!(partial.base_concurrency, 2);
+        assert_eq!(partial.history_capacity, 20);
+
+        // 非法取值被拒绝
+        assert!(BackoffConfig::from_json_str(r#"{"jitter_ratio": -1.0}"#).is_err());
+        assert!(BackoffConfig::from_json_str(r#"{"daily_reset_time": [24, 0]}"#).is_err());
+        assert!(BackoffConfig::from_json_str("not json").is_err());
+    }
+
+    #[test]
+    fn test_backoff_config_serde_validates() {
+        // 不经 from_json_str，直接走 serde 也会校验
+        let err = serde_json::from_str::<BackoffConfig>(r#"{"jitter_ratio": -1.0}"#).unwrap_err();
+        assert!(err.to_string().contains("jitter_ratio"));
+        assert!(serde_json::from_str::<BackoffConfig>(
+            r#"{"max_lockout_duration": {"secs": 0, "nanos": 0}}"#
+        )
+        .is_err());
+
+        #[derive(Deserialize)]
+        struct Wrapper {
+            backoff: BackoffConfig,
+        }
+        assert!(
+            serde_json::from_str::<Wrapper>(r#"{"backoff": {"daily_reset_time": [8, 60]}}"#)
+                .is_err()
+        );
+        let ok: Wrapper = serde_json::from_str(r#"{"backoff": {"base_concurrency": 3}}"#).unwrap();
+        assert_eq!(ok.backoff.base_concurrency, 3);
+
+        let json = serde_json::to_string(&BackoffConfig::default()).unwrap();
+        assert!(serde_json::from_str::<BackoffConfig>(&json).is_ok());
+    }
+
+    #[test]
+    fn test_get_next_available_time() {
+        let tracker = RateLimitTracker::new();
+        let before = SystemTime::now();
+        let next = tracker.get_next_available_time(&AccountKey::global("acc_n"), None);
+        assert!(next >= before && next <= SystemTime::now());
+
+        let account_reset = SystemTime::now() + Duration::from_millis(30_500);
+        let model_reset = SystemTime::now() + Duration::from_millis(90_250);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_n"),
+            account_reset,
+            RateLimitReason::ServerError,
+            None,
+        );
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_n"),
+            model_reset,
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-pro".to_string()),
+        );
+
+        // 保留亚秒精度，取两者中较晚者
+        assert_eq!(
+            tracker.get_next_available_time(&AccountKey::global("acc_n"), None),
+            account_reset
+        );
+        assert_eq!(
+            tracker.get_next_available_time(&AccountKey::global("acc_n"), Some("gemini-pro")),
+            model_reset
+        );
+
+        tracker.quarantine(&AccountKey::global("acc_n"), "revoked");
+        assert!(
+            tracker.get_next_available_time(&AccountKey::global("acc_n"), None)
+                > SystemTime::now() + Duration::from_secs(365 * 86400)
+        );
+    }
+
+    #[test]
+    fn test_limit_jump_resets_failure_count() {
+        let tracker = RateLimitTracker::new();
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        for _ in 0..3 {
+            tracker.parse_from_error(
+                &AccountKey::global("acc_tier"),
+                429,
+                None,
+                quota_body,
+                None,
+                &[60],
+            );
+        }
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_tier")), 3);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-limit", "60".parse().unwrap());
+        assert!(!tracker.observe_limit_headers(&AccountKey::global("acc_tier"), &headers));
+
+        // 小幅波动不视为升级
+        headers.insert("x-ratelimit-limit", "90".parse().unwrap());
+        assert!(!tracker.observe_limit_headers(&AccountKey::global("acc_tier"), &headers));
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_tier")), 3);
+
+        headers.insert("x-ratelimit-limit", "1000".parse().unwrap());
+        assert!(tracker.observe_limit_headers(&AccountKey::global("acc_tier"), &headers));
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_tier")), 0);
+    }
+
+    #[test]
+    fn test_reason_ladders_defaults_match_previous_behavior() {
+        let ladders = ReasonLadders::default();
+        assert_eq!(ladder_step(&ladders.model_capacity_exhausted, 1, 0), 5);
+        assert_eq!(ladder_step(&ladders.model_capacity_exhausted, 2, 0), 10);
+        assert_eq!(ladder_step(&ladders.model_capacity_exhausted, 9, 0), 15);
+        assert_eq!(ladder_step(&ladders.overloaded, 3, 0), 10);
+        assert_eq!(ladder_step(&ladders.rate_limit_exceeded, 4, 0), 5);
+        assert_eq!(ladder_step(&[], 1, 42), 42);
+    }
+
+    #[test]
+    fn test_reason_ladders_configurable_per_reason() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            ladders: ReasonLadders {
+                rate_limit_exceeded: vec![7, 14],
+                model_capacity_exhausted: vec![3],
+                overloaded: vec![1, 4],
+                unknown: vec![30],
+                ..ReasonLadders::default()
+            },
+            ..BackoffConfig::default()
+        });
+
+        let rate = r#"{"error":{"details":[{"reason":"RATE_LIMIT_EXCEEDED"}]}}"#;
+        let first = tracker
+            .parse_from_error(&AccountKey::global("acc_r"), 429, None, rate, None, &[])
+            .unwrap();
+        let second = tracker
+            .parse_from_error(&AccountKey::global("acc_r"), 429, None, rate, None, &[])
+            .unwrap();
+        assert_eq!((first.retry_after_sec, second.retry_after_sec), (7, 14));
+
+        let capacity = r#"{"error":{"details":[{"reason":"MODEL_CAPACITY_EXHAUSTED"}]}}"#;
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_c"), 429, None, capacity, None, &[])
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 3);
+
+        let overloaded =
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        let first = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_o"),
+                529,
+                None,
+                overloaded,
+                None,
+                &[],
+            )
+            .unwrap();
+        let second = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_o"),
+                529,
+                None,
+                overloaded,
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!((first.retry_after_sec, second.retry_after_sec), (1, 4));
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_u"),
+                429,
+                None,
+                "something odd",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 30);
+    }
+
+    #[test]
+    fn test_quota_ladder_from_vec() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig::from(vec![120, 600]));
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        // 调用方未传阶梯时使用配置
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_q"), 429, None, quota, None, &[])
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 120);
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_q"), 429, None, quota, None, &[])
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 600);
+
+        // 调用方传入的阶梯优先
+        let info = tracker
+            .parse_from_error(&AccountKey::global("acc_q2"), 429, None, quota, None, &[60])
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 60);
+    }
+
+    #[test]
+    fn test_history_capacity_applies_at_runtime() {
+        let tracker = RateLimitTracker::new();
+        for i in 0..5 {
+            let retry = (10 + i).to_string();
+            tracker.parse_from_error(
+                &AccountKey::global("acc_cap"),
+                429,
+                Some(retry.as_str()),
+                "",
+                None,
+                &[],
+            );
+        }
+        assert_eq!(tracker.history(&AccountKey::global("acc_cap")).len(), 5);
+
+        // 缩小容量时保留最新的记录
+        tracker.set_backoff_config(BackoffConfig {
+            history_capacity: 2,
+            ..BackoffConfig::default()
+        });
+        let events = tracker.history(&AccountKey::global("acc_cap"));
+        assert_eq!(
+            events.iter().map(|e| e.retry_sec).collect::<Vec<_>>(),
+            vec![13, 14]
+        );
+
+        // 0 关闭历史且不再占用存储
+        tracker.set_backoff_config(BackoffConfig {
+            history_capacity: 0,
+            ..BackoffConfig::default()
+        });
+        tracker.parse_from_error(
+            &AccountKey::global("acc_cap2"),
+            429,
+            Some("30"),
+            "",
+            None,
+            &[],
+        );
+        assert!(tracker.history(&AccountKey::global("acc_cap")).is_empty());
+        assert_eq!(tracker.memory_estimate().history_entries, 0);
+        assert!(tracker.history.is_empty());
+    }
+
+    #[test]
+    fn test_all_locked() {
+        let tracker = RateLimitTracker::new();
+        let candidates: Vec<AccountKey> = ["acc1", "acc2"]
+            .iter()
+            .map(|id| AccountKey::global(id))
+            .collect();
+        assert_eq!(tracker.all_locked(&[], None), None);
+        assert_eq!(tracker.all_locked(&candidates, None), None);
+
+        let now = SystemTime::now();
+        let reason = RateLimitReason::RateLimitExceeded;
+        tracker.set_lockout_until(
+            &AccountKey::global("acc1"),
+            now + Duration::from_secs(300),
+            reason,
+            None,
+        );
+        // 部分锁定
+        assert_eq!(tracker.all_locked(&candidates, None), None);
+
+        tracker.set_lockout_until(
+            &AccountKey::global("acc2"),
+            now + Duration::from_secs(30),
+            reason,
+            None,
+        );
+        let min_wait = tracker.all_locked(&candidates, None).unwrap();
+        assert!(min_wait > 25 && min_wait <= 30);
+    }
+
+    #[test]
+    fn test_quota_id_header_attached() {
+        let tracker = RateLimitTracker::new();
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", "30".parse().unwrap());
+        headers.insert(
+            "x-goog-quota-id",
+            "Requests per day per project".parse().unwrap(),
+        );
+        let info = tracker
+            .record_error(ErrorContext {
+                account: &AccountKey::global("acc_qid"),
+                status: 429,
+                headers: &headers,
+                body: "",
+                model: None,
+                backoff_steps: &[],
+            })
+            .unwrap();
+        assert_eq!(
+            info.quota_id.as_deref(),
+            Some("Requests per day per project")
+        );
+        assert_eq!(
+            tracker
+                .get(&AccountKey::global("acc_qid"))
+                .unwrap()
+                .quota_id
+                .as_deref(),
+            Some("Requests per day per project")
+        );
+        assert_eq!(
+            tracker.account_debug_json(&AccountKey::global("acc_qid"))["quota_id"],
+            "Requests per day per project"
+        );
+
+        // 没有该响应头时为 None
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_qid2"),
+                429,
+                Some("30"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.quota_id, None);
+    }
+
+    #[test]
+    fn test_consecutive_server_errors_escalate_soft_avoid() {
+        let tracker = RateLimitTracker::new();
+        let mut lockouts = Vec::new();
+        for _ in 0..10 {
+            let info = tracker
+                .parse_from_error(
+                    &AccountKey::global("acc_5xx"),
+                    503,
+                    None,
+                    "Service Unavailable",
+                    None,
+                    &[60],
+                )
+                .unwrap();
+            lockouts.push(info.retry_after_sec);
+        }
+        // 前 5 次保持 8 秒，之后逐次翻倍直到上限
+        assert_eq!(lockouts, vec![8, 8, 8, 8, 8, 16, 32, 64, 128, 256]);
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_5xx"),
+                503,
+                None,
+                "Service Unavailable",
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 300);
+
+        // 429 的退避阶梯不受 5xx 计数影响，仍从第一档开始
+        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_5xx"),
+                429,
+                None,
+                quota_body,
+                None,
+                &[60, 300],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 60);
+
+        // 成功后 5xx 计数清零
+        tracker.mark_success(&AccountKey::global("acc_5xx"));
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_5xx"),
+                503,
+                None,
+                "Service Unavailable",
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 8);
+    }
+
+    #[test]
+    fn test_classify_does_not_mutate_state() {
+        let tracker = RateLimitTracker::new();
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED","metadata":{"quotaResetDelay":"42s"}}]}}"#;
+
+        assert_eq!(
+            tracker.classify(429, None, quota),
+            (RateLimitReason::QuotaExhausted, Some(42))
+        );
+        assert_eq!(
+            tracker.classify(429, Some("30"), ""),
+            (RateLimitReason::Unknown, Some(30))
+        );
+        assert_eq!(
+            tracker.classify(503, None, ""),
+            (RateLimitReason::ServerError, Some(8))
+        );
+        assert_eq!(
+            tracker.classify(400, None, ""),
+            (RateLimitReason::Unknown, None)
+        );
+        for _ in 0..10 {
+            tracker.classify(503, None, "");
+            tracker.classify(429, None, quota);
+        }
+
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.failure_count_len(), 0);
+        assert!(tracker.overload_counts.is_empty());
+        assert!(tracker.server_error_counts.is_empty());
+        assert!(tracker.history.is_empty());
+    }
+
+    #[test]
+    fn test_account_policy_overrides_global_backoff() {
+        let tracker = RateLimitTracker::new();
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let global_steps = [60, 300, 1800];
+
+        tracker.set_account_policy(
+            &AccountKey::global("paid"),
+            BackoffConfig::from(vec![10, 20]),
+        );
+
+        let lock = |account: &str| {
+            tracker
+                .parse_from_error(
+                    &AccountKey::global(account),
+                    429,
+                    None,
+                    quota,
+                    None,
+                    &global_steps,
+                )
+                .unwrap()
+                .retry_after_sec
+        };
+        assert_eq!(lock("free"), 60);
+        assert_eq!(lock("free"), 300);
+        assert_eq!(lock("paid"), 10);
+        assert_eq!(lock("paid"), 20);
+
+        // 账号级最长锁定时长同样生效
+        tracker.set_account_policy(
+            &AccountKey::global("capped"),
+            BackoffConfig {
+                max_lockout_duration: Duration::from_secs(30),
+                ..BackoffConfig::default()
+            },
+        );
+        assert_eq!(lock("capped"), 30);
+
+        assert!(tracker.remove_account_policy(&AccountKey::global("paid")));
+        assert!(!tracker.remove_account_policy(&AccountKey::global("paid")));
+        assert_eq!(lock("paid"), 1800);
+    }
+
+    #[test]
+    fn test_parse_from_error_bytes_strips_bom_and_padding() {
+        let tracker = RateLimitTracker::new();
+        let mut body = b"\xEF\xBB\xBF".to_vec();
+        body.extend_from_slice(
+            br#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED","metadata":{"quotaResetDelay":"42s"}}]}}"#,
+        );
+        body.extend_from_slice(&[0, 0, 0]);
+
+        let info = tracker
+            .parse_from_error_bytes(
+                &AccountKey::global("acc_bom"),
+                429,
+                None,
+                &body,
+                None,
+                &[60],
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(info.retry_after_sec, 42);
+    }
+
+    #[test]
+    fn test_repeated_429_keeps_longer_lock_and_detected_at() {
+        let tracker = RateLimitTracker::new();
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let steps = [300, 10];
+
+        tracker.parse_from_error(
+            &AccountKey::global("acc_ext"),
+            429,
+            None,
+            quota,
+            None,
+            &steps,
+        );
+        let first = tracker.get(&AccountKey::global("acc_ext")).unwrap();
+
+        // 第二次计算出的锁更短: 保留原锁，失败计数照常累加
+        let (info, outcome) = tracker
+            .parse_from_error_with_outcome(
+                &AccountKey::global("acc_ext"),
+                429,
+                None,
+                quota,
+                None,
+                &steps,
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 10);
+        assert_eq!(outcome, LockOutcome::Ignored);
+        assert_eq!(tracker.failure_count(&AccountKey::global("acc_ext")), 2);
+        let stored = tracker.get(&AccountKey::global("acc_ext")).unwrap();
+        assert_eq!(stored.reset_time, first.reset_time);
+        assert_eq!(stored.detected_at, first.detected_at);
+
+        // 更长的锁延长到期时间，但不重置检测时间
+        std::thread::sleep(Duration::from_millis(10));
+        let (_, outcome) = tracker
+            .parse_from_error_with_outcome(
+                &AccountKey::global("acc_ext"),
+                429,
+                Some("600"),
+                quota,
+                None,
+                &steps,
+            )
+            .unwrap();
+        assert_eq!(outcome, LockOutcome::Extended);
+        let stored = tracker.get(&AccountKey::global("acc_ext")).unwrap();
+        assert!(stored.reset_time > first.reset_time);
+        assert_eq!(stored.detected_at, first.detected_at);
+    }
+
+    #[test]
+    fn test_health_check_levels_and_transitions() {
+        let tracker = RateLimitTracker::new();
+        let accounts: Vec<AccountKey> = ["h1", "h2", "h3"]
+            .iter()
+            .map(|id| AccountKey::global(id))
+            .collect();
+        let lock = |id: &str| {
+            tracker.set_lockout_until(
+                &AccountKey::global(id),
+                SystemTime::now() + Duration::from_secs(300),
+                RateLimitReason::QuotaExhausted,
+                None,
+            );
+        };
+
+        let report = tracker.health_check(&accounts);
+        assert_eq!(report.status, HealthStatus::Ok);
+        assert_eq!(report.previous_status, None);
+        assert_eq!(tracker.check_health_transition(&accounts), None);
+
+        lock("h1");
+        lock("h2");
+        // 查询不记录等级，也不报告变化
+        let report = tracker.health_check(&accounts);
+        assert_eq!(report.locked_accounts, 2);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.previous_status, None);
+        assert_eq!(*tracker.last_health_status.lock(), HealthStatus::Ok);
+
+        let report = tracker.check_health_transition(&accounts).unwrap();
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.previous_status, Some(HealthStatus::Ok));
+
+        // 等级未变化时不报告变化
+        assert_eq!(tracker.check_health_transition(&accounts), None);
+
+        // 模型级锁不算账号锁定，永久停用计入锁定
+        tracker.set_lockout_until(
+            &AccountKey::global("h3"),
+            SystemTime::now() + Duration::from_secs(300),
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-pro".to_string()),
+        );
+        assert_eq!(tracker.health_check(&accounts).locked_accounts, 2);
+        tracker.disable_permanently("h3", None);
+
+        let report = tracker.check_health_transition(&accounts).unwrap();
+        assert_eq!(report.status, HealthStatus::Critical);
+        assert_eq!(report.previous_status, Some(HealthStatus::Degraded));
+        assert_eq!(report.permanently_disabled, 1);
+
+        assert_eq!(tracker.health_check(&[]).status, HealthStatus::Ok);
+    }
+
+    #[test]
+    fn test_parse_iso8601_duration() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.parse_duration_string("PT42S"), Some(42));
+        assert_eq!(tracker.parse_duration_string("PT1H30M"), Some(5400));
+        assert_eq!(tracker.parse_duration_string("P1DT2H30M0S"), Some(95400));
+        assert_eq!(tracker.parse_duration_string("PT0.5S"), Some(1));
+        assert_eq!(tracker.parse_duration_string("P1W"), Some(604800));
+        assert_eq!(tracker.parse_duration_string("PT0S"), None);
+        assert_eq!(tracker.parse_duration_string("P1Y"), None);
+        assert_eq!(tracker.parse_duration_string("PT"), None);
+
+        // 非正式格式与 ISO 8601 混合的语料
+        let corpus = [
+            ("42s", 42),
+            ("PT42S", 42),
+            ("2h1m", 7260),
+            ("PT2H1M", 7260),
+            ("1d12h", 129600),
+            ("P1DT12H", 129600),
+            ("500ms", 1),
+            ("6m0s", 360),
+        ];
+        for (input, expected) in corpus {
+            assert_eq!(
+                tracker.parse_duration_string(input),
+                Some(expected),
+                "{input}"
+            );
+        }
+
+        let body = r#"{"error":{"details":[{"metadata":{"quotaResetDelay":"PT1M30S"}}]}}"#;
+        assert_eq!(tracker.parse_retry_time_from_body(body), Some(90));
+    }
+
+    #[test]
+    fn test_retry_info_detail_preferred() {
+        let tracker = RateLimitTracker::new();
+        let body = r#"{
+            "error": {
+                "code": 429,
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                        "violations": [{"quotaMetric": "generativelanguage.googleapis.com/generate_content_free_tier_requests"}],
+                        "metadata": {"quotaResetDelay": "120s"}
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                        "retryDelay": "30s"
+                    }
+                ]
+            }
+        }"#;
+        assert_eq!(tracker.parse_retry_time_from_body(body), Some(30));
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_retry_info"),
+                429,
+                None,
+                body,
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 30);
+    }
+
+    #[test]
+    fn test_configurable_min_retry_floor() {
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            min_retry_secs: 5,
+            ..BackoffConfig::default()
+        });
+
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_floor"),
+                429,
+                Some("1"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 5);
+
+        let retry_info = r#"{"error":{"details":[{"@type":"type.googleapis.com/google.rpc.RetryInfo","retryDelay":"1s"}]}}"#;
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_floor_body"),
+                429,
+                None,
+                retry_info,
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 5);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset-requests", "1s".parse().unwrap());
+        let info = tracker
+            .parse_from_headers(&AccountKey::global("acc_floor_hdr"), &headers, None)
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 5);
+
+        // 调低下限用于延迟敏感的场景
+        let tracker = RateLimitTracker::with_config(BackoffConfig {
+            min_retry_secs: 1,
+            ..BackoffConfig::default()
+        });
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_floor"),
+                429,
+                Some("1"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.retry_after_sec, 1);
+    }
+
+    #[test]
+    fn test_get_statistics() {
+        let tracker = RateLimitTracker::new();
+        assert_eq!(tracker.get_statistics(), AccountStatistics::default());
+
+        let lock = |id: &str, secs: u64, model: Option<&str>| {
+            tracker.set_lockout_until(
+                &AccountKey::global(id),
+                SystemTime::now() + Duration::from_secs(secs) + Duration::from_millis(500),
+                RateLimitReason::QuotaExhausted,
+                model.map(str::to_string),
+            );
+        };
+        lock("s1", 100, None);
+        lock("s2", 300, None);
+        lock("s3", 50, None);
+        lock("s4", 200, None);
+        // 同一账号的模型级锁更晚到期时以其为准
+        lock("s3", 400, Some("gemini-pro"));
+
+        let stats = tracker.get_statistics();
+        assert_eq!(stats.locked_count, 4);
+        assert_eq!(stats.min_remaining_secs, 100);
+        assert_eq!(stats.max_remaining_secs, 400);
+        assert_eq!(stats.min_account_id.as_deref(), Some("s1"));
+        assert_eq!(stats.max_account_id.as_deref(), Some("s3"));
+        assert_eq!(stats.mean_remaining_secs, 250.0);
+        assert_eq!(stats.median_remaining_secs, 250.0);
+    }
 
-        // 模式 3: "quota will reset in X seconds"
-        if let Ok(re) = Regex::new(r"(?i)quota will reset in (\d+) second") {
-            if let Some(caps) = re.captures(body) {
-                if let Ok(s) = caps[1].parse::<u64>() {
-                    return Some(s);
-                }
-            }
-        }
+    #[tokio::test]
+    async fn test_reason_change_broadcast() {
+        let tracker = RateLimitTracker::new();
+        let account = AccountKey::global("acc_rcb");
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        let mut rx = tracker.subscribe();
 
-        // 模式 4: OpenAI 风格的 "Retry after (\d+) seconds"
-        if let Ok(re) = Regex::new(r"(?i)retry after (\d+) second") {
-            if let Some(caps) = re.captures(body) {
-                if let Ok(s) = caps[1].parse::<u64>() {
-                    return Some(s);
-                }
-            }
-        }
+        tracker.parse_from_error(&account, 503, None, "", None, &[]);
+        assert_eq!(rx.recv().await.unwrap().kind, RateLimitUpdateKind::Locked);
 
-        // 模式 5: 括号形式 "(wait (\d+)s)"
-        if let Ok(re) = Regex::new(r"\(wait (\d+)s\)") {
-            if let Some(caps) = re.captures(body) {
-                if let Ok(s) = caps[1].parse::<u64>() {
-                    return Some(s);
-                }
+        tracker.parse_from_error(&account, 429, None, quota, None, &[7200]);
+        assert_eq!(rx.recv().await.unwrap().kind, RateLimitUpdateKind::Locked);
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update.account_id, "acc_rcb");
+        assert_eq!(
+            update.kind,
+            RateLimitUpdateKind::ReasonChanged {
+                from: RateLimitReason::ServerError,
+                to: RateLimitReason::QuotaExhausted,
             }
-        }
+        );
+        assert_eq!(update.reset_time, tracker.get(&account).unwrap().reset_time);
 
-        None
+        // 原因相同的延长只推送 Locked
+        tracker.parse_from_error(&account, 429, Some("9000"), quota, None, &[]);
+        assert_eq!(rx.recv().await.unwrap().kind, RateLimitUpdateKind::Locked);
+        assert!(rx.try_recv().is_err());
     }
 
-    /// 获取账号的限流信息
-    pub fn get(&self, account_id: &str) -> Option<RateLimitInfo> {
-        self.limits.get(account_id).map(|r| r.clone())
-    }
+    #[test]
+    fn test_reason_change_recorded_in_history() {
+        let tracker = RateLimitTracker::new();
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
 
-    /// 检查账号是否仍在限流中
-    /// 检查账号是否仍在限流中 (支持模型级)
-    pub fn is_rate_limited(&self, account_id: &str, model: Option<&str>) -> bool {
-        // Checking using get_remaining_wait which handles both global and model keys
-        self.get_remaining_wait(account_id, model) > 0
-    }
+        tracker.parse_from_error(&AccountKey::global("acc_rc"), 503, None, "", None, &[]);
+        tracker.parse_from_error(
+            &AccountKey::global("acc_rc"),
+            429,
+            None,
+            quota,
+            None,
+            &[7200],
+        );
 
-    /// 获取距离限流重置还有多少秒
-    pub fn get_reset_seconds(&self, account_id: &str) -> Option<u64> {
-        if let Some(info) = self.get(account_id) {
-            info.reset_time
-                .duration_since(SystemTime::now())
-                .ok()
-                .map(|d| d.as_secs())
-        } else {
+        let events = tracker.history(&AccountKey::global("acc_rc"));
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].previous_reason, None);
+        assert_eq!(events[1].reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(
+            events[1].previous_reason,
+            Some(RateLimitReason::ServerError)
+        );
+        assert_eq!(
+            tracker.get(&AccountKey::global("acc_rc")).unwrap().reason,
+            RateLimitReason::QuotaExhausted
+        );
+
+        // 原因相同的延长不算原因变化
+        tracker.parse_from_error(
+            &AccountKey::global("acc_rc"),
+            429,
+            Some("9000"),
+            quota,
+            None,
+            &[],
+        );
+        assert_eq!(
+            tracker.history(&AccountKey::global("acc_rc"))[2].previous_reason,
             None
-        }
+        );
     }
 
-    /// 清除过期的限流记录
-    #[allow(dead_code)]
-    pub fn cleanup_expired(&self) -> usize {
-        let now = SystemTime::now();
-        let mut count = 0;
-
-        self.limits.retain(|_k, v| {
-            if v.reset_time <= now {
-                count += 1;
-                false
-            } else {
-                true
-            }
-        });
+    #[test]
+    fn test_metrics_text_prometheus_format() {
+        let tracker = RateLimitTracker::new();
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        tracker.parse_from_error(
+            &AccountKey::global("acc_m1"),
+            429,
+            None,
+            quota,
+            Some("gemini-pro".into()),
+            &[60],
+        );
+        tracker.parse_from_error(&AccountKey::global("acc_m1"), 503, None, "", None, &[]);
+        tracker.parse_from_error(
+            &AccountKey::global("acc_m2"),
+            429,
+            None,
+            quota,
+            None,
+            &[3600],
+        );
 
-        if count > 0 {
-            tracing::debug!("清除了 {} 个过期的限流记录", count);
-        }
+        let text = tracker.metrics_text();
+        assert!(text.contains("# TYPE antigravity_rate_limit_locked_accounts gauge"));
+        assert!(text.contains("antigravity_rate_limit_locked_accounts 2\n"));
+        assert!(text.contains("# TYPE antigravity_rate_limit_lockouts_total counter"));
+        assert!(
+            text.contains("antigravity_rate_limit_lockouts_total{reason=\"quota_exhausted\"} 2\n")
+        );
+        assert!(text.contains("antigravity_rate_limit_lockouts_total{reason=\"server_error\"} 1\n"));
+        assert!(text.contains("# TYPE antigravity_rate_limit_lock_duration_seconds histogram"));
+        assert!(text.contains("antigravity_rate_limit_lock_duration_seconds_bucket{le=\"60\"} 2\n"));
+        assert!(
+            text.contains("antigravity_rate_limit_lock_duration_seconds_bucket{le=\"+Inf\"} 3\n")
+        );
+        assert!(text.contains("antigravity_rate_limit_lock_duration_seconds_sum 3668\n"));
+        assert!(text.contains("antigravity_rate_limit_lock_duration_seconds_count 3\n"));
+        assert!(!text.contains("acc_m1"));
 
-        count
+        // 计数器不随 StatsD 导出清零
+        tracker.statsd_metrics("app");
+        assert!(tracker
+            .metrics_text()
+            .contains("antigravity_rate_limit_lockouts_total{reason=\"quota_exhausted\"} 2\n"));
     }
 
-    /// 清除指定账号的限流记录
-    pub fn clear(&self, account_id: &str) -> bool {
-        self.limits.remove(account_id).is_some()
-    }
+    #[test]
+    fn test_extend_lockout() {
+        let tracker = RateLimitTracker::new();
+        let extra = Duration::from_secs(600);
+        assert!(!tracker.extend_lockout(&AccountKey::global("acc_extend"), None, extra));
 
-    /// 清除所有限流记录 (乐观重置策略)
-    ///
-    /// 用于乐观重置机制,当所有账号都被限流但等待时间很短时,
-    /// 清除所有限流记录以解决时序竞争条件
-    pub fn clear_all(&self) {
-        let count = self.limits.len();
-        self.limits.clear();
-        tracing::warn!(
-            "🔄 Optimistic reset: Cleared all {} rate limit record(s)",
-            count
+        tracker.parse_from_error(
+            &AccountKey::global("acc_extend"),
+            429,
+            Some("60"),
+            "",
+            None,
+            &[],
         );
-    }
-}
+        let before = tracker.get(&AccountKey::global("acc_extend")).unwrap();
+        assert!(tracker.extend_lockout(&AccountKey::global("acc_extend"), None, extra));
+        let after = tracker.get(&AccountKey::global("acc_extend")).unwrap();
+        assert_eq!(after.reset_time, before.reset_time + extra);
+        assert_eq!(after.retry_after_sec, 660);
+        assert_eq!(after.detected_at, before.detected_at);
 
-impl Default for RateLimitTracker {
-    fn default() -> Self {
-        Self::new()
+        // 模型级锁按模型 Key 延长
+        let until = SystemTime::now() + Duration::from_secs(120);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_extend"),
+            until,
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-pro".to_string()),
+        );
+        assert!(!tracker.extend_lockout(&AccountKey::global("acc_extend"), Some("claude"), extra));
+        assert!(tracker.extend_lockout(
+            &AccountKey::global("acc_extend"),
+            Some("gemini-pro"),
+            extra
+        ));
+        assert_eq!(
+            tracker
+                .get(&AccountKey::global("acc_extend:gemini-pro"))
+                .unwrap()
+                .reset_time,
+            until + extra
+        );
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn test_parse_retry_time_minutes_seconds() {
+    fn test_model_alias_shares_lock_key() {
         let tracker = RateLimitTracker::new();
-        let body = "Rate limit exceeded. Try again in 2m 30s";
-        let time = tracker.parse_retry_time_from_body(body);
-        assert_eq!(time, Some(150));
+        tracker.set_model_alias("gemini-2.0-flash-001", "gemini-2.0-flash");
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+
+        tracker.parse_from_error(
+            &AccountKey::global("acc_alias"),
+            429,
+            None,
+            quota,
+            Some("gemini-2.0-flash-001".to_string()),
+            &[60],
+        );
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_alias"), Some("gemini-2.0-flash")));
+        assert!(tracker.is_model_locked(&AccountKey::global("acc_alias"), "gemini-2.0-flash"));
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_alias"), Some("gemini-1.5-pro")));
+
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_alias2"),
+            SystemTime::now() + Duration::from_secs(300),
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-2.0-flash".to_string()),
+        );
+        assert!(tracker.is_rate_limited(
+            &AccountKey::global("acc_alias2"),
+            Some("gemini-2.0-flash-001")
+        ));
+        assert_eq!(
+            tracker
+                .soonest_reset(Some("gemini-2.0-flash-001"))
+                .map(|(key, _)| key),
+            Some("acc_alias:gemini-2.0-flash".to_string())
+        );
+
+        // 移除别名后两者重新独立
+        tracker.set_model_alias("gemini-2.0-flash-001", "gemini-2.0-flash-001");
+        assert!(!tracker.is_rate_limited(
+            &AccountKey::global("acc_alias2"),
+            Some("gemini-2.0-flash-001")
+        ));
     }
 
-    #[test]
-    fn test_parse_google_json_delay() {
+    #[tokio::test]
+    async fn test_subscribe_receives_lock_and_unlock() {
         let tracker = RateLimitTracker::new();
-        let body = r#"{
-            "error": {
-                "details": [
-                    { 
-                        "metadata": {
-                            "quotaResetDelay": "42s" 
-                        }
-                    }
-                ]
-            }
-        }"#;
-        let time = tracker.parse_retry_time_from_body(body);
-        assert_eq!(time, Some(42));
+        // 无订阅者时加锁不受影响
+        tracker.parse_from_error(
+            &AccountKey::global("acc_sub0"),
+            429,
+            Some("30"),
+            "",
+            None,
+            &[],
+        );
+
+        let mut rx = tracker.subscribe();
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        tracker.parse_from_error(
+            &AccountKey::global("acc_sub"),
+            429,
+            None,
+            quota,
+            Some("gemini-pro".to_string()),
+            &[60],
+        );
+
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update.account_id, "acc_sub");
+        assert_eq!(update.model.as_deref(), Some("gemini-pro"));
+        assert_eq!(update.kind, RateLimitUpdateKind::Locked);
+        assert_eq!(
+            update.reset_time,
+            tracker
+                .get(&AccountKey::global("acc_sub:gemini-pro"))
+                .unwrap()
+                .reset_time
+        );
+
+        tracker.mark_partial_success(&AccountKey::global("acc_sub"), "gemini-pro");
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update.account_id, "acc_sub");
+        assert_eq!(update.kind, RateLimitUpdateKind::Unlocked);
+
+        tracker.clear_all();
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update.account_id, "acc_sub0");
+        assert_eq!(update.model, None);
+        assert_eq!(update.kind, RateLimitUpdateKind::Unlocked);
+        assert!(rx.try_recv().is_err());
     }
 
     #[test]
-    fn test_parse_retry_after_ignore_case() {
+    fn test_model_failure_rate() {
         let tracker = RateLimitTracker::new();
-        let body = "Quota limit hit. Retry After 99 Seconds";
-        let time = tracker.parse_retry_time_from_body(body);
-        assert_eq!(time, Some(99));
+        assert_eq!(
+            tracker.model_failure_rate(&AccountKey::global("acc_mf"), "gemini-pro"),
+            0.0
+        );
+
+        tracker.record_model_request(&AccountKey::global("acc_mf"), "gemini-pro", true);
+        tracker.record_model_request(&AccountKey::global("acc_mf"), "gemini-pro", false);
+        tracker.record_model_request(&AccountKey::global("acc_mf"), "claude", false);
+        assert_eq!(
+            tracker.model_failure_rate(&AccountKey::global("acc_mf"), "gemini-pro"),
+            0.5
+        );
+        assert_eq!(
+            tracker.model_failure_rate(&AccountKey::global("acc_mf"), "claude"),
+            1.0
+        );
+        // 模型级计数不影响账号级失败率
+        assert_eq!(tracker.failure_rate(&AccountKey::global("acc_mf")), 0.0);
+
+        tracker.mark_partial_success(&AccountKey::global("acc_mf"), "gemini-pro");
+        assert_eq!(
+            tracker.model_failure_rate(&AccountKey::global("acc_mf"), "gemini-pro"),
+            0.0
+        );
+        assert_eq!(
+            tracker.model_failure_rate(&AccountKey::global("acc_mf"), "claude"),
+            1.0
+        );
+
+        tracker.record_model_request(&AccountKey::global("acc_mf2"), "claude", false);
+        tracker.mark_success(&AccountKey::global("acc_mf"));
+        assert_eq!(
+            tracker.model_failure_rate(&AccountKey::global("acc_mf"), "claude"),
+            0.0
+        );
+        assert_eq!(
+            tracker.model_failure_rate(&AccountKey::global("acc_mf2"), "claude"),
+            1.0
+        );
     }
 
     #[test]
-    fn test_get_remaining_wait() {
+    fn test_regional_account_keys_are_independent() {
         let tracker = RateLimitTracker::new();
-        tracker.parse_from_error("acc1", 429, Some("30"), "", None, &[]);
-        let wait = tracker.get_remaining_wait("acc1", None);
-        assert!(wait > 25 && wait <= 30);
+        let us = AccountKey::regional("us-central1", "acc_r");
+        let eu = AccountKey::regional("europe-west1", "acc_r");
+        let global = AccountKey::global("acc_r");
+        assert_eq!(global.tracker_id(), "acc_r");
+        assert_eq!(us.to_string(), "us-central1/acc_r");
+        assert_eq!(AccountKey::parse(&us.tracker_id()), us);
+        assert_eq!(AccountKey::parse("acc_r"), global);
+
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        tracker.parse_from_error(&us, 429, None, quota, Some("gemini-pro".to_string()), &[60]);
+        assert_eq!(
+            tracker.get_limit_key(&us, Some("gemini-pro")),
+            "us-central1/acc_r:gemini-pro"
+        );
+        assert!(tracker.is_rate_limited(&us, Some("gemini-pro")));
+        assert!(!tracker.is_rate_limited(&eu, Some("gemini-pro")));
+        assert!(!tracker.is_rate_limited(&global, Some("gemini-pro")));
+
+        let candidates = vec![us.clone(), eu.clone()];
+        assert_eq!(
+            tracker.pick_available(&candidates, Some("gemini-pro")),
+            Some(&eu)
+        );
+        assert_eq!(
+            tracker.get_accounts_by_reason(RateLimitReason::QuotaExhausted),
+            vec!["us-central1/acc_r".to_string()]
+        );
     }
 
     #[test]
-    fn test_safety_buffer() {
-        let tracker = RateLimitTracker::new();
-        // 如果 API 返回 1s，我们强制设为 2s
-        tracker.parse_from_error("acc1", 429, Some("1"), "", None, &[]);
-        let wait = tracker.get_remaining_wait("acc1", None);
-        // Due to time passing, it might be 1 or 2
-        assert!(wait >= 1 && wait <= 2);
+    fn test_frozen_time_is_deterministic() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let tracker = RateLimitTracker::with_frozen_time(start);
+
+        tracker.parse_from_error(
+            &AccountKey::global("acc_clock"),
+            429,
+            Some("90"),
+            "",
+            None,
+            &[],
+        );
+        let info = tracker.get(&AccountKey::global("acc_clock")).unwrap();
+        assert_eq!(info.detected_at, start);
+        assert_eq!(info.reset_time, start + Duration::from_secs(90));
+        assert_eq!(
+            tracker.get_reset_seconds(&AccountKey::global("acc_clock")),
+            Some(90)
+        );
+        assert_eq!(tracker.throttle_rate(Duration::from_secs(60)), 1.0);
+
+        tracker.clock().advance(Duration::from_secs(89));
+        assert_eq!(
+            tracker.get_reset_seconds(&AccountKey::global("acc_clock")),
+            Some(1)
+        );
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_clock"), None));
+
+        tracker.clock().advance(Duration::from_secs(1));
+        assert!(!tracker.is_rate_limited(&AccountKey::global("acc_clock"), None));
+        assert_eq!(tracker.cleanup_expired().limits_removed, 1);
+        assert_eq!(tracker.throttle_rate(Duration::from_secs(60)), 0.0);
     }
 
     #[test]
-    fn test_tpm_exhausted_is_rate_limit_exceeded() {
+    fn test_snapshot_restore_into_fresh_tracker() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let old = RateLimitTracker::with_frozen_time(start);
+        old.parse_from_error(
+            &AccountKey::global("acc_snap"),
+            429,
+            Some("120"),
+            "",
+            None,
+            &[],
+        );
+        old.set_lockout_until(
+            &AccountKey::global("acc_snap2"),
+            start + Duration::from_secs(300),
+            RateLimitReason::QuotaExhausted,
+            Some("gemini-pro".to_string()),
+        );
+        old.parse_from_error(
+            &AccountKey::global("acc_snap3"),
+            429,
+            None,
+            r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#,
+            None,
+            &[60, 300],
+        );
+
+        let new =
+            RateLimitTracker::with_clock(BackoffConfig::from(vec![30, 90]), MockClock::new(start));
+        new.set_lockout_until(
+            &AccountKey::global("stale"),
+            start + Duration::from_secs(60),
+            RateLimitReason::Unknown,
+            None,
+        );
+        new.restore(old.snapshot());
+
+        for (account, model) in [
+            ("acc_snap", None),
+            ("acc_snap2", Some("gemini-pro")),
+            ("acc_snap3", None),
+        ] {
+            assert!(new.get_remaining_wait(&AccountKey::global(account), model) > 0);
+            assert_eq!(
+                new.get_remaining_wait(&AccountKey::global(account), model),
+                old.get_remaining_wait(&AccountKey::global(account), model)
+            );
+        }
+        assert!(!new.is_rate_limited(&AccountKey::global("stale"), None));
+        assert_eq!(
+            new.failure_count(&AccountKey::global("acc_snap3")),
+            old.failure_count(&AccountKey::global("acc_snap3"))
+        );
+    }
+
+    #[test]
+    fn test_remaining_wait_millis_sub_second_precision() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let tracker = RateLimitTracker::with_frozen_time(start);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_ms"),
+            start + Duration::from_millis(2_750),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+
+        tracker.clock().advance(Duration::from_millis(400));
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc_ms"), None),
+            2
+        );
+        let millis = tracker.get_remaining_wait_millis(&AccountKey::global("acc_ms"), None);
+        assert!((2_000..3_000).contains(&millis), "got {}ms", millis);
+        assert_eq!(millis, 2_350);
+
+        tracker.clock().advance(Duration::from_millis(2_000));
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc_ms"), None),
+            0
+        );
+        assert_eq!(
+            tracker.get_remaining_wait_millis(&AccountKey::global("acc_ms"), None),
+            350
+        );
+
+        // 真实时钟下的短锁: 毫秒值落在 (0, 1000] 区间内
         let tracker = RateLimitTracker::new();
-        // 模拟真实世界的 TPM 错误，同时包含 "Resource exhausted" 和 "per minute"
-        let body = "Resource has been exhausted (e.g. check quota). Quota limit 'Tokens per minute' exceeded.";
-        let reason = tracker.parse_rate_limit_reason(body);
-        // 应该被识别为 RateLimitExceeded，而不是 QuotaExhausted
-        assert_eq!(reason, RateLimitReason::RateLimitExceeded);
+        tracker.set_lockout_until(
+            &AccountKey::global("acc_ms"),
+            SystemTime::now() + Duration::from_millis(900),
+            RateLimitReason::RateLimitExceeded,
+            None,
+        );
+        let millis = tracker.get_remaining_wait_millis(&AccountKey::global("acc_ms"), None);
+        assert!(millis > 0 && millis <= 900, "got {}ms", millis);
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc_ms"), None),
+            0
+        );
     }
 
     #[test]
-    fn test_server_error_does_not_accumulate_failure_count() {
+    fn test_anthropic_reset_headers_lock_until_timestamp() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let tracker = RateLimitTracker::with_frozen_time(start);
+        let rfc3339 = |secs: u64| {
+            chrono::DateTime::<chrono::Utc>::from(start + Duration::from_secs(secs)).to_rfc3339()
+        };
+        // body 给出的 quotaResetDelay 应被忽略
+        let body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED","metadata":{"quotaResetDelay":"999s"}}]}}"#;
+        let accounts = ["acc_anth_req", "acc_anth_tok", "acc_anth_past"].map(AccountKey::global);
+        let ctx = |account, headers| ErrorContext {
+            account,
+            status: 429,
+            headers,
+            body,
+            model: None,
+            backoff_steps: &[60],
+        };
+
+        // Requests 维度
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-reset-requests",
+            rfc3339(40).parse().unwrap(),
+        );
+        let info = tracker.record_error(ctx(&accounts[0], &headers)).unwrap();
+        assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+        assert_eq!(info.reset_time, start + Duration::from_secs(40));
+        assert_eq!(
+            tracker.get_remaining_wait(&AccountKey::global("acc_anth_req"), None),
+            40
+        );
+
+        // Tokens 维度 (官方 `-tokens-reset` 写法)，仅已耗尽的维度参与计算
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-reset-tokens",
+            rfc3339(75).parse().unwrap(),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-remaining",
+            "12".parse().unwrap(),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-reset",
+            rfc3339(500).parse().unwrap(),
+        );
+        headers.insert("anthropic-ratelimit-tokens-remaining", "0".parse().unwrap());
+        headers.insert(
+            "anthropic-ratelimit-tokens-reset",
+            rfc3339(90).parse().unwrap(),
+        );
+        let info = tracker.record_error(ctx(&accounts[1], &headers)).unwrap();
+        assert_eq!(info.reset_time, start + Duration::from_secs(90));
+
+        // 已过去的时间戳忽略，回退到 body 解析
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-reset-requests",
+            chrono::DateTime::<chrono::Utc>::from(start - Duration::from_secs(5))
+                .to_rfc3339()
+                .parse()
+                .unwrap(),
+        );
+        let info = tracker.record_error(ctx(&accounts[2], &headers)).unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(info.retry_after_sec, 999);
+    }
+
+    #[tokio::test]
+    async fn test_parse_from_error_response_reads_anthropic_headers() {
         let tracker = RateLimitTracker::new();
-        let backoff_steps = vec![60, 300, 1800, 7200];
+        let reset = chrono::Utc::now() + chrono::Duration::seconds(120);
+        let http_response = axum::http::Response::builder()
+            .status(429)
+            .header("anthropic-ratelimit-tokens-reset", reset.to_rfc3339())
+            .header("anthropic-ratelimit-tokens-remaining", "0")
+            .body(r#"{"type":"error","error":{"type":"rate_limit_error"}}"#)
+            .unwrap();
 
-        // 模拟连续 5 次 5xx 错误
-        for i in 1..=5 {
-            let info = tracker.parse_from_error(
-                "acc1",
-                503,
-                None,
-                "Service Unavailable",
+        let info = tracker
+            .parse_from_error_response(
+                &AccountKey::global("acc_anth_resp"),
+                http_response.into(),
                 None,
-                &backoff_steps,
-            );
-            assert!(info.is_some(), "第 {} 次 5xx 应该返回 RateLimitInfo", i);
-            let info = info.unwrap();
-            // 5xx 应该始终锁定 8 秒，不受 failure_count 影响
-            assert_eq!(info.retry_after_sec, 8, "5xx 第 {} 次应该锁定 8 秒", i);
-        }
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::RateLimitExceeded);
+        assert!((118..=120).contains(&info.retry_after_sec));
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_anth_resp"), None));
+    }
 
-        // 现在触发一次 429 QuotaExhausted（没有 quotaResetDelay）
-        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
-        let info = tracker.parse_from_error("acc1", 429, None, quota_body, None, &backoff_steps);
-        assert!(info.is_some());
-        let info = info.unwrap();
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_export_prometheus_metrics_per_account_gauges() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let tracker = RateLimitTracker::with_frozen_time(start);
+        let quota = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        tracker.parse_from_error(
+            &AccountKey::global("acc_p1"),
+            429,
+            None,
+            quota,
+            Some("gemini-pro".into()),
+            &[60],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc_p2"),
+            429,
+            Some("30"),
+            "",
+            None,
+            &[],
+        );
+        tracker.parse_from_error(
+            &AccountKey::global("acc\"q"),
+            429,
+            Some("10"),
+            "",
+            None,
+            &[],
+        );
 
-        // 关键断言：429 应该从第 1 次开始（锁 60 秒），而不是继承 5xx 的计数
-        assert_eq!(
-            info.retry_after_sec, 60,
-            "429 应该从第 1 次退避开始(60秒),而不是被 5xx 污染"
+        let mut buf = Vec::new();
+        tracker.export_prometheus_metrics(&mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        assert!(text.starts_with(&tracker.metrics_text()));
+        assert!(text.contains("# TYPE rate_limit_locked_accounts_total gauge"));
+        assert!(text.contains("rate_limit_locked_accounts_total 3\n"));
+        assert!(text.contains("# TYPE rate_limit_failure_count gauge"));
+        assert!(text.contains("\nrate_limit_failure_count{account_id=\"acc_p1\"} 1\n"));
+        assert!(text.contains("# TYPE rate_limit_remaining_seconds gauge"));
+        assert!(text.contains(
+            "\nrate_limit_remaining_seconds{account_id=\"acc_p1\",model=\"gemini-pro\"} 60\n"
+        ));
+        assert!(
+            text.contains("\nrate_limit_remaining_seconds{account_id=\"acc_p2\",model=\"\"} 30\n")
         );
+        assert!(text.contains("account_id=\"acc\\\"q\""));
+
+        // 已过期的锁不再导出
+        tracker.clock().advance(Duration::from_secs(45));
+        let mut buf = Vec::new();
+        tracker.export_prometheus_metrics(&mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(!text.contains("account_id=\"acc_p2\",model"));
+        assert!(text.contains("model=\"gemini-pro\"} 15\n"));
+        assert!(text.contains("rate_limit_locked_accounts_total 1\n"));
     }
 
     #[test]
-    fn test_quota_exhausted_does_accumulate_failure_count() {
+    fn test_classification_hook_handles_custom_provider() {
         let tracker = RateLimitTracker::new();
-        let backoff_steps = vec![60, 300, 1800, 7200];
-        let quota_body = r#"{"error":{"details":[{"reason":"QUOTA_EXHAUSTED"}]}}"#;
+        // 内置逻辑不处理 418
+        let teapot = r#"{"brew_error":"TEAPOT_QUOTA","cooldown":"90"}"#;
+        assert!(tracker
+            .parse_from_error(
+                &AccountKey::global("acc_hook"),
+                418,
+                None,
+                teapot,
+                None,
+                &[]
+            )
+            .is_none());
 
-        // 第 1 次 429 → 60 秒
-        let info = tracker.parse_from_error("acc2", 429, None, quota_body, None, &backoff_steps);
-        assert_eq!(info.unwrap().retry_after_sec, 60);
+        tracker.add_classification_hook(|status, body| {
+            if status != 418 || !body.contains("TEAPOT_QUOTA") {
+                return None;
+            }
+            let secs = serde_json::from_str::<serde_json::Value>(body)
+                .ok()
+                .and_then(|v| v["cooldown"].as_str()?.parse::<u64>().ok());
+            Some((RateLimitReason::QuotaExhausted, secs))
+        });
 
-        // 第 2 次 429 → 300 秒
-        let info = tracker.parse_from_error("acc2", 429, None, quota_body, None, &backoff_steps);
-        assert_eq!(info.unwrap().retry_after_sec, 300);
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_hook"),
+                418,
+                Some("5"),
+                teapot,
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(
+            info.retry_after_sec, 90,
+            "hook seconds win over Retry-After"
+        );
+        assert!(tracker.is_rate_limited(&AccountKey::global("acc_hook"), None));
+        assert_eq!(
+            tracker.classify(418, None, teapot),
+            (RateLimitReason::QuotaExhausted, Some(90))
+        );
 
-        // 第 3 次 429 → 1800 秒
-        let info = tracker.parse_from_error("acc2", 429, None, quota_body, None, &backoff_steps);
-        assert_eq!(info.unwrap().retry_after_sec, 1800);
+        // 钩子未给出秒数时按该原因走默认退避
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_hook2"),
+                418,
+                None,
+                r#"{"brew_error":"TEAPOT_QUOTA"}"#,
+                None,
+                &[120],
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::QuotaExhausted);
+        assert_eq!(info.retry_after_sec, 120);
 
-        // 第 4 次 429 → 7200 秒
-        let info = tracker.parse_from_error("acc2", 429, None, quota_body, None, &backoff_steps);
-        assert_eq!(info.unwrap().retry_after_sec, 7200);
+        // 钩子返回 None 时回落到内置逻辑
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_hook3"),
+                429,
+                Some("30"),
+                "",
+                None,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.reason, RateLimitReason::Unknown);
+        assert_eq!(info.retry_after_sec, 30);
+        assert!(tracker
+            .parse_from_error(&AccountKey::global("acc_hook4"), 418, None, "{}", None, &[])
+            .is_none());
     }
 }
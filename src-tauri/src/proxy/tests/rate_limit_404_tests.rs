@@ -2,15 +2,24 @@
 //! - 短时锁定（5s）
 //! - 不累加失败计数
 //! - 与 5xx 锁定时长的差异
+//! - 模型不存在 (ModelNotFound) 只锁模型级 Key，其他 404 锁账号
+//! - `parse_404_reason` 对各类 Gemini 404 文案的分类
 
-use crate::proxy::rate_limit::{RateLimitReason, RateLimitTracker};
+use crate::proxy::rate_limit::{parse_404_reason, AccountKey, RateLimitReason, RateLimitTracker};
 
 #[test]
 fn test_parse_from_error_404_short_lockout() {
     let tracker = RateLimitTracker::new();
     let backoff_steps = vec![60, 300, 1800, 7200];
 
-    let info = tracker.parse_from_error("acc_404", 404, None, "Not Found", None, &backoff_steps);
+    let info = tracker.parse_from_error(
+        &AccountKey::global("acc_404"),
+        404,
+        None,
+        "Not Found",
+        None,
+        &backoff_steps,
+    );
     assert!(info.is_some(), "404 should return Some(RateLimitInfo)");
     let info = info.unwrap();
     assert_eq!(info.retry_after_sec, 5, "404 should lock out for 5 seconds");
@@ -29,9 +38,9 @@ fn test_404_does_not_accumulate_failure_count() {
     // 连续多次 404，锁定时间应始终为 5s（不像 429 QuotaExhausted 那样递增）
     for i in 1..=5 {
         // 清除上一次的限流记录，模拟轮换后再次遇到 404
-        tracker.clear("acc_404_repeat");
+        tracker.clear(&AccountKey::global("acc_404_repeat"));
         let info = tracker.parse_from_error(
-            "acc_404_repeat",
+            &AccountKey::global("acc_404_repeat"),
             404,
             None,
             "Not Found",
@@ -54,8 +63,14 @@ fn test_404_vs_5xx_lockout_duration() {
     let backoff_steps = vec![60, 300, 1800, 7200];
 
     // 404 → 5s lockout
-    let info_404 =
-        tracker.parse_from_error("acc_cmp_404", 404, None, "Not Found", None, &backoff_steps);
+    let info_404 = tracker.parse_from_error(
+        &AccountKey::global("acc_cmp_404"),
+        404,
+        None,
+        "Not Found",
+        None,
+        &backoff_steps,
+    );
     assert_eq!(
         info_404.unwrap().retry_after_sec,
         5,
@@ -64,7 +79,7 @@ fn test_404_vs_5xx_lockout_duration() {
 
     // 503 → 8s lockout
     let info_503 = tracker.parse_from_error(
-        "acc_cmp_503",
+        &AccountKey::global("acc_cmp_503"),
         503,
         None,
         "Service Unavailable",
@@ -77,3 +92,130 @@ fn test_404_vs_5xx_lockout_duration() {
         "503 should lock for 8s"
     );
 }
+
+#[test]
+fn test_404_model_not_found_locks_model_only() {
+    let tracker = RateLimitTracker::new();
+    let body = r#"{"error":{"code":404,"message":"models/gemini-9-ultra is not found for API version v1beta","status":"NOT_FOUND"}}"#;
+
+    let info = tracker
+        .parse_from_error(
+            &AccountKey::global("acc_404_model"),
+            404,
+            None,
+            body,
+            Some("gemini-9-ultra".to_string()),
+            &[],
+        )
+        .unwrap();
+    assert_eq!(info.reason, RateLimitReason::ModelNotFound);
+    assert_eq!(
+        info.retry_after_sec, 300,
+        "bad model gets the longer model lock"
+    );
+
+    assert!(tracker.is_rate_limited(&AccountKey::global("acc_404_model"), Some("gemini-9-ultra")));
+    assert!(
+        !tracker.is_rate_limited(
+            &AccountKey::global("acc_404_model"),
+            Some("gemini-2.0-flash")
+        ),
+        "other models on the same account stay available"
+    );
+    assert!(!tracker.is_rate_limited(&AccountKey::global("acc_404_model"), None));
+}
+
+#[test]
+fn test_404_without_model_mention_locks_account() {
+    let tracker = RateLimitTracker::new();
+
+    let info = tracker
+        .parse_from_error(
+            &AccountKey::global("acc_404_generic"),
+            404,
+            None,
+            "Requested entity was not found.",
+            Some("gemini-2.0-flash".to_string()),
+            &[],
+        )
+        .unwrap();
+    assert_eq!(info.reason, RateLimitReason::ServerError);
+    assert!(tracker.is_rate_limited(&AccountKey::global("acc_404_generic"), None));
+    assert!(tracker.is_rate_limited(
+        &AccountKey::global("acc_404_generic"),
+        Some("claude-sonnet")
+    ));
+}
+
+#[test]
+fn test_parse_404_reason_known_phrasings() {
+    let cases: &[(&str, Option<&str>, RateLimitReason, u64)] = &[
+        (
+            r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND"}}"#,
+            Some("gemini-2.0-flash"),
+            RateLimitReason::ServerError,
+            5,
+        ),
+        (
+            "models/gemini-1.0-ultra is not found for API version v1beta, or is not supported for generateContent. Call ListModels to see the list of available models and their supported methods.",
+            None,
+            RateLimitReason::ModelNotFound,
+            300,
+        ),
+        (
+            "Publisher Model `projects/p-123/locations/us-central1/publishers/google/models/gemini-x` was not found or your project does not have access to it.",
+            None,
+            RateLimitReason::ModelNotFound,
+            300,
+        ),
+        (
+            "gemini-embedding is not supported for streamGenerateContent",
+            None,
+            RateLimitReason::ModelNotFound,
+            300,
+        ),
+        (
+            "Unknown model: claude-opus-9",
+            None,
+            RateLimitReason::ModelNotFound,
+            300,
+        ),
+        (
+            "The model gemini-x does not exist.",
+            None,
+            RateLimitReason::ModelNotFound,
+            300,
+        ),
+        (
+            "Resource gemini-exp-1206 missing",
+            Some("gemini-exp-1206"),
+            RateLimitReason::ModelNotFound,
+            300,
+        ),
+        ("Not Found", Some("gemini-2.0-flash"), RateLimitReason::ServerError, 5),
+    ];
+
+    for (i, (body, model, reason, lockout)) in cases.iter().enumerate() {
+        assert_eq!(
+            parse_404_reason(body, *model),
+            *reason,
+            "case {}: {}",
+            i,
+            body
+        );
+
+        let tracker = RateLimitTracker::new();
+        let info = tracker
+            .parse_from_error(
+                &AccountKey::global("acc_404_phrase"),
+                404,
+                None,
+                body,
+                model.map(str::to_string),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(info.reason, *reason, "case {}", i);
+        assert_eq!(info.retry_after_sec, *lockout, "case {}", i);
+    }
+}
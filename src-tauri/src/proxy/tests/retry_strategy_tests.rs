@@ -2,7 +2,8 @@
 //! 重点覆盖 404 重试与账号轮换逻辑。
 
 use crate::proxy::handlers::common::{
-    determine_retry_strategy, should_rotate_account, RetryStrategy,
+    apply_retry_strategy, determine_retry_strategy, rotation_budget_exhausted,
+    should_rotate_account, AccountRotationBudget, RetryStrategy, RotationError,
 };
 use std::time::Duration;
 
@@ -146,3 +147,61 @@ fn test_rotate_account_false_cases() {
         );
     }
 }
+
+// ===== 账号轮换预算 (max_accounts_tried) =====
+
+/// 按 handler 重试循环的结构驱动：取号 -> 预算检查 -> 上游失败 -> 退避 -> 决定是否轮换
+async fn run_retry_loop(
+    pool: &[&str],
+    statuses: &[(u16, &str)],
+    max_accounts_tried: usize,
+) -> (Vec<String>, String) {
+    let max_attempts = statuses.len();
+    let mut rotation_budget = AccountRotationBudget::new(max_accounts_tried);
+    let mut last_error = String::new();
+    let mut force_rotate = false;
+    let mut current = 0;
+    let mut used = Vec::new();
+
+    for (attempt, &(status_code, error_text)) in statuses.iter().enumerate() {
+        if force_rotate {
+            current = (current + 1) % pool.len();
+        }
+        let account_id = pool[current];
+        if rotation_budget_exhausted(&mut rotation_budget, account_id, &mut last_error, "test") {
+            break;
+        }
+        used.push(account_id.to_string());
+
+        last_error = format!("HTTP {}", status_code);
+        let strategy = determine_retry_strategy(status_code, error_text, false);
+        if apply_retry_strategy(strategy.clone(), attempt, max_attempts, status_code, "test").await
+        {
+            force_rotate = should_rotate_account(status_code, Some(&strategy));
+            continue;
+        }
+        break;
+    }
+    (used, last_error)
+}
+
+#[tokio::test]
+async fn test_retry_loop_stops_rotating_at_distinct_account_cap() {
+    let pool = ["acc_a", "acc_b", "acc_c", "acc_d"];
+    let statuses = [
+        (400, "Invalid `signature`"), // 签名错误在同一账号上重试，不消耗预算
+        (401, "unauthorized"),
+        (401, "unauthorized"),
+        (401, "unauthorized"),
+    ];
+
+    let (used, last_error) = run_retry_loop(&pool, &statuses, 2).await;
+    assert_eq!(used, vec!["acc_a", "acc_a", "acc_b"]);
+    assert!(last_error.starts_with("HTTP 401"));
+    assert!(last_error.contains(&RotationError::PoolExhausted { accounts_tried: 2 }.to_string()));
+
+    // 不限制时用满全部尝试次数
+    let (used, last_error) = run_retry_loop(&pool, &statuses, 0).await;
+    assert_eq!(used, vec!["acc_a", "acc_a", "acc_b", "acc_c"]);
+    assert_eq!(last_error, "HTTP 401");
+}
@@ -6,7 +6,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
-use crate::proxy::rate_limit::RateLimitTracker;
+use crate::proxy::rate_limit::{AccountKey, RateLimitTracker};
 use crate::proxy::sticky_config::StickySessionConfig;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -61,6 +61,7 @@ pub struct TokenManager {
 
     /// 支持优雅关闭时主动 abort 后台任务
     auto_cleanup_handle: Arc<tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    health_watch_handle: Arc<tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
     cancel_token: CancellationToken,
 }
 
@@ -83,35 +84,17 @@ impl TokenManager {
             refresh_locks: Arc::new(DashMap::new()),
             load_code_assist_inflight: Arc::new(DashMap::new()), // 初始化 inflight 表
             auto_cleanup_handle: Arc::new(tokio::sync::Mutex::new(None)),
+            health_watch_handle: Arc::new(tokio::sync::Mutex::new(None)),
             cancel_token: CancellationToken::new(),
         }
     }
 
     /// 启动限流记录自动清理后台任务（每15秒检查并清除过期记录）
     pub async fn start_auto_cleanup(&self) {
-        let tracker = self.rate_limit_tracker.clone();
-        let cancel = self.cancel_token.child_token();
-
-        let handle = tokio::spawn(async move {
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
-            loop {
-                tokio::select! {
-                    _ = cancel.cancelled() => {
-                        tracing::info!("Auto-cleanup task received cancel signal");
-                        break;
-                    }
-                    _ = interval.tick() => {
-                        let cleaned = tracker.cleanup_expired();
-                        if cleaned > 0 {
-                            tracing::info!(
-                                "Auto-cleanup: Removed {} expired rate limit record(s)",
-                                cleaned
-                            );
-                        }
-                    }
-                }
-            }
-        });
+        let handle = self
+            .rate_limit_tracker
+            .clone()
+            .start_cleanup_task(std::time::Duration::from_secs(15));
 
         // 先 abort 旧任务（防止任务泄漏），再存储新 handle
         let mut guard = self.auto_cleanup_handle.lock().await;
@@ -124,6 +107,34 @@ impl TokenManager {
         tracing::info!("Rate limit auto-cleanup task started (interval: 15s)");
     }
 
+    /// 启动限流健康等级监听: 每次加锁/解锁 (含自动清理解除的过期锁) 后重新评估等级，
+    /// 等级变化时调用 `on_change`
+    pub async fn start_health_watch<F>(&self, on_change: F)
+    where
+        F: Fn(crate::proxy::rate_limit::TrackerHealthReport) + Send + 'static,
+    {
+        let mut updates = self.rate_limit_tracker.subscribe();
+        let tracker = self.rate_limit_tracker.clone();
+        let tokens = self.tokens.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                match updates.recv().await {
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+                if let Some(report) = tracker.check_health_transition(&Self::account_keys(&tokens))
+                {
+                    on_change(report);
+                }
+            }
+        });
+
+        let mut guard = self.health_watch_handle.lock().await;
+        if let Some(old) = guard.replace(handle) {
+            old.abort();
+        }
+    }
+
     /// 从主应用账号目录加载所有账号
     pub async fn load_accounts(&self) -> Result<usize, String> {
         let accounts_dir = self.data_dir.join("accounts");
@@ -1130,6 +1141,7 @@ impl TokenManager {
     /// abort() 仅设置取消标志，必须 await 确认清理完成
     pub async fn abort_background_tasks(&self) {
         Self::abort_task(&self.auto_cleanup_handle, "Auto-cleanup task").await;
+        Self::abort_task(&self.health_watch_handle, "Health watch task").await;
     }
 
     /// 中止单个后台任务并记录结果
@@ -1569,7 +1581,9 @@ impl TokenManager {
                             .email_to_account_id(&bound_token.email)
                             .unwrap_or_else(|| bound_token.account_id.clone());
                         // [FIX] Pass None for specific model wait time if not applicable
-                        let reset_sec = self.rate_limit_tracker.get_remaining_wait(&key, None);
+                        let reset_sec = self
+                            .rate_limit_tracker
+                            .get_remaining_wait(&AccountKey::global(&key), None);
                         if reset_sec > 0 {
                             // 【修复 Issue #284】立即解绑并切换账号，不再阻塞等待
                             // 原因：阻塞等待会导致并发请求时客户端 socket 超时 (UND_ERR_SOCKET)
@@ -1716,19 +1730,28 @@ impl TokenManager {
                 Some(t) => t,
                 None => {
                     // 乐观重置策略: 双层防护机制
-                    // 计算最短等待时间
-                    let min_wait = tokens_snapshot
+                    // 计算最短等待时间 (毫秒精度，避免按整秒 sleep 后提前醒来)
+                    let min_wait_ms = tokens_snapshot
                         .iter()
-                        .filter_map(|t| self.rate_limit_tracker.get_reset_seconds(&t.account_id))
+                        .filter(|t| {
+                            self.rate_limit_tracker
+                                .get_reset_seconds(&AccountKey::global(&t.account_id))
+                                .is_some()
+                        })
+                        .map(|t| {
+                            self.rate_limit_tracker
+                                .get_remaining_wait_millis(&AccountKey::global(&t.account_id), None)
+                        })
                         .min();
 
                     // Layer 1: 如果最短等待时间 <= 2秒,执行缓冲延迟
-                    if let Some(wait_sec) = min_wait {
-                        if wait_sec <= 2 {
-                            let wait_ms = (wait_sec as f64 * 1000.0) as u64;
+                    if let Some(wait_ms) = min_wait_ms {
+                        let wait_sec = wait_ms.div_ceil(1000);
+                        if wait_ms <= 2000 {
+                            let wait_ms = wait_ms as u64;
                             tracing::warn!(
-                                "All accounts rate-limited but shortest wait is {}s. Applying {}ms buffer for state sync...",
-                                wait_sec, wait_ms
+                                "All accounts rate-limited but shortest wait is {}ms. Applying buffer for state sync...",
+                                wait_ms
                             );
 
                             // 缓冲延迟
@@ -2244,7 +2267,8 @@ impl TokenManager {
         if !config.enabled {
             return false;
         }
-        self.rate_limit_tracker.is_rate_limited(account_id, model)
+        self.rate_limit_tracker
+            .is_rate_limited(&AccountKey::global(account_id), model)
     }
 
     /// [NEW] 检查账号是否在限流中 (同步版本，仅用于 Iterator)
@@ -2254,13 +2278,30 @@ impl TokenManager {
         if !config.enabled {
             return false;
         }
-        self.rate_limit_tracker.is_rate_limited(account_id, model)
+        self.rate_limit_tracker
+            .is_rate_limited(&AccountKey::global(account_id), model)
     }
 
     /// 获取距离限流重置还有多少秒
     #[allow(dead_code)]
     pub fn get_rate_limit_reset_seconds(&self, account_id: &str) -> Option<u64> {
-        self.rate_limit_tracker.get_reset_seconds(account_id)
+        self.rate_limit_tracker
+            .get_reset_seconds(&AccountKey::global(account_id))
+    }
+
+    /// 获取最近 `window` 内触发限流的请求比例 (0.0 - 1.0)
+    pub fn get_throttle_rate(&self, window: std::time::Duration) -> f64 {
+        self.rate_limit_tracker.throttle_rate(window)
+    }
+
+    /// 统计当前账号池的限流健康状况 (锁定比例、永久停用、高失败账号)，不改变任何状态
+    pub fn rate_limit_health(&self) -> crate::proxy::rate_limit::TrackerHealthReport {
+        self.rate_limit_tracker
+            .health_check(&Self::account_keys(&self.tokens))
+    }
+
+    fn account_keys(tokens: &DashMap<String, ProxyToken>) -> Vec<AccountKey> {
+        tokens.iter().map(|e| AccountKey::global(e.key())).collect()
     }
 
     /// 清除过期的限流记录
@@ -2280,7 +2321,8 @@ impl TokenManager {
 
     /// 清除指定账号的限流记录
     pub fn clear_rate_limit(&self, account_id: &str) -> bool {
-        self.rate_limit_tracker.clear(account_id)
+        self.rate_limit_tracker
+            .clear(&AccountKey::global(account_id))
     }
 
     /// 清除所有限流记录
@@ -2288,12 +2330,24 @@ impl TokenManager {
         self.rate_limit_tracker.clear_all();
     }
 
+    /// 重置指定账号的退避阶梯 (不解除当前锁定)
+    pub fn reset_failure_count(&self, account_id: &str) -> bool {
+        self.rate_limit_tracker
+            .reset_failure_count(&AccountKey::global(account_id))
+    }
+
+    /// 重置所有账号的退避阶梯 (不解除当前锁定)，用于故障恢复后
+    pub fn reset_all_failure_counts(&self) {
+        self.rate_limit_tracker.reset_all_failure_counts();
+    }
+
     /// 标记账号请求成功，重置连续失败计数
     ///
     /// 在请求成功完成后调用，将该账号的失败计数归零，
     /// 下次失败时从最短的锁定时间开始（智能限流）。
     pub fn mark_account_success(&self, account_id: &str) {
-        self.rate_limit_tracker.mark_success(account_id);
+        self.rate_limit_tracker
+            .mark_success(&AccountKey::global(account_id));
     }
 
     /// 检查是否有可用的 Google 账号
@@ -2416,7 +2470,7 @@ impl TokenManager {
         if let Some(reset_time_str) = self.get_quota_reset_time(account_id) {
             tracing::info!("找到账号 {} 的配额刷新时间: {}", account_id, reset_time_str);
             self.rate_limit_tracker.set_lockout_until_iso(
-                account_id,
+                &AccountKey::global(account_id),
                 &reset_time_str,
                 reason,
                 model_to_lock,
@@ -2502,7 +2556,7 @@ impl TokenManager {
 
                     // [FIX] 使用 account_id 作为 key，与 is_rate_limited 检查一致
                     self.rate_limit_tracker.set_lockout_until_iso(
-                        &account_id,
+                        &AccountKey::global(&account_id),
                         reset_time_str,
                         reason,
                         model_to_lock,
@@ -2576,7 +2630,7 @@ impl TokenManager {
                 );
             }
             self.rate_limit_tracker.parse_from_error(
-                &account_id,
+                &AccountKey::global(&account_id),
                 status,
                 retry_after_header,
                 error_body,
@@ -2633,7 +2687,7 @@ impl TokenManager {
         // 都失败了,回退到指数退避策略
         tracing::warn!("账号 {} 无法获取配额刷新时间,使用指数退避策略", account_id);
         self.rate_limit_tracker.parse_from_error(
-            &account_id,
+            &AccountKey::global(&account_id),
             status,
             retry_after_header,
             error_body,
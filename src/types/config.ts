@@ -116,6 +116,7 @@ export interface ExperimentalConfig {
     context_compression_threshold_l1?: number;
     context_compression_threshold_l2?: number;
     context_compression_threshold_l3?: number;
+    max_accounts_tried?: number;
 }
 
 export interface CircuitBreakerConfig {